@@ -7,12 +7,16 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also compiles `keymap.toml` to Rust, failing the build with the line of any error in it.
 
 use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+use rmk_mekk_elek::codegen::Config;
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
@@ -28,4 +32,18 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    let source = std::fs::read_to_string("keymap.toml").unwrap();
+    let config = match Config::parse(&source) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: keymap.toml:{}: {}", e.line, e.message);
+            std::process::exit(1);
+        }
+    };
+    File::create(out.join("keymap.rs"))
+        .unwrap()
+        .write_all(config.to_rust().as_bytes())
+        .unwrap();
+    println!("cargo:rerun-if-changed=keymap.toml");
 }
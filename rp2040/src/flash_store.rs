@@ -0,0 +1,48 @@
+//! The settings store's flash, at the end of the RP2040's QSPI flash
+
+use rmk_mekk_elek::storage::Flash;
+
+/// Where the flash is memory-mapped
+const XIP_BASE: u32 = 0x1000_0000;
+const FLASH_SIZE: u32 = 2048 * 1024;
+const PAGE_SIZE: usize = 256;
+pub const SECTORS: u32 = 16;
+/// `memory.x` keeps the program out of these sectors
+const STORE_OFFSET: u32 = FLASH_SIZE - SECTORS * Rp2040Flash::SECTOR_SIZE;
+
+pub struct Rp2040Flash;
+
+impl Flash for Rp2040Flash {
+    const SECTOR_SIZE: u32 = 4096;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let start = (XIP_BASE + STORE_OFFSET + offset) as *const u8;
+        for (index, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile(start.add(index)) };
+        }
+    }
+
+    fn erase(&mut self, offset: u32) {
+        // Nothing may run from flash meanwhile, and only core 0 is running
+        cortex_m::interrupt::free(|_| unsafe {
+            rp2040_flash::flash::flash_range_erase(STORE_OFFSET + offset, Self::SECTOR_SIZE, true)
+        });
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        // Programs whole pages, padded with `0xFF`s which leave the flash as it is
+        let mut offset = STORE_OFFSET + offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let skip = offset as usize % PAGE_SIZE;
+            let len = (PAGE_SIZE - skip).min(data.len());
+            let mut page = [0xFF; PAGE_SIZE];
+            page[skip..skip + len].copy_from_slice(&data[..len]);
+            cortex_m::interrupt::free(|_| unsafe {
+                rp2040_flash::flash::flash_range_program(offset - skip as u32, &page, true)
+            });
+            offset += len as u32;
+            data = &data[len..];
+        }
+    }
+}
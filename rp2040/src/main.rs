@@ -4,42 +4,31 @@
 use defmt_rtt as _;
 use panic_probe as _;
 
-const ROWS: usize = 6;
-const COLS: usize = 6;
-const SIZE: usize = ROWS * COLS;
-const LAYERS: usize = 2;
+mod flash_store;
+mod raw_hid;
+mod system_control;
+
+/// Generated from `keymap.toml` by `build.rs`
+#[allow(dead_code)]
+mod layout {
+    include!(concat!(env!("OUT_DIR"), "/keymap.rs"));
+}
+use layout::{COLS, LAYERS, ROWS, SIZE};
 const ROLLOVER: usize = 32;
+/// Keyboard reports kept while the host is not reading them
+const REPORTS: usize = 16;
 use rmk_mekk_elek::keystate::Keymap;
 type KeymapT = Keymap<SIZE, LAYERS, ROLLOVER>;
+/// Space for `Keymap::persist`
+const STORE_LEN: usize = rmk_mekk_elek::keystate::max_persist_len(SIZE, LAYERS);
 
 use fugit::ExtU64;
 use rp2040_monotonic::Rp2040Monotonic;
 type Duration = <Rp2040Monotonic as rtic::Monotonic>::Duration;
 
 pub fn keymap() -> KeymapT {
-    use rmk_mekk_elek::keystate::prelude::*;
-    let mod_timeout: Duration = 200.millis();
-    let tap_release: Duration = 100.millis();
-    let tap_repeat: Duration = 500.millis();
-
-    #[rustfmt::skip]
-    let ret_statement = Keymap::new([[
-            Kb(Equal),   Kb(K0),      Kb(K1),      Kb(K2),      Kb(K3),      Kb(K4),
-            Kb(BSL),     Kb(Q),       Kb(W),       Kb(E),       Kb(R),       Kb(T),
-            Kb(Escape),  MT(LSFT, A), MT(LSFT, S), MT(LCTL, D), MT(LCTL, F), Kb(G),
-            Kb(LSFT),    MT(LWIN, Z), MT(LWIN, X), MT(LALT, C), MT(LALT, V), Kb(B),
-            Kb(LWIN),    Kb(LEFT),    Kb(DOWN),    Kb(UP),      Kb(RIGHT),   Kb(Space) /* (MT (L 1) SPACE)*/,
-            Kb(___),     Kb(___),     Kb(___),     Kb(___),     Kb(___),     Kb(___),
-        ], [
-            Kb(F1),     Kb(F2),     Kb(F3),     Kb(F4),     Kb(F5),     Kb(F6),
-            Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),
-            Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),
-            Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),
-            Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),
-            Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),    Kb(___),
-        ]],
-        mod_timeout.ticks(), tap_release.ticks(), tap_repeat.ticks());
-    ret_statement
+    let millisecond: Duration = 1.millis();
+    layout::keymap(millisecond.ticks())
 }
 
 #[rtic::app(device = rp_pico::hal::pac, peripherals = true, dispatchers = [XIP_IRQ])]
@@ -48,7 +37,12 @@ mod app {
     use rp_pico as bsp;
 
     use rmk_mekk_elek::debounce::SchmittDebouncer;
+    use rmk_mekk_elek::keystate::queue::ReportQueue;
+    use rmk_mekk_elek::keystate::report::Report;
+    use rmk_mekk_elek::keystate::CONSUMER_ROLLOVER;
     use rmk_mekk_elek::matrix::decode;
+    use rmk_mekk_elek::storage::Store;
+    use rmk_mekk_elek::via::{Via, ViaRequest, REPORT_LEN};
 
     use super::*;
 
@@ -65,9 +59,23 @@ mod app {
     use rp2040_monotonic::Rp2040Monotonic;
     use usb_device::class_prelude::*;
     use usb_device::prelude::*;
+    use usbd_human_interface_device::descriptor::HidProtocol;
+    use usbd_human_interface_device::device::consumer::{
+        ConsumerControl, ConsumerControlConfig, MultipleConsumerReport,
+    };
     use usbd_human_interface_device::device::keyboard::{NKROBootKeyboard, NKROBootKeyboardConfig};
+    use usbd_human_interface_device::device::mouse::{
+        WheelMouse, WheelMouseConfig, WheelMouseReport,
+    };
+    use usbd_human_interface_device::device::DeviceClass;
+    use usbd_human_interface_device::interface::InterfaceClass;
+    use usbd_human_interface_device::page::{Consumer, Desktop};
     use usbd_human_interface_device::prelude::*;
 
+    use crate::flash_store::{self, Rp2040Flash};
+    use crate::raw_hid::{RawHid, RawHidConfig};
+    use crate::system_control::{SystemControl, SystemControlConfig};
+
     #[monotonic(binds = TIMER_IRQ_0, default = true)]
     type AppMonotonic = Rp2040Monotonic;
     type Instant = <Rp2040Monotonic as rtic::Monotonic>::Instant;
@@ -77,9 +85,17 @@ mod app {
         keyboard: UsbHidClass<
             'static,
             hal::usb::UsbBus,
-            HList!(NKROBootKeyboard<'static, hal::usb::UsbBus>),
+            HList!(
+                RawHid<'static, hal::usb::UsbBus>,
+                SystemControl<'static, hal::usb::UsbBus>,
+                ConsumerControl<'static, hal::usb::UsbBus>,
+                WheelMouse<'static, hal::usb::UsbBus>,
+                NKROBootKeyboard<'static, hal::usb::UsbBus>,
+            ),
         >,
         usb_device: UsbDevice<'static, hal::usb::UsbBus>,
+        /// A VIA request read from the raw HID interface, for `write_keyboard` to answer
+        via_report: Option<[u8; REPORT_LEN]>,
     }
 
     #[local]
@@ -89,6 +105,21 @@ mod app {
         cols: Vec<Pin<DynPinId, FunctionSio<SioInput>, PullDown>, COLS>,
         keymap: KeymapT,
         debouncer: SchmittDebouncer<SIZE, 10>,
+        keyboard_reports: ReportQueue<Report<ROLLOVER>, REPORTS>,
+        /// Not yet sent as the endpoint was busy, retried until it is, as the keymap only reports
+        /// changes
+        mouse_report: Option<WheelMouseReport>,
+        /// Last sent, as the consumer and system control interfaces send even unchanged reports
+        consumer_report: MultipleConsumerReport,
+        system_report: Option<Desktop>,
+        store: Store<Rp2040Flash>,
+        /// `Keymap::fingerprint` of the compiled keymap, saved with it
+        origin: u32,
+        /// Saves a while after the last change, to spare the flash
+        save_at: Option<Instant>,
+        via: Via,
+        /// Not yet sent as raw HID was busy, retried until it is
+        via_response: Option<[u8; REPORT_LEN]>,
     }
 
     #[init(local = [usb_alloc: Option<UsbBusAllocator<hal::usb::UsbBus>> = None])]
@@ -147,6 +178,18 @@ mod app {
 
         let mono = Rp2040Monotonic::new(cx.device.TIMER);
 
+        // Falls back to the compiled-in keymap if the store is empty, corrupt or saved from
+        // another keymap.toml
+        let mut store = Store::new(Rp2040Flash, flash_store::SECTORS);
+        let mut buf = [0; STORE_LEN];
+        let mut keymap = keymap();
+        let origin = keymap.fingerprint(&mut buf).unwrap();
+        if let Some(data) = store.load(&mut buf) {
+            if let Err(e) = keymap.restore(origin, data) {
+                defmt::warn!("Stored keymap is not used: {}", defmt::Debug2Format(&e));
+            }
+        }
+
         // USB
         let usb_alloc = cx
             .local
@@ -161,6 +204,10 @@ mod app {
 
         let keyboard = UsbHidClassBuilder::new()
             .add_device(NKROBootKeyboardConfig::default())
+            .add_device(WheelMouseConfig::default())
+            .add_device(ConsumerControlConfig::default())
+            .add_device(SystemControlConfig::default())
+            .add_device(RawHidConfig::default())
             .build(usb_alloc);
 
         // https://pid.codes
@@ -183,13 +230,23 @@ mod app {
             Shared {
                 keyboard,
                 usb_device,
+                via_report: None,
             },
             Local {
                 led,
                 rows,
                 cols,
-                keymap: keymap(),
+                keymap,
                 debouncer: Default::default(),
+                keyboard_reports: Default::default(),
+                mouse_report: None,
+                consumer_report: Default::default(),
+                system_report: None,
+                store,
+                origin,
+                save_at: None,
+                via: Via::new(COLS as u8),
+                via_response: None,
             },
             init::Monotonics(mono),
         )
@@ -212,8 +269,22 @@ mod app {
     }
 
     #[task(
-        shared = [keyboard],
-        local = [rows, cols, keymap, debouncer],
+        shared = [keyboard, via_report],
+        local = [
+            rows,
+            cols,
+            keymap,
+            debouncer,
+            keyboard_reports,
+            mouse_report,
+            consumer_report,
+            system_report,
+            store,
+            origin,
+            save_at,
+            via,
+            via_response,
+        ],
     )]
     fn write_keyboard(mut cx: write_keyboard::Context, scheduled: Instant) {
         cx.shared.keyboard.lock(|k| {
@@ -225,45 +296,166 @@ mod app {
                 .into_array()
                 .unwrap();
             cx.local.debouncer.debounce(&mut pressed);
-            cx.local.keymap.process(pressed, scheduled.ticks());
-            match k
-                .device()
-                .write_report(cx.local.keymap.pressed_keys.iter().cloned())
-            {
-                Err(UsbHidError::WouldBlock) => {}
-                Err(UsbHidError::Duplicate) => {}
-                Ok(_) => {}
-                Err(e) => {
-                    core::panic!("Failed to write keyboard report: {:?}", e)
+            let interface = k.device::<NKROBootKeyboard<'static, _>, _>().interface();
+            cx.local.keymap.boot_protocol = interface.get_protocol() == HidProtocol::Boot;
+            // Hold off scanning while the queue has no room for a scan's reports, rather than lose
+            // a transition the host has not seen
+            if cx.local.keyboard_reports.room() < KeymapT::MAX_REPORTS {
+                defmt::warn!("Keyboard report queue full");
+            } else {
+                cx.local.keymap.process(pressed, scheduled.ticks());
+                for report in cx.local.keymap.reports() {
+                    cx.local.keyboard_reports.push(report);
+                }
+            }
+            let keyboard = k.device::<NKROBootKeyboard<'static, _>, _>();
+            cx.local
+                .keyboard_reports
+                .flush(|report| match keyboard.write_report(report.codes()) {
+                    Err(UsbHidError::WouldBlock) => false,
+                    Err(UsbHidError::Duplicate) => true,
+                    Ok(_) => true,
+                    Err(e) => {
+                        core::panic!("Failed to write keyboard report: {:?}", e)
+                    }
+                });
+            if let Some(report) = cx.local.keymap.mouse_report {
+                // The latest buttons, with the movement not yet sent
+                let pending = cx.local.mouse_report.get_or_insert_with(Default::default);
+                *pending = WheelMouseReport {
+                    buttons: report.buttons,
+                    x: pending.x.saturating_add(report.x),
+                    y: pending.y.saturating_add(report.y),
+                    vertical_wheel: pending.vertical_wheel.saturating_add(report.wheel),
+                    horizontal_wheel: pending.horizontal_wheel.saturating_add(report.pan),
+                };
+            }
+            if let Some(report) = *cx.local.mouse_report {
+                match k
+                    .device::<WheelMouse<'static, _>, _>()
+                    .write_report(&report)
+                {
+                    Err(UsbHidError::WouldBlock) => {}
+                    Ok(_) => *cx.local.mouse_report = None,
+                    Err(e) => {
+                        core::panic!("Failed to write mouse report: {:?}", e)
+                    }
+                }
+            }
+
+            // Sized by the keymap's rollover, so the report not fitting it does not compile
+            let mut codes = [Consumer::Unassigned; CONSUMER_ROLLOVER];
+            for (code, key) in codes.iter_mut().zip(&cx.local.keymap.consumer_keys) {
+                *code = *key;
+            }
+            let report = MultipleConsumerReport { codes };
+            if report != *cx.local.consumer_report {
+                match k
+                    .device::<ConsumerControl<'static, _>, _>()
+                    .write_report(&report)
+                {
+                    Err(UsbError::WouldBlock) => {}
+                    Ok(_) => *cx.local.consumer_report = report,
+                    Err(e) => {
+                        core::panic!("Failed to write consumer report: {:?}", e)
+                    }
+                }
+            }
+
+            let report = cx.local.keymap.system_keys.first().copied();
+            if report != *cx.local.system_report {
+                match k
+                    .device::<SystemControl<'static, _>, _>()
+                    .write_report(report)
+                {
+                    Err(UsbError::WouldBlock) => {}
+                    Ok(_) => *cx.local.system_report = report,
+                    Err(e) => {
+                        core::panic!("Failed to write system control report: {:?}", e)
+                    }
                 }
             }
         });
 
+        // A new request is only handled once the previous response is sent
+        if cx.local.via_response.is_none() {
+            if let Some(mut report) = cx.shared.via_report.lock(Option::take) {
+                let uptime = scheduled.duration_since_epoch().to_millis() as u32;
+                match cx.local.via.handle(&mut report, cx.local.keymap, uptime) {
+                    Some(ViaRequest::ResetKeymap) => {
+                        *cx.local.keymap = keymap();
+                        *cx.local.save_at = Some(scheduled);
+                    }
+                    Some(ViaRequest::Bootloader) => hal::rom_data::reset_to_usb_boot(0, 0),
+                    None => {}
+                }
+                *cx.local.via_response = Some(report);
+            }
+        }
+        if let Some(report) = *cx.local.via_response {
+            cx.shared.keyboard.lock(|k| {
+                match k.device::<RawHid<'static, _>, _>().write_report(&report) {
+                    Err(UsbError::WouldBlock) => {}
+                    Ok(_) => *cx.local.via_response = None,
+                    Err(e) => {
+                        core::panic!("Failed to write raw HID report: {:?}", e)
+                    }
+                }
+            });
+        }
+
+        if cx.local.keymap.take_changed() {
+            *cx.local.save_at = Some(scheduled + 1.secs());
+        }
+        if cx.local.save_at.is_some_and(|at| at <= scheduled) {
+            *cx.local.save_at = None;
+            let mut buf = [0; STORE_LEN];
+            let saved = match cx.local.keymap.persist(*cx.local.origin, &mut buf) {
+                Ok(len) => cx.local.store.save(&buf[..len]).is_ok(),
+                Err(_) => false,
+            };
+            if !saved {
+                defmt::warn!("Failed to save the keymap");
+            }
+        }
+
         let next = scheduled + 1.millis();
         write_keyboard::spawn_at(next, next).unwrap();
     }
 
     #[task(
         binds = USBCTRL_IRQ,
-        shared = [keyboard, usb_device],
+        shared = [keyboard, usb_device, via_report],
         local = [led]
     )]
     fn usb_irq(cx: usb_irq::Context) {
-        (cx.shared.keyboard, cx.shared.usb_device).lock(|keyboard, usb_device| {
-            if usb_device.poll(&mut [keyboard]) {
-                let interface = keyboard.device();
-                match interface.read_report() {
-                    Err(UsbError::WouldBlock) => {}
-                    Err(e) => {
-                        core::panic!("Failed to read keyboard report: {:?}", e)
+        (
+            cx.shared.keyboard,
+            cx.shared.usb_device,
+            cx.shared.via_report,
+        )
+            .lock(|keyboard, usb_device, via_report| {
+                if usb_device.poll(&mut [keyboard]) {
+                    let interface = keyboard.device::<NKROBootKeyboard<'static, _>, _>();
+                    match interface.read_report() {
+                        Err(UsbError::WouldBlock) => {}
+                        Err(e) => {
+                            core::panic!("Failed to read keyboard report: {:?}", e)
+                        }
+                        Ok(leds) => cx
+                            .local
+                            .led
+                            .set_state(PinState::from(leds.num_lock))
+                            .unwrap(),
+                    }
+                    match keyboard.device::<RawHid<'static, _>, _>().read_report() {
+                        Err(UsbError::WouldBlock) => {}
+                        Err(e) => {
+                            core::panic!("Failed to read raw HID report: {:?}", e)
+                        }
+                        Ok(report) => *via_report = Some(report),
                     }
-                    Ok(leds) => cx
-                        .local
-                        .led
-                        .set_state(PinState::from(leds.num_lock))
-                        .unwrap(),
                 }
-            }
-        })
+            })
     }
 }
@@ -0,0 +1,75 @@
+//! HID system control (power, sleep, wake), which `usbd_human_interface_device` does not provide
+
+use fugit::ExtU32;
+use usb_device::class_prelude::*;
+use usbd_human_interface_device::page::Desktop;
+use usbd_human_interface_device::usb_class::prelude::*;
+
+/// One Generic Desktop system control usage as an array, `0` for none
+#[rustfmt::skip]
+pub const SYSTEM_CONTROL_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop),
+    0x09, 0x80, // Usage (System Control),
+    0xA1, 0x01, // Collection (Application),
+    0x75, 0x08, //     Report Size(8)
+    0x95, 0x01, //     Report Count(1)
+    0x15, 0x01, //     Logical Minimum(0x01)
+    0x26, 0xB7, 0x00, //     Logical Maximum(0xB7)
+    0x19, 0x01, //     Usage Minimum(0x01)
+    0x2A, 0xB7, 0x00, //     Usage Maximum(0xB7)
+    0x81, 0x00, //     Input (Data, Array, Absolute)
+    0xC0, // End Collection
+];
+
+pub struct SystemControl<'a, B: UsbBus> {
+    interface: Interface<'a, B, InBytes8, OutNone, ReportSingle>,
+}
+
+impl<'a, B: UsbBus> SystemControl<'a, B> {
+    pub fn write_report(&mut self, key: Option<Desktop>) -> usb_device::Result<usize> {
+        self.interface
+            .write_report(&[key.map(u8::from).unwrap_or(0)])
+    }
+}
+
+impl<'a, B: UsbBus> DeviceClass<'a> for SystemControl<'a, B> {
+    type I = Interface<'a, B, InBytes8, OutNone, ReportSingle>;
+
+    fn interface(&mut self) -> &mut Self::I {
+        &mut self.interface
+    }
+
+    fn reset(&mut self) {}
+
+    fn tick(&mut self) -> Result<(), UsbHidError> {
+        Ok(())
+    }
+}
+
+pub struct SystemControlConfig<'a> {
+    interface: InterfaceConfig<'a, InBytes8, OutNone, ReportSingle>,
+}
+
+impl<'a> Default for SystemControlConfig<'a> {
+    fn default() -> Self {
+        Self {
+            interface: InterfaceBuilder::new(SYSTEM_CONTROL_REPORT_DESCRIPTOR)
+                .unwrap()
+                .description("System Control")
+                .in_endpoint(50.millis())
+                .unwrap()
+                .without_out_endpoint()
+                .build(),
+        }
+    }
+}
+
+impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for SystemControlConfig<'a> {
+    type Allocated = SystemControl<'a, B>;
+
+    fn allocate(self, usb_alloc: &'a UsbBusAllocator<B>) -> Self::Allocated {
+        Self::Allocated {
+            interface: Interface::new(usb_alloc, self.interface),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Converts a QMK Configurator `keymap.json` for a board, given as a keymap config whose matrix,
+//! layout and timings are kept, to Rust like the build script generates, or to the binary format
+//!
+//! ```text
+//! qmk-import board.toml keymap.json > keymap.rs
+//! qmk-import --binary 1000 board.toml keymap.json > keymap.bin
+//! ```
+//!
+//! `--binary` takes the keymap's ticks per millisecond. Unsupported keycodes are listed on stderr.
+
+use std::io::Write;
+use std::process::exit;
+
+use rmk_mekk_elek::codegen::{qmk, Config};
+
+const USAGE: &str = "usage: qmk-import [--binary TICKS_PER_MS] BOARD.toml KEYMAP.json";
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", path, e);
+        exit(1);
+    })
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let ticks_per_ms = match args.iter().position(|arg| arg == "--binary") {
+        Some(index) => {
+            let ticks = args.get(index + 1).and_then(|ticks| ticks.parse().ok());
+            let Some(ticks) = ticks else {
+                eprintln!("{}", USAGE);
+                exit(2);
+            };
+            args.drain(index..index + 2);
+            Some(ticks)
+        }
+        None => None,
+    };
+    let [board_path, keymap_path] = &args[..] else {
+        eprintln!("{}", USAGE);
+        exit(2);
+    };
+
+    let board = Config::parse(&read(board_path)).unwrap_or_else(|e| {
+        eprintln!("error: {}:{}: {}", board_path, e.line, e.message);
+        exit(1);
+    });
+    let import = qmk::import(&read(keymap_path), &board).unwrap_or_else(|e| {
+        eprintln!("error: {}:{}: {}", keymap_path, e.line, e.message);
+        exit(1);
+    });
+    for unsupported in &import.unsupported {
+        eprintln!("warning: {}: {}", keymap_path, unsupported);
+    }
+
+    let out = match ticks_per_ms {
+        Some(ticks_per_ms) => import.config.to_binary(ticks_per_ms).unwrap_or_else(|e| {
+            eprintln!("error: cannot encode the keymap: {:?}", e);
+            exit(1);
+        }),
+        None => import.config.to_rust().into_bytes(),
+    };
+    std::io::stdout().write_all(&out).unwrap();
+}
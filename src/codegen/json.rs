@@ -0,0 +1,306 @@
+//! Enough JSON for QMK's `keymap.json`, each value remembering its line for errors
+
+extern crate std;
+
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+use super::ConfigError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Item>),
+    /// Members in the order written
+    Object(Vec<(String, Item)>),
+}
+
+/// A value with the line it starts on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub line: usize,
+    pub value: Value,
+}
+
+impl Item {
+    /// The member `key`, if this is an object with one
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        match &self.value {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, item)| item),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    at: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.at..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ConfigError> {
+        Err(ConfigError::new(self.line, message))
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ConfigError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("expected `{}`, found `{}`", expected, c)),
+            None => self.error(format!("expected `{}`, found the end", expected)),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, ' ' | '\t' | '\r' | '\n'))
+        {
+            self.bump();
+        }
+    }
+
+    fn item(&mut self) -> Result<Item, ConfigError> {
+        self.skip_space();
+        let line = self.line;
+        let value = match self.peek() {
+            Some('{') => Value::Object(self.object()?),
+            Some('[') => Value::Array(self.array()?),
+            Some('"') => Value::Str(self.string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => Value::Number(self.number()?),
+            Some(c) if c.is_ascii_alphabetic() => self.literal()?,
+            Some(c) => return self.error(format!("expected a value, found `{}`", c)),
+            None => return self.error("expected a value, found the end"),
+        };
+        Ok(Item { line, value })
+    }
+
+    /// Items separated by commas up to `close`, which is consumed
+    fn list(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<(), ConfigError>,
+    ) -> Result<(), ConfigError> {
+        self.skip_space();
+        if self.peek() == Some(close) {
+            self.bump();
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            self.skip_space();
+            match self.bump() {
+                Some(',') => (),
+                Some(c) if c == close => return Ok(()),
+                _ => return self.error(format!("expected `,` or `{}`", close)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Vec<(String, Item)>, ConfigError> {
+        self.expect('{')?;
+        let mut members: Vec<(String, Item)> = Vec::new();
+        self.list('}', |parser| {
+            parser.skip_space();
+            let line = parser.line;
+            let key = parser.string()?;
+            if members.iter().any(|(other, _)| *other == key) {
+                return Err(ConfigError::new(line, format!("duplicate key `{}`", key)));
+            }
+            parser.skip_space();
+            parser.expect(':')?;
+            members.push((key, parser.item()?));
+            Ok(())
+        })?;
+        Ok(members)
+    }
+
+    fn array(&mut self) -> Result<Vec<Item>, ConfigError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.list(']', |parser| {
+            items.push(parser.item()?);
+            Ok(())
+        })?;
+        Ok(items)
+    }
+
+    fn string(&mut self) -> Result<String, ConfigError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek().filter(|c| *c != '\n') else {
+                return self.error("unterminated string");
+            };
+            self.bump();
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let c = match self.bump() {
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return self.error("invalid escape in string"),
+                    };
+                    string.push(c);
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ConfigError> {
+        let digits = self.source.get(self.at..self.at + 4).unwrap_or_default();
+        match u32::from_str_radix(digits, 16) {
+            Ok(value) if digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                self.at += 4;
+                Ok(value)
+            }
+            _ => self.error("expected four hex digits after `\\u`"),
+        }
+    }
+
+    /// After `\u`, including a second escape for the low half of a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, ConfigError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.source[self.at..].starts_with("\\u") {
+                return self.error("unpaired surrogate in string");
+            }
+            self.at += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return self.error("unpaired surrogate in string");
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error("unpaired surrogate in string"),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, ConfigError> {
+        let start = self.at;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.bump();
+        }
+        let text = &self.source[start..self.at];
+        match text.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => self.error(format!("invalid number `{}`", text)),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, ConfigError> {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.bump();
+        }
+        match &self.source[start..self.at] {
+            "null" => Ok(Value::Null),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            word => self.error(format!("expected a value, found `{}`", word)),
+        }
+    }
+}
+
+/// A whole JSON document
+pub fn parse(source: &str) -> Result<Item, ConfigError> {
+    let mut parser = Parser {
+        source,
+        at: 0,
+        line: 1,
+    };
+    let item = parser.item()?;
+    parser.skip_space();
+    match parser.peek() {
+        None => Ok(item),
+        Some(c) => parser.error(format!("expected the end, found `{}`", c)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec;
+
+    #[test]
+    fn values() {
+        let source = "{\n  \"a\": [1, -2.5e1, \"x\\\"\\u00e9\\ud83d\\ude00\"],\n  \"b\": {\"c\": null},\n  \"d\": [true, false, []]\n}\n";
+        let item = parse(source).unwrap();
+        assert_eq!(
+            item.get("a"),
+            Some(&Item {
+                line: 2,
+                value: Value::Array(vec![
+                    Item {
+                        line: 2,
+                        value: Value::Number(1.0),
+                    },
+                    Item {
+                        line: 2,
+                        value: Value::Number(-25.0),
+                    },
+                    Item {
+                        line: 2,
+                        value: Value::Str("x\"é😀".to_string()),
+                    },
+                ]),
+            })
+        );
+        assert_eq!(
+            item.get("b").and_then(|b| b.get("c")).map(|c| &c.value),
+            Some(&Value::Null)
+        );
+        assert_eq!(item.get("d").map(|d| d.line), Some(4));
+        assert_eq!(item.get("e"), None);
+    }
+
+    #[test]
+    fn errors() {
+        let line = |source| parse(source).unwrap_err().line;
+        assert_eq!(line("{\"a\": 1,\n\"a\": 2}"), 2);
+        assert_eq!(line("[1,\n2\n3]"), 3);
+        assert_eq!(line("[\n\"open\n\"]"), 2);
+        assert_eq!(line("\n[1] 2"), 2);
+        assert_eq!(line("[nope]"), 1);
+        assert_eq!(line("[\"\\ud83d\"]"), 1);
+        assert_eq!(line("{\"a\" 1}"), 1);
+        assert_eq!(line("[1,\n"), 2);
+    }
+}
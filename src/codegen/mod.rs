@@ -0,0 +1,989 @@
+//! Keymaps from a config file, compiled to Rust by a build script
+//!
+//! The config is TOML, for example
+//!
+//! ```toml
+//! [matrix]
+//! rows = 2
+//! cols = 3
+//! # Optional, the matrix position of each key by physical row, when it differs from the matrix
+//! layout = ["0,0 0,1 0,2", "1,0 1,1 1,2"]
+//!
+//! [timing]  # In milliseconds
+//! mod_timeout = 200
+//! tap_release = 100
+//! tap_repeat = 500
+//! # Optional, send the tap of a mod-tap held past `mod_timeout` when no other key was pressed
+//! retro_tap = true
+//!
+//! [[layer]]
+//! name = "base"
+//! keys = [
+//!   "Escape  MT(LSFT, A)  La(fn)",
+//!   "Sft(K1) Cc(VOLU)     CapsWord",
+//! ]
+//!
+//! [[layer]]
+//! name = "fn"
+//! keys = ["F1 F2 ___", "___ ___ ___"]
+//! # Optional, timings for this layer's mod-tap and layer-tap keys, over those in `[timing]`
+//! mod_timeout = 300
+//!
+//! # Optional, timings for keys by matrix position on every layer, over those of the layer
+//! [[key_timing]]
+//! keys = "0,1"
+//! mod_timeout = 250
+//! ```
+//!
+//! Keys are written as in `keystate::prelude`, with `Kb` left out, and `La` and `LT` take a
+//! layer's name or number. `MT` holds several modifiers written like `MT(LCTL|LSFT, A)`. Each row
+//! must have as many keys as the matrix or layout row, so a missing key is reported on its line
+//! rather than shifting the keys after it.
+//!
+//! `qmk::import` replaces a config's layers with those of a QMK Configurator `keymap.json`, and
+//! the `qmk-import` binary writes the result as Rust or in the binary format.
+
+extern crate std;
+
+use std::fmt;
+use std::format;
+use std::string::String;
+use std::string::ToString;
+use std::vec::Vec;
+
+use crate::keystate::binary::{self, EncodeError};
+use crate::keystate::mods::Mods;
+use crate::keystate::modtap::{ModTapConfig, Timing};
+use crate::keystate::mouse::MouseAction;
+use crate::keystate::Consumer;
+use crate::keystate::Desktop;
+use crate::keystate::KeyShorthand;
+use crate::keystate::Keyboard;
+
+pub mod json;
+pub mod qmk;
+pub mod toml;
+
+use toml::{Entry, Table, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Line in the config, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        ConfigError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A keymap config, checked and with the keys in matrix order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub rows: usize,
+    pub cols: usize,
+    pub mod_timeout: u64,
+    pub tap_release: u64,
+    pub tap_repeat: u64,
+    pub retro_tap: bool,
+    /// Each layer's timings, in milliseconds
+    pub layer_timings: Vec<Timing>,
+    /// Timings by matrix index, in milliseconds
+    pub key_timings: Vec<(usize, Timing)>,
+    pub layer_names: Vec<String>,
+    /// The matrix index of each key in layout order, by physical row as QMK's `LAYOUT` lists them
+    pub positions: Vec<usize>,
+    /// Each layer's `rows * cols` bindings, `Kb(___)` where the layout has no key
+    pub layers: Vec<Vec<KeyShorthand>>,
+}
+
+/// Names `keystate::prelude` adds for keyboard keys
+const KEYBOARD_ALIASES: &[(&str, Keyboard)] = &[
+    ("NOP", Keyboard::NoEventIndicated),
+    ("___", Keyboard::NoEventIndicated),
+    ("K0", Keyboard::Keyboard0),
+    ("K1", Keyboard::Keyboard1),
+    ("K2", Keyboard::Keyboard2),
+    ("K3", Keyboard::Keyboard3),
+    ("K4", Keyboard::Keyboard4),
+    ("K5", Keyboard::Keyboard5),
+    ("K6", Keyboard::Keyboard6),
+    ("K7", Keyboard::Keyboard7),
+    ("K8", Keyboard::Keyboard8),
+    ("K9", Keyboard::Keyboard9),
+    ("LSFT", Keyboard::LeftShift),
+    ("LCTL", Keyboard::LeftControl),
+    ("LALT", Keyboard::LeftAlt),
+    ("LWIN", Keyboard::LeftGUI),
+    ("LGUI", Keyboard::LeftGUI),
+    ("RSFT", Keyboard::RightShift),
+    ("RCTL", Keyboard::RightControl),
+    ("RALT", Keyboard::RightAlt),
+    ("RWIN", Keyboard::RightGUI),
+    ("RGUI", Keyboard::RightGUI),
+    ("LEFT", Keyboard::LeftArrow),
+    ("RIGHT", Keyboard::RightArrow),
+    ("UP", Keyboard::UpArrow),
+    ("DOWN", Keyboard::DownArrow),
+    ("BSL", Keyboard::Backslash),
+];
+
+const MOUSE_ACTIONS: &[(&str, MouseAction)] = &[
+    ("MS_U", MouseAction::Up),
+    ("MS_D", MouseAction::Down),
+    ("MS_L", MouseAction::Left),
+    ("MS_R", MouseAction::Right),
+    ("WH_U", MouseAction::WheelUp),
+    ("WH_D", MouseAction::WheelDown),
+    ("WH_L", MouseAction::WheelLeft),
+    ("WH_R", MouseAction::WheelRight),
+    ("BTN1", MouseAction::Button(1)),
+    ("BTN2", MouseAction::Button(2)),
+    ("BTN3", MouseAction::Button(3)),
+    ("BTN4", MouseAction::Button(4)),
+    ("BTN5", MouseAction::Button(5)),
+];
+
+const CONSUMER_ALIASES: &[(&str, Consumer)] = &[
+    ("MUTE", Consumer::Mute),
+    ("VOLU", Consumer::VolumeIncrement),
+    ("VOLD", Consumer::VolumeDecrement),
+    ("MPLY", Consumer::PlayPause),
+    ("MSTP", Consumer::Stop),
+    ("MNXT", Consumer::ScanNextTrack),
+    ("MPRV", Consumer::ScanPreviousTrack),
+    ("EJCT", Consumer::Eject),
+];
+
+const DESKTOP_ALIASES: &[(&str, Desktop)] = &[
+    ("PWR", Desktop::SystemPowerDown),
+    ("SLEP", Desktop::SystemSleep),
+    ("WAKE", Desktop::SystemWakeUp),
+];
+
+const MOD_WRAPPERS: &[(&str, Mods)] = &[
+    ("Sft", Mods::LSFT),
+    ("Ctl", Mods::LCTL),
+    ("Alt", Mods::LALT),
+    ("Gui", Mods::LGUI),
+    ("RSft", Mods::RSFT),
+    ("RCtl", Mods::RCTL),
+    ("RAlt", Mods::RALT),
+    ("RGui", Mods::RGUI),
+];
+
+fn alias<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, value)| *value)
+}
+
+/// A keyboard key by its `Keyboard` variant or prelude name
+pub fn keyboard(name: &str) -> Option<Keyboard> {
+    alias(KEYBOARD_ALIASES, name).or_else(|| {
+        (0..=u8::MAX)
+            .map(Keyboard::from)
+            .find(|key| format!("{:?}", key) == name)
+    })
+}
+
+fn consumer(name: &str) -> Option<Consumer> {
+    alias(CONSUMER_ALIASES, name).or_else(|| {
+        (0..=0x0FFF)
+            .map(Consumer::from)
+            .find(|key| format!("{:?}", key) == name)
+    })
+}
+
+fn desktop(name: &str) -> Option<Desktop> {
+    alias(DESKTOP_ALIASES, name).or_else(|| {
+        (0..=u8::MAX)
+            .map(Desktop::from)
+            .find(|key| format!("{:?}", key) == name)
+    })
+}
+
+/// Splits a row on whitespace outside parentheses
+fn tokens(row: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    for (at, c) in row.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    tokens.push(&row[start..at]);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(at);
+    }
+    if let Some(start) = start {
+        tokens.push(&row[start..]);
+    }
+    tokens
+}
+
+/// A layer by name or number
+fn layer(layer: &str, layers: &[String]) -> Result<u8, String> {
+    let layer = layer.trim();
+    let index = match layers.iter().position(|name| name == layer) {
+        Some(index) => index,
+        None => layer
+            .parse()
+            .map_err(|_| format!("unknown layer `{}`", layer))?,
+    };
+    if index >= layers.len() {
+        return Err(format!("layer {} does not exist", index));
+    }
+    Ok(index as u8)
+}
+
+/// Parses one key such as `A`, `MT(LSFT, A)`, `MT(LCTL|LSFT, A)` or `Sft(K1)`, with layers looked
+/// up in `layers`
+pub fn parse_key(token: &str, layers: &[String]) -> Result<KeyShorthand, String> {
+    let token = token.trim();
+    let Some((name, args)) = token.split_once('(') else {
+        return match token {
+            "Lead" => Ok(KeyShorthand::Lead),
+            "CapsWord" => Ok(KeyShorthand::CapsWord),
+            _ => keyboard(token)
+                .map(KeyShorthand::Kb)
+                .ok_or_else(|| format!("unknown key `{}`", token)),
+        };
+    };
+    let args = args
+        .strip_suffix(')')
+        .ok_or_else(|| format!("missing `)` in `{}`", token))?;
+    let key = |name: &str| keyboard(name.trim()).ok_or_else(|| format!("unknown key `{}`", name));
+    match name {
+        "MT" => {
+            let (mod_, tap) = args
+                .split_once(',')
+                .ok_or_else(|| format!("expected `MT(modifier, key)`, found `{}`", token))?;
+            let mut mods = Mods::NONE;
+            for name in mod_.split('|') {
+                let mod_ = key(name)?;
+                mods |= Mods::from_key(mod_)
+                    .ok_or_else(|| format!("`{:?}` is not a modifier", mod_))?;
+            }
+            let mut keys = mods.keys();
+            Ok(match (keys.next(), keys.next()) {
+                (Some(mod_), None) => KeyShorthand::MT(mod_, key(tap)?),
+                _ => KeyShorthand::MMT(mods, key(tap)?),
+            })
+        }
+        "La" => Ok(KeyShorthand::La(layer(args, layers)?)),
+        "LT" => {
+            let (layer_name, tap) = args
+                .split_once(',')
+                .ok_or_else(|| format!("expected `LT(layer, key)`, found `{}`", token))?;
+            Ok(KeyShorthand::LT(layer(layer_name, layers)?, key(tap)?))
+        }
+        "AS" => Ok(KeyShorthand::AS(key(args)?)),
+        "Kb" => Ok(KeyShorthand::Kb(key(args)?)),
+        "Cc" => consumer(args.trim())
+            .map(KeyShorthand::Cc)
+            .ok_or_else(|| format!("unknown consumer key `{}`", args)),
+        "Sys" => desktop(args.trim())
+            .map(KeyShorthand::Sys)
+            .ok_or_else(|| format!("unknown system key `{}`", args)),
+        "Ms" => alias(MOUSE_ACTIONS, args.trim())
+            .map(KeyShorthand::Ms)
+            .ok_or_else(|| format!("unknown mouse key `{}`", args)),
+        _ => {
+            let mods =
+                alias(MOD_WRAPPERS, name).ok_or_else(|| format!("unknown key `{}`", token))?;
+            match parse_key(args, layers)? {
+                KeyShorthand::Kb(key) => Ok(KeyShorthand::MK(mods, key)),
+                KeyShorthand::MK(held, key) => Ok(KeyShorthand::MK(held.union(mods), key)),
+                _ => Err(format!(
+                    "only plain keys can be modified, found `{}`",
+                    token
+                )),
+            }
+        }
+    }
+}
+
+fn int(entry: &Entry) -> Result<u64, ConfigError> {
+    match entry.value {
+        Value::Int(int) if int >= 0 => Ok(int as u64),
+        _ => Err(ConfigError::new(
+            entry.line,
+            format!("`{}` must be a non-negative integer", entry.key),
+        )),
+    }
+}
+
+fn boolean(entry: &Entry) -> Result<bool, ConfigError> {
+    match entry.value {
+        Value::Bool(bool) => Ok(bool),
+        _ => Err(ConfigError::new(
+            entry.line,
+            format!("`{}` must be `true` or `false`", entry.key),
+        )),
+    }
+}
+
+fn string(entry: &Entry) -> Result<&str, ConfigError> {
+    match &entry.value {
+        Value::Str(string) => Ok(string),
+        _ => Err(ConfigError::new(
+            entry.line,
+            format!("`{}` must be a string", entry.key),
+        )),
+    }
+}
+
+/// The strings of an array, with their lines
+fn strings(entry: &Entry) -> Result<Vec<(usize, &str)>, ConfigError> {
+    let Value::Array(items) = &entry.value else {
+        return Err(ConfigError::new(
+            entry.line,
+            format!("`{}` must be an array of strings", entry.key),
+        ));
+    };
+    items
+        .iter()
+        .map(|item| match &item.value {
+            Value::Str(string) => Ok((item.line, string.as_str())),
+            _ => Err(ConfigError::new(
+                item.line,
+                format!("`{}` must be an array of strings", entry.key),
+            )),
+        })
+        .collect()
+}
+
+/// The table's entries by key, failing on keys not in `keys` and on missing `required` ones
+fn entries<'t, const N: usize>(
+    table: &'t Table,
+    keys: [&str; N],
+    required: usize,
+) -> Result<[Option<&'t Entry>; N], ConfigError> {
+    if let Some(entry) = table
+        .entries
+        .iter()
+        .find(|e| !keys.contains(&e.key.as_str()))
+    {
+        return Err(ConfigError::new(
+            entry.line,
+            format!("unknown key `{}` in `{}`", entry.key, table.name),
+        ));
+    }
+    let found = keys.map(|key| table.entries.iter().find(|e| e.key == key));
+    if let Some(missing) = (0..required).find(|&index| found[index].is_none()) {
+        return Err(ConfigError::new(
+            table.line,
+            format!("`{}` is missing `{}`", table.name, keys[missing]),
+        ));
+    }
+    Ok(found)
+}
+
+fn single<'t>(tables: &'t [Table], name: &str) -> Result<&'t Table, ConfigError> {
+    let mut matching = tables.iter().filter(|table| table.name == name);
+    let table = matching
+        .next()
+        .ok_or_else(|| ConfigError::new(1, format!("missing the `[{}]` table", name)))?;
+    match matching.next() {
+        Some(other) => Err(ConfigError::new(
+            other.line,
+            format!("duplicate `[{}]` table", name),
+        )),
+        None if table.array => Err(ConfigError::new(
+            table.line,
+            format!("`{}` must be a table, `[{}]`", name, name),
+        )),
+        None => Ok(table),
+    }
+}
+
+/// The matrix index of a `row,col` position
+fn position(token: &str, rows: usize, cols: usize) -> Result<usize, String> {
+    let (row, col): (usize, usize) = token
+        .split_once(',')
+        .and_then(|(row, col)| Some((row.parse().ok()?, col.parse().ok()?)))
+        .ok_or_else(|| format!("expected `row,col`, found `{}`", token))?;
+    if row >= rows || col >= cols {
+        return Err(format!("`{}` is outside the matrix", token));
+    }
+    Ok(row * cols + col)
+}
+
+/// Timings over those in `[timing]`, from the given entries
+fn timing_override(entries: [Option<&Entry>; 3]) -> Result<Timing, ConfigError> {
+    let [mod_timeout, tap_release, tap_repeat] = entries.map(|entry| entry.map(int).transpose());
+    Ok(Timing {
+        mod_timeout: mod_timeout?,
+        tap_release: tap_release?,
+        tap_repeat: tap_repeat?,
+    })
+}
+
+/// The matrix position of each key by physical row
+fn layout(
+    entry: &Entry,
+    rows: usize,
+    cols: usize,
+) -> Result<Vec<(usize, Vec<usize>)>, ConfigError> {
+    let mut used = std::vec![false; rows * cols];
+    strings(entry)?
+        .into_iter()
+        .map(|(line, row)| {
+            let error = |message| ConfigError::new(line, message);
+            let positions = tokens(row)
+                .into_iter()
+                .map(|token| {
+                    let index = position(token, rows, cols).map_err(error)?;
+                    if core::mem::replace(&mut used[index], true) {
+                        return Err(error(format!("`{}` is used twice", token)));
+                    }
+                    Ok(index)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((line, positions))
+        })
+        .collect()
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        let tables = toml::parse(source)?;
+        if let Some(table) = tables.iter().find(|table| {
+            !["matrix", "timing", "layer", "key_timing"].contains(&table.name.as_str())
+        }) {
+            return Err(ConfigError::new(
+                table.line,
+                format!("unknown table `{}`", table.name),
+            ));
+        }
+
+        let matrix = single(&tables, "matrix")?;
+        let [rows, cols, layout_entry] = entries(matrix, ["rows", "cols", "layout"], 2)?;
+        let (rows, cols) = (int(rows.unwrap())? as usize, int(cols.unwrap())? as usize);
+        let layout = match layout_entry {
+            Some(entry) => layout(entry, rows, cols)?,
+            None => (0..rows)
+                .map(|row| (matrix.line, (row * cols..(row + 1) * cols).collect()))
+                .collect(),
+        };
+
+        let timing = single(&tables, "timing")?;
+        let [mod_timeout, tap_release, tap_repeat, retro_tap] = entries(
+            timing,
+            ["mod_timeout", "tap_release", "tap_repeat", "retro_tap"],
+            3,
+        )?;
+
+        let layer_tables: Vec<&Table> = tables.iter().filter(|t| t.name == "layer").collect();
+        if layer_tables.is_empty() {
+            return Err(ConfigError::new(1, "missing a `[[layer]]` table"));
+        }
+        if layer_tables.len() > u8::MAX as usize {
+            return Err(ConfigError::new(layer_tables[0].line, "too many layers"));
+        }
+        let mut layer_names = Vec::new();
+        let mut layer_keys = Vec::new();
+        let mut layer_timings = Vec::new();
+        for table in &layer_tables {
+            if !table.array {
+                return Err(ConfigError::new(
+                    table.line,
+                    "layers must be an array of tables, `[[layer]]`",
+                ));
+            }
+            let [name, keys, mod_timeout, tap_release, tap_repeat] = entries(
+                table,
+                ["name", "keys", "mod_timeout", "tap_release", "tap_repeat"],
+                2,
+            )?;
+            let (name_entry, keys) = (name.unwrap(), keys.unwrap());
+            layer_timings.push(timing_override([mod_timeout, tap_release, tap_repeat])?);
+            let name = string(name_entry)?;
+            if layer_names.iter().any(|other| other == name) {
+                return Err(ConfigError::new(
+                    name_entry.line,
+                    format!("duplicate layer `{}`", name),
+                ));
+            }
+            layer_names.push(name.to_string());
+            layer_keys.push((table.line, keys));
+        }
+
+        let mut layers = Vec::new();
+        for (layer_line, keys) in layer_keys {
+            let keys = strings(keys)?;
+            if keys.len() != layout.len() {
+                return Err(ConfigError::new(
+                    layer_line,
+                    format!("expected {} rows, found {}", layout.len(), keys.len()),
+                ));
+            }
+            let mut bindings = std::vec![KeyShorthand::Kb(Keyboard::NoEventIndicated); rows * cols];
+            for ((line, row), (_, positions)) in keys.into_iter().zip(&layout) {
+                let row = tokens(row);
+                if row.len() != positions.len() {
+                    return Err(ConfigError::new(
+                        line,
+                        format!("expected {} keys, found {}", positions.len(), row.len()),
+                    ));
+                }
+                for (token, position) in row.into_iter().zip(positions) {
+                    bindings[*position] = parse_key(token, &layer_names)
+                        .map_err(|message| ConfigError::new(line, message))?;
+                }
+            }
+            layers.push(bindings);
+        }
+
+        let mut key_timings = Vec::new();
+        for table in tables.iter().filter(|t| t.name == "key_timing") {
+            if !table.array {
+                return Err(ConfigError::new(
+                    table.line,
+                    "key timings must be an array of tables, `[[key_timing]]`",
+                ));
+            }
+            let [keys, mod_timeout, tap_release, tap_repeat] = entries(
+                table,
+                ["keys", "mod_timeout", "tap_release", "tap_repeat"],
+                1,
+            )?;
+            let keys = keys.unwrap();
+            let timing = timing_override([mod_timeout, tap_release, tap_repeat])?;
+            for token in tokens(string(keys)?) {
+                let index = position(token, rows, cols)
+                    .map_err(|message| ConfigError::new(keys.line, message))?;
+                key_timings.retain(|(other, _)| *other != index);
+                key_timings.push((index, timing));
+            }
+        }
+
+        Ok(Config {
+            rows,
+            cols,
+            mod_timeout: int(mod_timeout.unwrap())?,
+            tap_release: int(tap_release.unwrap())?,
+            tap_repeat: int(tap_repeat.unwrap())?,
+            retro_tap: retro_tap.map(boolean).transpose()?.unwrap_or(false),
+            layer_timings,
+            key_timings,
+            layer_names,
+            positions: layout
+                .into_iter()
+                .flat_map(|(_, positions)| positions)
+                .collect(),
+            layers,
+        })
+    }
+
+    /// Rust source with the size constants, the bindings as `BINDINGS`, and a
+    /// `keymap(ticks_per_ms)` constructor, using absolute paths so it can be `include!`d anywhere
+    pub fn to_rust(&self) -> String {
+        let mut out = String::new();
+        out.push_str("// Generated from a keymap config, edit that instead\n\n");
+        out.push_str(&format!("pub const ROWS: usize = {};\n", self.rows));
+        out.push_str(&format!("pub const COLS: usize = {};\n", self.cols));
+        out.push_str("pub const SIZE: usize = ROWS * COLS;\n");
+        out.push_str(&format!(
+            "pub const LAYERS: usize = {};\n",
+            self.layers.len()
+        ));
+        out.push_str("/// Timings in milliseconds\n");
+        out.push_str(&format!(
+            "pub const MOD_TIMEOUT: u64 = {};\n",
+            self.mod_timeout
+        ));
+        out.push_str(&format!(
+            "pub const TAP_RELEASE: u64 = {};\n",
+            self.tap_release
+        ));
+        out.push_str(&format!(
+            "pub const TAP_REPEAT: u64 = {};\n",
+            self.tap_repeat
+        ));
+        out.push_str(&format!(
+            "pub const RETRO_TAP: bool = {};\n\n",
+            self.retro_tap
+        ));
+
+        out.push_str(
+            "pub const BINDINGS: [[::rmk_mekk_elek::keystate::KeyShorthand; SIZE]; LAYERS] = [\n",
+        );
+        for layer in &self.layers {
+            out.push_str("    [\n");
+            for row in layer.chunks(self.cols.max(1)) {
+                // Configs cannot hold macros
+                let row: Vec<String> = row
+                    .iter()
+                    .map(|binding| binding_to_rust(*binding).unwrap_or_default())
+                    .collect();
+                out.push_str(&format!("        {},\n", row.join(", ")));
+            }
+            out.push_str("    ],\n");
+        }
+        out.push_str("];\n\n");
+
+        out.push_str(concat!(
+            "/// The keymap, with the timings in ticks of `ticks_per_ms` a millisecond\n",
+            "pub fn keymap<const ROLLOVER: usize>(\n",
+            "    ticks_per_ms: u64,\n",
+            ") -> ::rmk_mekk_elek::keystate::Keymap<SIZE, LAYERS, ROLLOVER> {\n",
+            "    let mut keymap = ::rmk_mekk_elek::keystate::Keymap::new(\n",
+            "        BINDINGS,\n",
+            "        MOD_TIMEOUT * ticks_per_ms,\n",
+            "        TAP_RELEASE * ticks_per_ms,\n",
+            "        TAP_REPEAT * ticks_per_ms,\n",
+            "    );\n",
+            "    keymap.modtap_config.retro_tap = RETRO_TAP;\n",
+        ));
+        for (layer, timing) in self.layer_timings.iter().enumerate() {
+            if *timing != Timing::NONE {
+                out.push_str(&format!(
+                    "    keymap.set_layer_timing({}, {});\n",
+                    layer,
+                    timing_to_rust(timing)
+                ));
+            }
+        }
+        for (index, timing) in &self.key_timings {
+            out.push_str(&format!(
+                "    keymap.set_key_timing({}, {});\n",
+                index,
+                timing_to_rust(timing)
+            ));
+        }
+        out.push_str("    keymap\n}\n");
+        out
+    }
+
+    /// The keymap in the binary format, with the timings in ticks of `ticks_per_ms` a millisecond
+    pub fn to_binary(&self, ticks_per_ms: u64) -> Result<Vec<u8>, EncodeError> {
+        let config = ModTapConfig {
+            mod_timeout: self.mod_timeout * ticks_per_ms,
+            tap_release: self.tap_release * ticks_per_ms,
+            tap_repeat: self.tap_repeat * ticks_per_ms,
+            retro_tap: self.retro_tap,
+        };
+        let size = self.layers.first().map_or(0, Vec::len);
+        let mut out = std::vec![0; binary::max_len(size, self.layers.len())];
+        let ticks = |timing: &Timing| Timing {
+            mod_timeout: timing.mod_timeout.map(|ms| ms * ticks_per_ms),
+            tap_release: timing.tap_release.map(|ms| ms * ticks_per_ms),
+            tap_repeat: timing.tap_repeat.map(|ms| ms * ticks_per_ms),
+        };
+        let layer_timings: Vec<Timing> = self.layer_timings.iter().map(ticks).collect();
+        let key_timings = self
+            .key_timings
+            .iter()
+            .map(|(index, timing)| (*index, ticks(timing)));
+        let len =
+            binary::encode_layers(&self.layers, &config, &layer_timings, key_timings, &mut out)?;
+        out.truncate(len);
+        Ok(out)
+    }
+}
+
+/// Timings in milliseconds as a Rust expression in ticks of `ticks_per_ms`
+fn timing_to_rust(timing: &Timing) -> String {
+    let ticks = |ms: Option<u64>| match ms {
+        Some(ms) => format!("Some({} * ticks_per_ms)", ms),
+        None => "None".to_string(),
+    };
+    format!(
+        "::rmk_mekk_elek::keystate::modtap::Timing {{ mod_timeout: {}, tap_release: {}, tap_repeat: {} }}",
+        ticks(timing.mod_timeout),
+        ticks(timing.tap_release),
+        ticks(timing.tap_repeat)
+    )
+}
+
+/// A binding as a Rust expression with absolute paths, `None` for keystroke macros
+pub fn binding_to_rust(binding: KeyShorthand) -> Option<String> {
+    const KEYSTATE: &str = "::rmk_mekk_elek::keystate";
+    let key = |key: Keyboard| format!("{}::Keyboard::{:?}", KEYSTATE, key);
+    let args = match binding {
+        KeyShorthand::Kb(code) => key(code),
+        KeyShorthand::La(layer) => layer.to_string(),
+        KeyShorthand::MT(mod_, tap) => format!("{}, {}", key(mod_), key(tap)),
+        KeyShorthand::MMT(mods, tap) => format!(
+            "{}::mods::Mods::from_bits({:#04x}), {}",
+            KEYSTATE,
+            mods.bits(),
+            key(tap)
+        ),
+        KeyShorthand::LT(layer, tap) => format!("{}, {}", layer, key(tap)),
+        KeyShorthand::MK(mods, code) => format!(
+            "{}::mods::Mods::from_bits({:#04x}), {}",
+            KEYSTATE,
+            mods.bits(),
+            key(code)
+        ),
+        KeyShorthand::AS(code) => key(code),
+        KeyShorthand::Mac(_) => return None,
+        KeyShorthand::Str(text) => format!("{:?}", text),
+        KeyShorthand::Uc(c) => format!("{:?}", c),
+        KeyShorthand::UcMode(mode) => format!("{}::unicode::UnicodeMode::{:?}", KEYSTATE, mode),
+        KeyShorthand::Lead => return Some(format!("{}::KeyShorthand::Lead", KEYSTATE)),
+        KeyShorthand::CapsWord => return Some(format!("{}::KeyShorthand::CapsWord", KEYSTATE)),
+        KeyShorthand::Ms(action) => format!("{}::mouse::MouseAction::{:?}", KEYSTATE, action),
+        KeyShorthand::Cc(code) => format!("{}::Consumer::{:?}", KEYSTATE, code),
+        KeyShorthand::Sys(code) => format!("{}::Desktop::{:?}", KEYSTATE, code),
+    };
+    let variant = format!("{:?}", binding);
+    let variant = variant.split('(').next().unwrap_or_default();
+    Some(format!("{}::KeyShorthand::{}({})", KEYSTATE, variant, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystate::prelude::*;
+    use std::vec;
+
+    const CONFIG: &str = r#"
+[matrix]
+rows = 2
+cols = 3
+layout = ["0,0 0,1 0,2", "1,0 1,2"]
+
+[timing]
+mod_timeout = 200
+tap_release = 100
+tap_repeat = 500
+
+[[layer]]
+name = "base"
+keys = [
+  "Escape  MT(LSFT, A)  La(fn)",
+  "Sft(RAlt(K1))        Cc(VOLU)",
+]
+
+[[layer]]
+name = "fn"
+keys = [
+  "F1 LT(base, Space) La(0)",
+  "Ms(BTN1) Sys(SystemSleep)",
+]
+tap_release = 50
+
+[[key_timing]]
+keys = "0,1 1,2"
+mod_timeout = 300
+"#;
+
+    #[test]
+    fn config() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!((config.rows, config.cols), (2, 3));
+        assert_eq!(
+            (config.mod_timeout, config.tap_release, config.tap_repeat),
+            (200, 100, 500)
+        );
+        assert_eq!(config.layer_names, ["base", "fn"]);
+        let slow = Timing {
+            mod_timeout: Some(300),
+            ..Timing::NONE
+        };
+        assert_eq!(
+            config.layer_timings,
+            [
+                Timing::NONE,
+                Timing {
+                    tap_release: Some(50),
+                    ..Timing::NONE
+                }
+            ]
+        );
+        assert_eq!(config.key_timings, [(1, slow), (5, slow)]);
+        assert_eq!(
+            parse_key("MT(LCTL|LSFT, A)", &[]),
+            Ok(MMT(Mods::LCTL | Mods::LSFT, A))
+        );
+        assert_eq!(parse_key("MT(LSFT|LSFT, A)", &[]), Ok(MT(LSFT, A)));
+        assert_eq!(
+            parse_key("MT(LCTL|A, B)", &[]),
+            Err("`A` is not a modifier".to_string())
+        );
+        assert_eq!(
+            config.layers,
+            [
+                vec![
+                    Kb(Escape),
+                    MT(LSFT, A),
+                    La(1),
+                    MK(Mods::LSFT.union(Mods::RALT), K1),
+                    Kb(___),
+                    Cc(VOLU)
+                ],
+                vec![Kb(F1), LT(0, Space), La(0), Ms(BTN1), Kb(___), Sys(SLEP)],
+            ]
+        );
+    }
+
+    #[test]
+    fn rust() {
+        let rust = Config::parse(CONFIG).unwrap().to_rust();
+        assert!(rust.contains("pub const LAYERS: usize = 2;\n"));
+        assert_eq!(
+            binding_to_rust(MMT(Mods::MEH, A)).unwrap(),
+            concat!(
+                "::rmk_mekk_elek::keystate::KeyShorthand::MMT(",
+                "::rmk_mekk_elek::keystate::mods::Mods::from_bits(0x07), ",
+                "::rmk_mekk_elek::keystate::Keyboard::A)"
+            )
+        );
+        assert!(rust.contains(concat!(
+            "    keymap.set_key_timing(5, ::rmk_mekk_elek::keystate::modtap::Timing { ",
+            "mod_timeout: Some(300 * ticks_per_ms), tap_release: None, tap_repeat: None });\n"
+        )));
+        assert!(rust.contains("    keymap.set_layer_timing(1, "));
+        assert!(!rust.contains("set_layer_timing(0, "));
+        assert!(rust.contains(concat!(
+            "::rmk_mekk_elek::keystate::KeyShorthand::MT(",
+            "::rmk_mekk_elek::keystate::Keyboard::LeftShift, ",
+            "::rmk_mekk_elek::keystate::Keyboard::A), "
+        )));
+        assert_eq!(
+            binding_to_rust(Sft(K1)).unwrap(),
+            concat!(
+                "::rmk_mekk_elek::keystate::KeyShorthand::MK(",
+                "::rmk_mekk_elek::keystate::mods::Mods::from_bits(0x02), ",
+                "::rmk_mekk_elek::keystate::Keyboard::Keyboard1)"
+            )
+        );
+        assert_eq!(
+            binding_to_rust(Ms(BTN2)).unwrap(),
+            concat!(
+                "::rmk_mekk_elek::keystate::KeyShorthand::Ms(",
+                "::rmk_mekk_elek::keystate::mouse::MouseAction::Button(2))"
+            )
+        );
+        assert_eq!(
+            binding_to_rust(Str("a\"b")).unwrap(),
+            "::rmk_mekk_elek::keystate::KeyShorthand::Str(\"a\\\"b\")"
+        );
+        assert_eq!(
+            binding_to_rust(Lead).unwrap(),
+            "::rmk_mekk_elek::keystate::KeyShorthand::Lead"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error =
+            |from: &str, to: &str| Config::parse(&CONFIG.replacen(from, to, 1)).unwrap_err();
+        assert_eq!(
+            error("Escape  ", ""),
+            ConfigError::new(15, "expected 3 keys, found 2")
+        );
+        assert_eq!(
+            error("F1", "F13x"),
+            ConfigError::new(22, "unknown key `F13x`")
+        );
+        assert_eq!(
+            error("La(fn)", "La(nav)"),
+            ConfigError::new(15, "unknown layer `nav`")
+        );
+        assert_eq!(
+            error("La(0)", "La(2)"),
+            ConfigError::new(22, "layer 2 does not exist")
+        );
+        assert_eq!(
+            error("MT(LSFT, A)", "MT(A, B)"),
+            ConfigError::new(15, "`A` is not a modifier")
+        );
+        assert_eq!(
+            error("Sft(RAlt(K1))", "Sft(La(1))"),
+            ConfigError::new(16, "only plain keys can be modified, found `Sft(La(1))`")
+        );
+        assert_eq!(
+            error("1,2\"", "1,3\""),
+            ConfigError::new(5, "`1,3` is outside the matrix")
+        );
+        assert_eq!(
+            error("1,0 1,2", "0,0 1,2"),
+            ConfigError::new(5, "`0,0` is used twice")
+        );
+        assert_eq!(
+            error("tap_repeat = 500\n", ""),
+            ConfigError::new(7, "`timing` is missing `tap_repeat`")
+        );
+        assert_eq!(
+            error("rows", "row"),
+            ConfigError::new(3, "unknown key `row` in `matrix`")
+        );
+        assert_eq!(
+            error("  \"Ms(BTN1) Sys(SystemSleep)\",\n", ""),
+            ConfigError::new(19, "expected 2 rows, found 1")
+        );
+        assert_eq!(error("[[layer]]", "[layer]").line, 12);
+        assert_eq!(
+            error("0,1 1,2", "0,1 2,2"),
+            ConfigError::new(28, "`2,2` is outside the matrix")
+        );
+        assert_eq!(
+            error("mod_timeout = 300", "mod_timeout = \"slow\""),
+            ConfigError::new(29, "`mod_timeout` must be a non-negative integer")
+        );
+        assert_eq!(
+            error("tap_repeat = 500", "tap_repeat = 500\nretro_tap = 1"),
+            ConfigError::new(11, "`retro_tap` must be `true` or `false`")
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let config = Config::parse(CONFIG).unwrap();
+        let (_, timings) = binary::decode::<6, 2>(&config.to_binary(1000).unwrap()).unwrap();
+        assert_eq!(timings.config.mod_timeout, 200_000);
+        assert_eq!(timings.layers[0], Timing::NONE);
+        assert_eq!(timings.layers[1].tap_release, Some(50_000));
+        let slow = Timing {
+            mod_timeout: Some(300_000),
+            ..Timing::NONE
+        };
+        assert_eq!(
+            timings.keys,
+            [
+                Timing::NONE,
+                slow,
+                Timing::NONE,
+                Timing::NONE,
+                Timing::NONE,
+                slow
+            ]
+        );
+    }
+
+    #[test]
+    fn retro_tap() {
+        assert!(!Config::parse(CONFIG).unwrap().retro_tap);
+        let source = CONFIG.replacen("tap_repeat = 500", "tap_repeat = 500\nretro_tap = true", 1);
+        let config = Config::parse(&source).unwrap();
+        assert!(config.retro_tap);
+        assert!(config
+            .to_rust()
+            .contains("pub const RETRO_TAP: bool = true;\n"));
+        let (_, timing) = binary::decode::<6, 2>(&config.to_binary(1).unwrap()).unwrap();
+        assert!(timing.config.retro_tap);
+    }
+}
@@ -0,0 +1,672 @@
+//! Imports QMK Configurator `keymap.json` layouts onto a board config
+//!
+//! Keycode names such as `KC_A`, `LT(1,KC_SPC)` or `MT(MOD_LSFT,KC_A)` are turned into QMK's
+//! 16-bit keycodes, as are plain numbers, and then into bindings with `keystate::qmk`, so exactly
+//! what VIA can set is supported. Anything else becomes `Kb(___)` and is listed in
+//! `Import::unsupported`. As layers do not fall through, `KC_TRNS` takes the binding of the base
+//! layer, which is what QMK falls through to while a layer is held on its own. The keys of each
+//! layer are in `LAYOUT` order, and are placed with the board's `Config::positions`.
+
+extern crate std;
+
+use std::fmt;
+use std::format;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use super::json::{self, Item, Value};
+use super::{Config, ConfigError};
+use crate::keystate::modtap::Timing;
+use crate::keystate::qmk as keycodes;
+use crate::keystate::KeyShorthand;
+use crate::keystate::Keyboard;
+
+/// Names of keycodes in the basic range, long and short, with the shifted symbol aliases
+const NAMES: &[(&str, u16)] = &[
+    ("KC_NO", 0x00),
+    ("XXXXXXX", 0x00),
+    ("KC_TRANSPARENT", 0x01),
+    ("KC_TRNS", 0x01),
+    ("_______", 0x01),
+    ("KC_ENTER", 0x28),
+    ("KC_ENT", 0x28),
+    ("KC_ESCAPE", 0x29),
+    ("KC_ESC", 0x29),
+    ("KC_BACKSPACE", 0x2A),
+    ("KC_BSPC", 0x2A),
+    ("KC_TAB", 0x2B),
+    ("KC_SPACE", 0x2C),
+    ("KC_SPC", 0x2C),
+    ("KC_MINUS", 0x2D),
+    ("KC_MINS", 0x2D),
+    ("KC_EQUAL", 0x2E),
+    ("KC_EQL", 0x2E),
+    ("KC_LEFT_BRACKET", 0x2F),
+    ("KC_LBRC", 0x2F),
+    ("KC_RIGHT_BRACKET", 0x30),
+    ("KC_RBRC", 0x30),
+    ("KC_BACKSLASH", 0x31),
+    ("KC_BSLS", 0x31),
+    ("KC_NONUS_HASH", 0x32),
+    ("KC_NUHS", 0x32),
+    ("KC_SEMICOLON", 0x33),
+    ("KC_SCLN", 0x33),
+    ("KC_QUOTE", 0x34),
+    ("KC_QUOT", 0x34),
+    ("KC_GRAVE", 0x35),
+    ("KC_GRV", 0x35),
+    ("KC_COMMA", 0x36),
+    ("KC_COMM", 0x36),
+    ("KC_DOT", 0x37),
+    ("KC_SLASH", 0x38),
+    ("KC_SLSH", 0x38),
+    ("KC_CAPS_LOCK", 0x39),
+    ("KC_CAPS", 0x39),
+    ("KC_PRINT_SCREEN", 0x46),
+    ("KC_PSCR", 0x46),
+    ("KC_SCROLL_LOCK", 0x47),
+    ("KC_SCRL", 0x47),
+    ("KC_PAUSE", 0x48),
+    ("KC_PAUS", 0x48),
+    ("KC_INSERT", 0x49),
+    ("KC_INS", 0x49),
+    ("KC_HOME", 0x4A),
+    ("KC_PAGE_UP", 0x4B),
+    ("KC_PGUP", 0x4B),
+    ("KC_DELETE", 0x4C),
+    ("KC_DEL", 0x4C),
+    ("KC_END", 0x4D),
+    ("KC_PAGE_DOWN", 0x4E),
+    ("KC_PGDN", 0x4E),
+    ("KC_RIGHT", 0x4F),
+    ("KC_RGHT", 0x4F),
+    ("KC_LEFT", 0x50),
+    ("KC_DOWN", 0x51),
+    ("KC_UP", 0x52),
+    ("KC_NUM_LOCK", 0x53),
+    ("KC_NUM", 0x53),
+    ("KC_KP_SLASH", 0x54),
+    ("KC_PSLS", 0x54),
+    ("KC_KP_ASTERISK", 0x55),
+    ("KC_PAST", 0x55),
+    ("KC_KP_MINUS", 0x56),
+    ("KC_PMNS", 0x56),
+    ("KC_KP_PLUS", 0x57),
+    ("KC_PPLS", 0x57),
+    ("KC_KP_ENTER", 0x58),
+    ("KC_PENT", 0x58),
+    ("KC_KP_DOT", 0x63),
+    ("KC_PDOT", 0x63),
+    ("KC_NONUS_BACKSLASH", 0x64),
+    ("KC_NUBS", 0x64),
+    ("KC_APPLICATION", 0x65),
+    ("KC_APP", 0x65),
+    ("KC_KB_POWER", 0x66),
+    ("KC_KP_EQUAL", 0x67),
+    ("KC_PEQL", 0x67),
+    ("KC_EXECUTE", 0x74),
+    ("KC_EXEC", 0x74),
+    ("KC_HELP", 0x75),
+    ("KC_MENU", 0x76),
+    ("KC_SELECT", 0x77),
+    ("KC_SLCT", 0x77),
+    ("KC_STOP", 0x78),
+    ("KC_AGAIN", 0x79),
+    ("KC_AGIN", 0x79),
+    ("KC_UNDO", 0x7A),
+    ("KC_CUT", 0x7B),
+    ("KC_COPY", 0x7C),
+    ("KC_PASTE", 0x7D),
+    ("KC_PSTE", 0x7D),
+    ("KC_FIND", 0x7E),
+    ("KC_KB_MUTE", 0x7F),
+    ("KC_KB_VOLUME_UP", 0x80),
+    ("KC_KB_VOLUME_DOWN", 0x81),
+    ("KC_LOCKING_CAPS_LOCK", 0x82),
+    ("KC_LCAP", 0x82),
+    ("KC_LOCKING_NUM_LOCK", 0x83),
+    ("KC_LNUM", 0x83),
+    ("KC_LOCKING_SCROLL_LOCK", 0x84),
+    ("KC_LSCR", 0x84),
+    ("KC_KP_COMMA", 0x85),
+    ("KC_PCMM", 0x85),
+    ("KC_KP_EQUAL_AS400", 0x86),
+    ("KC_ALTERNATE_ERASE", 0x99),
+    ("KC_ERAS", 0x99),
+    ("KC_SYSTEM_REQUEST", 0x9A),
+    ("KC_SYRQ", 0x9A),
+    ("KC_CANCEL", 0x9B),
+    ("KC_CNCL", 0x9B),
+    ("KC_CLEAR", 0x9C),
+    ("KC_CLR", 0x9C),
+    ("KC_PRIOR", 0x9D),
+    ("KC_PRIR", 0x9D),
+    ("KC_RETURN", 0x9E),
+    ("KC_RETN", 0x9E),
+    ("KC_SEPARATOR", 0x9F),
+    ("KC_SEPR", 0x9F),
+    ("KC_OUT", 0xA0),
+    ("KC_OPER", 0xA1),
+    ("KC_CLEAR_AGAIN", 0xA2),
+    ("KC_CLAG", 0xA2),
+    ("KC_CRSEL", 0xA3),
+    ("KC_CRSL", 0xA3),
+    ("KC_EXSEL", 0xA4),
+    ("KC_EXSL", 0xA4),
+    ("KC_SYSTEM_POWER", 0xA5),
+    ("KC_PWR", 0xA5),
+    ("KC_SYSTEM_SLEEP", 0xA6),
+    ("KC_SLEP", 0xA6),
+    ("KC_SYSTEM_WAKE", 0xA7),
+    ("KC_WAKE", 0xA7),
+    ("KC_AUDIO_MUTE", 0xA8),
+    ("KC_MUTE", 0xA8),
+    ("KC_AUDIO_VOL_UP", 0xA9),
+    ("KC_VOLU", 0xA9),
+    ("KC_AUDIO_VOL_DOWN", 0xAA),
+    ("KC_VOLD", 0xAA),
+    ("KC_MEDIA_NEXT_TRACK", 0xAB),
+    ("KC_MNXT", 0xAB),
+    ("KC_MEDIA_PREV_TRACK", 0xAC),
+    ("KC_MPRV", 0xAC),
+    ("KC_MEDIA_STOP", 0xAD),
+    ("KC_MSTP", 0xAD),
+    ("KC_MEDIA_PLAY_PAUSE", 0xAE),
+    ("KC_MPLY", 0xAE),
+    ("KC_MEDIA_SELECT", 0xAF),
+    ("KC_MSEL", 0xAF),
+    ("KC_MEDIA_EJECT", 0xB0),
+    ("KC_EJCT", 0xB0),
+    ("KC_MS_UP", 0xCD),
+    ("KC_MS_U", 0xCD),
+    ("MS_UP", 0xCD),
+    ("KC_MS_DOWN", 0xCE),
+    ("KC_MS_D", 0xCE),
+    ("MS_DOWN", 0xCE),
+    ("KC_MS_LEFT", 0xCF),
+    ("KC_MS_L", 0xCF),
+    ("MS_LEFT", 0xCF),
+    ("KC_MS_RIGHT", 0xD0),
+    ("KC_MS_R", 0xD0),
+    ("MS_RGHT", 0xD0),
+    ("KC_MS_BTN1", 0xD1),
+    ("KC_BTN1", 0xD1),
+    ("MS_BTN1", 0xD1),
+    ("KC_MS_BTN2", 0xD2),
+    ("KC_BTN2", 0xD2),
+    ("MS_BTN2", 0xD2),
+    ("KC_MS_BTN3", 0xD3),
+    ("KC_BTN3", 0xD3),
+    ("MS_BTN3", 0xD3),
+    ("KC_MS_BTN4", 0xD4),
+    ("KC_BTN4", 0xD4),
+    ("MS_BTN4", 0xD4),
+    ("KC_MS_BTN5", 0xD5),
+    ("KC_BTN5", 0xD5),
+    ("MS_BTN5", 0xD5),
+    ("KC_MS_WH_UP", 0xD9),
+    ("KC_WH_U", 0xD9),
+    ("MS_WHLU", 0xD9),
+    ("KC_MS_WH_DOWN", 0xDA),
+    ("KC_WH_D", 0xDA),
+    ("MS_WHLD", 0xDA),
+    ("KC_MS_WH_LEFT", 0xDB),
+    ("KC_WH_L", 0xDB),
+    ("MS_WHLL", 0xDB),
+    ("KC_MS_WH_RIGHT", 0xDC),
+    ("KC_WH_R", 0xDC),
+    ("MS_WHLR", 0xDC),
+    ("KC_LEFT_CTRL", 0xE0),
+    ("KC_LCTL", 0xE0),
+    ("KC_LEFT_SHIFT", 0xE1),
+    ("KC_LSFT", 0xE1),
+    ("KC_LEFT_ALT", 0xE2),
+    ("KC_LALT", 0xE2),
+    ("KC_LOPT", 0xE2),
+    ("KC_LEFT_GUI", 0xE3),
+    ("KC_LGUI", 0xE3),
+    ("KC_LCMD", 0xE3),
+    ("KC_LWIN", 0xE3),
+    ("KC_RIGHT_CTRL", 0xE4),
+    ("KC_RCTL", 0xE4),
+    ("KC_RIGHT_SHIFT", 0xE5),
+    ("KC_RSFT", 0xE5),
+    ("KC_RIGHT_ALT", 0xE6),
+    ("KC_RALT", 0xE6),
+    ("KC_ROPT", 0xE6),
+    ("KC_ALGR", 0xE6),
+    ("KC_RIGHT_GUI", 0xE7),
+    ("KC_RGUI", 0xE7),
+    ("KC_RCMD", 0xE7),
+    ("KC_RWIN", 0xE7),
+    ("KC_TILDE", 0x0235),
+    ("KC_TILD", 0x0235),
+    ("KC_EXCLAIM", 0x021E),
+    ("KC_EXLM", 0x021E),
+    ("KC_AT", 0x021F),
+    ("KC_HASH", 0x0220),
+    ("KC_DOLLAR", 0x0221),
+    ("KC_DLR", 0x0221),
+    ("KC_PERCENT", 0x0222),
+    ("KC_PERC", 0x0222),
+    ("KC_CIRCUMFLEX", 0x0223),
+    ("KC_CIRC", 0x0223),
+    ("KC_AMPERSAND", 0x0224),
+    ("KC_AMPR", 0x0224),
+    ("KC_ASTERISK", 0x0225),
+    ("KC_ASTR", 0x0225),
+    ("KC_LEFT_PAREN", 0x0226),
+    ("KC_LPRN", 0x0226),
+    ("KC_RIGHT_PAREN", 0x0227),
+    ("KC_RPRN", 0x0227),
+    ("KC_UNDERSCORE", 0x022D),
+    ("KC_UNDS", 0x022D),
+    ("KC_PLUS", 0x022E),
+    ("KC_LEFT_CURLY_BRACE", 0x022F),
+    ("KC_LCBR", 0x022F),
+    ("KC_RIGHT_CURLY_BRACE", 0x0230),
+    ("KC_RCBR", 0x0230),
+    ("KC_PIPE", 0x0231),
+    ("KC_COLON", 0x0233),
+    ("KC_COLN", 0x0233),
+    ("KC_DOUBLE_QUOTE", 0x0234),
+    ("KC_DQUO", 0x0234),
+    ("KC_DQT", 0x0234),
+    ("KC_LEFT_ANGLE_BRACKET", 0x0236),
+    ("KC_LABK", 0x0236),
+    ("KC_LT", 0x0236),
+    ("KC_RIGHT_ANGLE_BRACKET", 0x0237),
+    ("KC_RABK", 0x0237),
+    ("KC_GT", 0x0237),
+    ("KC_QUESTION", 0x0238),
+    ("KC_QUES", 0x0238),
+    ("QK_LEADER", 0x7C58),
+    ("QK_LEAD", 0x7C58),
+    ("QK_CAPS_WORD_TOGGLE", 0x7C73),
+    ("CW_TOGG", 0x7C73),
+];
+
+/// QMK's five-bit modifiers as `MOD_` names, for `MT`
+const MODS: &[(&str, u16)] = &[
+    ("MOD_LCTL", 0x01),
+    ("MOD_LSFT", 0x02),
+    ("MOD_LALT", 0x04),
+    ("MOD_LGUI", 0x08),
+    ("MOD_RCTL", 0x11),
+    ("MOD_RSFT", 0x12),
+    ("MOD_RALT", 0x14),
+    ("MOD_RGUI", 0x18),
+    ("MOD_MEH", 0x07),
+    ("MOD_HYPR", 0x0F),
+];
+
+/// Modifier functions such as `LSFT(kc)`, and their mod-tap forms with `_T`
+const MOD_FUNCTIONS: &[(&str, u16)] = &[
+    ("LCTL", 0x01),
+    ("C", 0x01),
+    ("CTL", 0x01),
+    ("LSFT", 0x02),
+    ("S", 0x02),
+    ("SFT", 0x02),
+    ("LALT", 0x04),
+    ("A", 0x04),
+    ("ALT", 0x04),
+    ("LOPT", 0x04),
+    ("LGUI", 0x08),
+    ("G", 0x08),
+    ("GUI", 0x08),
+    ("LCMD", 0x08),
+    ("LWIN", 0x08),
+    ("RCTL", 0x11),
+    ("RSFT", 0x12),
+    ("RALT", 0x14),
+    ("ROPT", 0x14),
+    ("ALGR", 0x14),
+    ("RGUI", 0x18),
+    ("RCMD", 0x18),
+    ("RWIN", 0x18),
+    ("MEH", 0x07),
+    ("HYPR", 0x0F),
+];
+
+const QK_MODS: u16 = 0x0100;
+const QK_MOD_TAP: u16 = 0x2000;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_MOMENTARY: u16 = 0x5220;
+
+fn lookup(table: &[(&str, u16)], name: &str) -> Option<u16> {
+    table
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .map(|(_, code)| *code)
+}
+
+/// Letters, digits and numbered keys, which are in order
+fn numbered(name: &str) -> Option<u16> {
+    let name = name.strip_prefix("KC_")?;
+    let number = |prefix: &str| -> Option<u16> {
+        let digits = name.strip_prefix(prefix)?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    if let [letter @ b'A'..=b'Z'] = name.as_bytes() {
+        return Some(0x04 + (letter - b'A') as u16);
+    }
+    match (
+        number(""),
+        number("F"),
+        number("KP_").or_else(|| number("P")),
+    ) {
+        (Some(0), _, _) => Some(0x27),
+        (Some(digit @ 1..=9), _, _) => Some(0x1E + digit - 1),
+        (_, Some(function @ 1..=12), _) => Some(0x3A + function - 1),
+        (_, Some(function @ 13..=24), _) => Some(0x68 + function - 13),
+        (_, _, Some(0)) => Some(0x62),
+        (_, _, Some(digit @ 1..=9)) => Some(0x59 + digit - 1),
+        _ => None,
+    }
+}
+
+fn basic(name: &str) -> Option<u16> {
+    numbered(name).or_else(|| lookup(NAMES, name))
+}
+
+fn layer(arg: &str, limit: u16) -> Option<u16> {
+    arg.trim().parse().ok().filter(|layer| *layer < limit)
+}
+
+/// QMK's 16-bit keycode for a name as QMK writes it, `None` for names this does not know
+pub fn keycode(name: &str) -> Option<u16> {
+    let name = name.trim();
+    let Some((function, args)) = name.strip_suffix(')').and_then(|name| name.split_once('('))
+    else {
+        return basic(name);
+    };
+    // Only a basic keycode can be tapped
+    let tap = |kc: &str| basic(kc.trim()).filter(|code| *code <= 0xFF);
+    match function {
+        "MO" => Some(QK_MOMENTARY | layer(args, 0x20)?),
+        "LT" => {
+            let (layer_arg, kc) = args.split_once(',')?;
+            Some(QK_LAYER_TAP | layer(layer_arg, 0x10)? << 8 | tap(kc)?)
+        }
+        "MT" => {
+            let (mods, kc) = args.split_once(',')?;
+            let mods = mods
+                .split('|')
+                .map(|name| lookup(MODS, name.trim()))
+                .try_fold(0, |all, mods| Some(all | mods?))?;
+            Some(QK_MOD_TAP | mods << 8 | tap(kc)?)
+        }
+        _ => match function.strip_suffix("_T") {
+            Some(function) => Some(QK_MOD_TAP | lookup(MOD_FUNCTIONS, function)? << 8 | tap(args)?),
+            None => {
+                let mods = lookup(MOD_FUNCTIONS, function)?;
+                let inner = keycode(args).filter(|code| *code < QK_MOD_TAP)?;
+                Some(inner | mods << 8).filter(|code| *code >= QK_MODS)
+            }
+        },
+    }
+}
+
+/// A key `import` could not convert, which became `Kb(___)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    pub layer: usize,
+    /// Position in the layer, in `LAYOUT` order
+    pub index: usize,
+    /// As written in the `keymap.json`
+    pub keycode: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "layer {} key {}: unsupported keycode `{}`",
+            self.layer, self.index, self.keycode
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// The board config with the imported layers, named by number
+    pub config: Config,
+    pub unsupported: Vec<Unsupported>,
+}
+
+/// A keycode as a name or number, and how to show it in the report
+fn key_item(item: &Item) -> Result<(Option<u16>, String), ConfigError> {
+    match &item.value {
+        Value::Str(name) => Ok((keycode(name), name.clone())),
+        Value::Number(number) => Ok((
+            (number.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(number))
+                .then_some(*number as u16),
+            number.to_string(),
+        )),
+        _ => Err(ConfigError::new(item.line, "expected a keycode")),
+    }
+}
+
+fn array<'i>(item: &'i Item, message: &str) -> Result<&'i [Item], ConfigError> {
+    match &item.value {
+        Value::Array(items) => Ok(items),
+        _ => Err(ConfigError::new(item.line, message)),
+    }
+}
+
+/// Puts the layers of a `keymap.json` on `board`, replacing its layers
+pub fn import(source: &str, board: &Config) -> Result<Import, ConfigError> {
+    let root = json::parse(source)?;
+    let layers = root
+        .get("layers")
+        .ok_or_else(|| ConfigError::new(root.line, "missing `layers`"))?;
+    let layers = array(layers, "`layers` must be an array")?;
+    if layers.is_empty() || layers.len() > u8::MAX as usize {
+        return Err(ConfigError::new(
+            root.line,
+            format!("expected 1 to 255 layers, found {}", layers.len()),
+        ));
+    }
+
+    let mut imported: Vec<Vec<KeyShorthand>> = Vec::new();
+    let mut unsupported = Vec::new();
+    for (layer, item) in layers.iter().enumerate() {
+        let keys = array(item, "each layer must be an array of keycodes")?;
+        if keys.len() != board.positions.len() {
+            return Err(ConfigError::new(
+                item.line,
+                format!(
+                    "expected {} keys, found {}",
+                    board.positions.len(),
+                    keys.len()
+                ),
+            ));
+        }
+        let mut bindings =
+            std::vec![KeyShorthand::Kb(Keyboard::NoEventIndicated); board.rows * board.cols];
+        for (index, (key, position)) in keys.iter().zip(&board.positions).enumerate() {
+            let (code, text) = key_item(key)?;
+            let binding = match code {
+                Some(keycodes::KC_TRANSPARENT) => Some(match imported.first() {
+                    Some(base) => base[*position],
+                    None => KeyShorthand::Kb(Keyboard::NoEventIndicated),
+                }),
+                code => code.and_then(keycodes::from_keycode),
+            };
+            let binding = binding.filter(|binding| match binding {
+                KeyShorthand::La(target) | KeyShorthand::LT(target, _) => {
+                    (*target as usize) < layers.len()
+                }
+                _ => true,
+            });
+            match binding {
+                Some(binding) => bindings[*position] = binding,
+                None => unsupported.push(Unsupported {
+                    layer,
+                    index,
+                    keycode: text,
+                }),
+            }
+        }
+        imported.push(bindings);
+    }
+
+    Ok(Import {
+        config: Config {
+            layer_timings: std::vec![Timing::NONE; imported.len()],
+            layer_names: (0..imported.len()).map(|layer| layer.to_string()).collect(),
+            layers: imported,
+            ..board.clone()
+        },
+        unsupported,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystate::binary;
+    use crate::keystate::mods::Mods;
+    use crate::keystate::prelude::*;
+    use std::vec;
+
+    const BOARD: &str = r#"
+[matrix]
+rows = 2
+cols = 2
+layout = ["0,1 0,0", "1,1"]
+
+[timing]
+mod_timeout = 200
+tap_release = 100
+tap_repeat = 500
+
+[[layer]]
+name = "base"
+keys = ["A B", "C"]
+"#;
+
+    const KEYMAP: &str = r#"{
+  "version": 1,
+  "keyboard": "test",
+  "keymap": "default",
+  "layout": "LAYOUT",
+  "layers": [
+    ["KC_A", "LT(1,KC_SPC)", "MT(MOD_LSFT,KC_A)"],
+    ["MO(2)", "KC_TRNS", "QK_BOOT"],
+    ["C(S(KC_X))", "MT(MOD_LCTL|MOD_LSFT,KC_B)", 4]
+  ]
+}"#;
+
+    #[test]
+    fn keycodes() {
+        let cases = [
+            ("KC_A", 0x0004),
+            ("KC_0", 0x0027),
+            ("KC_F12", 0x0045),
+            ("KC_F13", 0x0068),
+            ("KC_P7", 0x005F),
+            ("KC_KP_0", 0x0062),
+            ("XXXXXXX", 0x0000),
+            ("_______", 0x0001),
+            ("KC_EXLM", 0x021E),
+            ("LSFT(KC_1)", 0x021E),
+            ("RCTL(KC_ESC)", 0x1129),
+            ("LT(1,KC_SPC)", 0x412C),
+            ("MT(MOD_LSFT,KC_A)", 0x2204),
+            ("MT(MOD_LCTL | MOD_LALT, KC_B)", 0x2505),
+            ("LSFT_T(KC_Z)", 0x221D),
+            ("MO(2)", 0x5222),
+            ("CW_TOGG", 0x7C73),
+        ];
+        for (name, code) in cases {
+            assert_eq!(keycode(name), Some(code), "{}", name);
+        }
+        for name in [
+            "KC_F25",
+            "KC_A1",
+            "LT(16,KC_A)",
+            "LT(1,LSFT(KC_A))",
+            "MT(MOD_X,KC_A)",
+        ] {
+            assert_eq!(keycode(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn import_keymap() {
+        let board = Config::parse(BOARD).unwrap();
+        assert_eq!(board.positions, [1, 0, 3]);
+        let import = import(KEYMAP, &board).unwrap();
+        let ctrl_shift = Mods::LCTL.union(Mods::LSFT);
+        assert_eq!(
+            import.config.layers,
+            [
+                vec![LT(1, Space), Kb(A), Kb(___), MT(LSFT, A)],
+                vec![LT(1, Space), La(2), Kb(___), Kb(___)],
+                vec![MMT(ctrl_shift, B), MK(ctrl_shift, X), Kb(___), Kb(A)],
+            ]
+        );
+        assert_eq!(import.config.layer_names, ["0", "1", "2"]);
+        assert_eq!(import.config.mod_timeout, 200);
+        assert_eq!(
+            import.unsupported,
+            [Unsupported {
+                layer: 1,
+                index: 2,
+                keycode: "QK_BOOT".to_string(),
+            }]
+        );
+        assert_eq!(
+            import.unsupported[0].to_string(),
+            "layer 1 key 2: unsupported keycode `QK_BOOT`"
+        );
+
+        // Layers which do not exist are unsupported too
+        let two_layers = r#"{"layers": [["MO(2)", "KC_A", "KC_B"], ["KC_C", "KC_D", "KC_E"]]}"#;
+        let import = super::import(two_layers, &board).unwrap();
+        assert_eq!(import.config.layers.len(), 2);
+        assert_eq!(import.unsupported[0].keycode, "MO(2)");
+
+        let rust = super::import(KEYMAP, &board).unwrap().config.to_rust();
+        assert!(rust.contains("pub const LAYERS: usize = 3;\n"));
+        assert!(rust.contains("::rmk_mekk_elek::keystate::KeyShorthand::LT(1, "));
+    }
+
+    #[test]
+    fn binary() {
+        let board = Config::parse(BOARD).unwrap();
+        let config = import(KEYMAP, &board).unwrap().config;
+        let data = config.to_binary(1000).unwrap();
+        let (bindings, timing) = binary::decode::<4, 3>(&data).unwrap();
+        assert_eq!(bindings[0], [LT(1, Space), Kb(A), Kb(___), MT(LSFT, A)]);
+        assert_eq!(timing.config.mod_timeout, 200_000);
+    }
+
+    #[test]
+    fn errors() {
+        let board = Config::parse(BOARD).unwrap();
+        let error =
+            |from: &str, to: &str| import(&KEYMAP.replacen(from, to, 1), &board).unwrap_err();
+        assert_eq!(
+            error(", \"QK_BOOT\"", ""),
+            ConfigError::new(8, "expected 3 keys, found 2")
+        );
+        assert_eq!(
+            error("\"QK_BOOT\"", "null"),
+            ConfigError::new(8, "expected a keycode")
+        );
+        assert_eq!(
+            error("\"layers\"", "\"layer\""),
+            ConfigError::new(1, "missing `layers`")
+        );
+        assert_eq!(
+            error("\"KC_TRNS\",", "\"KC_TRNS\"\n"),
+            ConfigError::new(9, "expected `,` or `]`")
+        );
+    }
+}
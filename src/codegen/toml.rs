@@ -0,0 +1,345 @@
+//! The part of TOML keymap configs use: tables, arrays of tables, and integer, boolean, string and
+//! array values, each remembering its line for errors
+
+extern crate std;
+
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+use super::ConfigError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    Array(Vec<Item>),
+}
+
+/// A value with the line it starts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub line: usize,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: Value,
+}
+
+/// `[name]`, or one `[[name]]` of an array of tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub line: usize,
+    pub name: String,
+    pub array: bool,
+    pub entries: Vec<Entry>,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    at: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.at..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ConfigError> {
+        Err(ConfigError::new(self.line, message))
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ConfigError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("expected `{}`, found `{}`", expected, c)),
+            None => self.error(format!("expected `{}`, found the end", expected)),
+        }
+    }
+
+    /// Spaces and a comment, up to the end of the line
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.bump();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Spaces, comments and line ends
+    fn skip_lines(&mut self) {
+        loop {
+            self.skip_space();
+            if self.peek() != Some('\n') {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ConfigError> {
+        self.skip_space();
+        match self.bump() {
+            None | Some('\n') => Ok(()),
+            Some(c) => self.error(format!("expected the end of the line, found `{}`", c)),
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<String, ConfigError> {
+        let mut key = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        {
+            key.push(c);
+            self.bump();
+        }
+        if key.is_empty() {
+            return self.error("expected a key");
+        }
+        Ok(key)
+    }
+
+    fn table(&mut self) -> Result<Table, ConfigError> {
+        let line = self.line;
+        self.expect('[')?;
+        let array = self.peek() == Some('[');
+        if array {
+            self.bump();
+        }
+        self.skip_space();
+        let name = self.bare_key()?;
+        self.skip_space();
+        self.expect(']')?;
+        if array {
+            self.expect(']')?;
+        }
+        self.end_of_line()?;
+        Ok(Table {
+            line,
+            name,
+            array,
+            entries: Vec::new(),
+        })
+    }
+
+    fn entry(&mut self) -> Result<Entry, ConfigError> {
+        let line = self.line;
+        let key = self.bare_key()?;
+        self.skip_space();
+        self.expect('=')?;
+        self.skip_space();
+        let value = self.value()?;
+        self.end_of_line()?;
+        Ok(Entry { line, key, value })
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => self.array().map(Value::Array),
+            Some(c) if c == '-' || c == '+' || c.is_ascii_digit() => self.int().map(Value::Int),
+            Some('t' | 'f') => self.boolean().map(Value::Bool),
+            Some(c) => self.error(format!("expected a value, found `{}`", c)),
+            None => self.error("expected a value, found the end"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ConfigError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek().filter(|c| *c != '\n') else {
+                return self.error("unterminated string");
+            };
+            self.bump();
+            match c {
+                '"' => return Ok(string),
+                '\\' => match self.bump() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    _ => return self.error("unsupported escape in string"),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn int(&mut self) -> Result<i64, ConfigError> {
+        let mut digits = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '_'))
+        {
+            if c != '_' {
+                digits.push(c);
+            }
+            self.bump();
+        }
+        match digits.parse() {
+            Ok(int) => Ok(int),
+            Err(_) => self.error(format!("invalid integer `{}`", digits)),
+        }
+    }
+
+    fn boolean(&mut self) -> Result<bool, ConfigError> {
+        match self.bare_key()?.as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            word => self.error(format!("expected a value, found `{}`", word)),
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Item>, ConfigError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_lines();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(items);
+            }
+            let line = self.line;
+            let value = self.value()?;
+            items.push(Item { line, value });
+            self.skip_lines();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => (),
+                _ => return self.error("expected `,` or `]` in array"),
+            }
+        }
+    }
+}
+
+/// The tables in order, entries before the first table are an error
+pub fn parse(source: &str) -> Result<Vec<Table>, ConfigError> {
+    let mut parser = Parser {
+        source,
+        at: 0,
+        line: 1,
+    };
+    let mut tables: Vec<Table> = Vec::new();
+    loop {
+        parser.skip_lines();
+        match parser.peek() {
+            None => return Ok(tables),
+            Some('[') => tables.push(parser.table()?),
+            Some(_) => {
+                let entry = parser.entry()?;
+                let Some(table) = tables.last_mut() else {
+                    return Err(ConfigError::new(entry.line, "expected a table first"));
+                };
+                if table.entries.iter().any(|other| other.key == entry.key) {
+                    return Err(ConfigError::new(
+                        entry.line,
+                        format!("duplicate key `{}`", entry.key),
+                    ));
+                }
+                table.entries.push(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec;
+
+    #[test]
+    fn tables() {
+        let source =
+            "# Comment\n[a]\nx = 1 # Comment\ny = \"s\\\"\"\nw = true\n\n[[b]]\nz = [\n  \"p\",\n  -2,\n]\n";
+        let tables = parse(source).unwrap();
+        assert_eq!(
+            tables,
+            vec![
+                Table {
+                    line: 2,
+                    name: "a".to_string(),
+                    array: false,
+                    entries: vec![
+                        Entry {
+                            line: 3,
+                            key: "x".to_string(),
+                            value: Value::Int(1),
+                        },
+                        Entry {
+                            line: 4,
+                            key: "y".to_string(),
+                            value: Value::Str("s\"".to_string()),
+                        },
+                        Entry {
+                            line: 5,
+                            key: "w".to_string(),
+                            value: Value::Bool(true),
+                        },
+                    ],
+                },
+                Table {
+                    line: 7,
+                    name: "b".to_string(),
+                    array: true,
+                    entries: vec![Entry {
+                        line: 8,
+                        key: "z".to_string(),
+                        value: Value::Array(vec![
+                            Item {
+                                line: 9,
+                                value: Value::Str("p".to_string()),
+                            },
+                            Item {
+                                line: 10,
+                                value: Value::Int(-2),
+                            },
+                        ]),
+                    }],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let line = |source| parse(source).unwrap_err().line;
+        assert_eq!(line("x = 1\n"), 1);
+        assert_eq!(line("[a]\nx = 1\nx = 2\n"), 3);
+        assert_eq!(line("[a]\n\nx = \"open\n"), 3);
+        assert_eq!(line("[a]\nx = [1,\n2\n3]\n"), 4);
+        assert_eq!(line("[a\n"), 1);
+        assert_eq!(line("[a]\nx = 1 2\n"), 2);
+        assert_eq!(line("[a]\nx = yes\n"), 2);
+    }
+}
@@ -0,0 +1,104 @@
+//! Keys which do something to the keymap itself when pressed, rather than sending a key
+
+use super::unicode::UnicodeMode;
+use super::KeyState;
+use super::Keyish;
+use super::Shared;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Selects the host's Unicode input method
+    UnicodeMode(UnicodeMode),
+    /// Starts a leader sequence
+    Leader,
+    /// Toggles Caps Word
+    CapsWord,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unpressed {
+    action: Action,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pressed {
+    action: Action,
+}
+
+impl KeyState<Unpressed> {
+    fn press(&self) -> KeyState<Pressed> {
+        KeyState {
+            state: Pressed {
+                action: self.state.action,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl KeyState<Pressed> {
+    fn release(&self) -> KeyState<Unpressed> {
+        KeyState {
+            state: Unpressed {
+                action: self.state.action,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ActionState {
+    Unpressed(KeyState<Unpressed>),
+    Pressed(KeyState<Pressed>),
+}
+
+impl Keyish for ActionState {
+    fn is_finished(&self) -> bool {
+        matches!(self, ActionState::Unpressed(_))
+    }
+}
+
+impl ActionState {
+    pub fn new(action: Action) -> Self {
+        Self::Unpressed(KeyState {
+            state: Unpressed { action },
+            shared: Shared,
+        })
+    }
+
+    /// Returns the action to perform, once per press
+    pub fn action_transition(&mut self, pressed: bool) -> Option<Action> {
+        match &self {
+            Self::Unpressed(state) if pressed => {
+                let action = state.state.action;
+                *self = Self::Pressed(state.press());
+                Some(action)
+            }
+            Self::Pressed(state) if !pressed => {
+                *self = Self::Unpressed(state.release());
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn action_once_per_press() {
+        let mut state = ActionState::new(Action::CapsWord);
+        assert!(state.is_finished());
+        assert_eq!(state.action_transition(false), None);
+        assert_eq!(state.action_transition(true), Some(Action::CapsWord));
+        assert!(!state.is_finished());
+        assert_eq!(state.action_transition(true), None);
+        assert_eq!(state.action_transition(false), None);
+        assert!(state.is_finished());
+        assert_eq!(state.action_transition(true), Some(Action::CapsWord));
+    }
+}
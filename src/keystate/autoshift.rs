@@ -0,0 +1,300 @@
+use super::macros::Stroke;
+use super::mods::Mods;
+use super::Duration;
+use super::Instant;
+use super::KeyState;
+use super::Keyboard;
+use super::Keyish;
+use super::Shared;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AutoShiftConfig {
+    /// Time a key has to be held to be sent shifted
+    pub timeout: Duration,
+    /// Auto-shift all plain keys which type a character, not just `AS` keys
+    pub global: bool,
+}
+
+/// Keys typing a character on most layouts: letters, digits and punctuation
+pub fn is_shiftable(key: Keyboard) -> bool {
+    (Keyboard::A..=Keyboard::Keyboard0).contains(&key)
+        || (Keyboard::Minus..=Keyboard::ForwardSlash).contains(&key)
+        || key == Keyboard::NonUSBackslash
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unpressed {
+    key: Keyboard,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Wait {
+    key: Keyboard,
+    shift_timeout: Instant,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Tap {
+    key: Keyboard,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shifted {
+    key: Keyboard,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Interrupted {
+    key: Keyboard,
+}
+
+impl KeyState<Unpressed> {
+    fn start(&self, shift_timeout: Instant) -> KeyState<Wait> {
+        KeyState {
+            state: Wait {
+                key: self.state.key,
+                shift_timeout,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl KeyState<Wait> {
+    fn tap(&self) -> KeyState<Tap> {
+        KeyState {
+            state: Tap {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+    fn shift(&self) -> KeyState<Shifted> {
+        KeyState {
+            state: Shifted {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+    fn interrupt(&self) -> KeyState<Interrupted> {
+        KeyState {
+            state: Interrupted {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl KeyState<Tap> {
+    fn new_tap(&self, shift_timeout: Instant) -> KeyState<Wait> {
+        KeyState {
+            state: Wait {
+                key: self.state.key,
+                shift_timeout,
+            },
+            shared: self.shared,
+        }
+    }
+    fn release(&self) -> KeyState<Unpressed> {
+        KeyState {
+            state: Unpressed {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl KeyState<Shifted> {
+    fn release(&self) -> KeyState<Unpressed> {
+        KeyState {
+            state: Unpressed {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl KeyState<Interrupted> {
+    fn release(&self) -> KeyState<Unpressed> {
+        KeyState {
+            state: Unpressed {
+                key: self.state.key,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+/// Sends nothing until released, another key is pressed or the timeout passes, then the key or
+/// the shifted key
+#[derive(Debug, PartialEq, Eq)]
+pub enum AutoShiftState {
+    Unpressed(KeyState<Unpressed>),
+    Wait(KeyState<Wait>),
+    /// Released before the timeout, sent for one report
+    Tap(KeyState<Tap>),
+    Shifted(KeyState<Shifted>),
+    /// Another key was pressed while waiting, so sent unshifted until released
+    Interrupted(KeyState<Interrupted>),
+}
+
+impl Keyish for AutoShiftState {
+    fn is_finished(&self) -> bool {
+        matches!(self, AutoShiftState::Unpressed(_))
+    }
+}
+
+impl AutoShiftState {
+    pub fn new(key: Keyboard) -> Self {
+        Self::Unpressed(KeyState {
+            state: Unpressed { key },
+            shared: Shared,
+        })
+    }
+
+    pub fn key(&self) -> Keyboard {
+        match self {
+            Self::Unpressed(state) => state.state.key,
+            Self::Wait(state) => state.state.key,
+            Self::Tap(state) => state.state.key,
+            Self::Shifted(state) => state.state.key,
+            Self::Interrupted(state) => state.state.key,
+        }
+    }
+
+    /// Another key was pressed, so a waiting key is sent unshifted right away, before it.
+    /// Returns whether it was waiting.
+    pub fn interrupt(&mut self) -> bool {
+        match &self {
+            Self::Wait(state) => {
+                *self = Self::Interrupted(state.interrupt());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn autoshift_transition(&mut self, pressed: bool, now: Instant, config: &AutoShiftConfig) {
+        match &self {
+            Self::Unpressed(state) if pressed => {
+                *self = Self::Wait(state.start(now + config.timeout))
+            }
+            Self::Unpressed(_state) => (),
+
+            Self::Wait(state) if pressed && state.state.shift_timeout <= now => {
+                *self = Self::Shifted(state.shift())
+            }
+            Self::Wait(state) if !pressed => *self = Self::Tap(state.tap()),
+            Self::Wait(_state) => (),
+
+            Self::Tap(state) if pressed => *self = Self::Wait(state.new_tap(now + config.timeout)),
+            Self::Tap(state) => *self = Self::Unpressed(state.release()),
+
+            Self::Shifted(state) if !pressed => *self = Self::Unpressed(state.release()),
+            Self::Shifted(_state) => (),
+
+            Self::Interrupted(state) if !pressed => *self = Self::Unpressed(state.release()),
+            Self::Interrupted(_state) => (),
+        }
+    }
+
+    pub fn get_stroke(&self) -> Option<Stroke> {
+        match self {
+            Self::Unpressed(_) => None,
+            Self::Wait(_) => None,
+            Self::Tap(state) => Some(Stroke::key(state.state.key)),
+            Self::Shifted(state) => Some(Stroke::new(Mods::LSFT, state.state.key)),
+            Self::Interrupted(state) => Some(Stroke::key(state.state.key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    const CONFIG: AutoShiftConfig = AutoShiftConfig {
+        timeout: 3,
+        global: false,
+    };
+
+    #[test]
+    fn tap() {
+        let mut state = AutoShiftState::new(Keyboard::A);
+        assert_eq!(state.get_stroke(), None);
+        state.autoshift_transition(true, 0, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        assert!(!state.is_finished());
+        state.autoshift_transition(true, 2, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        state.autoshift_transition(false, 3, &CONFIG);
+        assert_eq!(state.get_stroke(), Some(Stroke::key(Keyboard::A)));
+        assert!(!state.is_finished());
+        state.autoshift_transition(false, 4, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn hold() {
+        let mut state = AutoShiftState::new(Keyboard::A);
+        state.autoshift_transition(true, 0, &CONFIG);
+        state.autoshift_transition(true, 2, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        state.autoshift_transition(true, 3, &CONFIG);
+        assert_eq!(
+            state.get_stroke(),
+            Some(Stroke::new(Mods::LSFT, Keyboard::A))
+        );
+        state.autoshift_transition(true, 10, &CONFIG);
+        assert_eq!(
+            state.get_stroke(),
+            Some(Stroke::new(Mods::LSFT, Keyboard::A))
+        );
+        state.autoshift_transition(false, 11, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn quick_retap() {
+        let mut state = AutoShiftState::new(Keyboard::A);
+        state.autoshift_transition(true, 0, &CONFIG);
+        state.autoshift_transition(false, 1, &CONFIG);
+        assert_eq!(state.get_stroke(), Some(Stroke::key(Keyboard::A)));
+        state.autoshift_transition(true, 2, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        state.autoshift_transition(true, 5, &CONFIG);
+        assert_eq!(
+            state.get_stroke(),
+            Some(Stroke::new(Mods::LSFT, Keyboard::A))
+        );
+    }
+
+    #[test]
+    fn interrupted() {
+        let mut state = AutoShiftState::new(Keyboard::A);
+        assert!(!state.interrupt());
+        state.autoshift_transition(true, 0, &CONFIG);
+        assert!(state.interrupt());
+        assert_eq!(state.get_stroke(), Some(Stroke::key(Keyboard::A)));
+        state.autoshift_transition(true, 5, &CONFIG);
+        assert_eq!(state.get_stroke(), Some(Stroke::key(Keyboard::A)));
+        assert!(!state.interrupt());
+        state.autoshift_transition(false, 6, &CONFIG);
+        assert_eq!(state.get_stroke(), None);
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn shiftable() {
+        assert!(is_shiftable(Keyboard::A));
+        assert!(is_shiftable(Keyboard::Keyboard0));
+        assert!(is_shiftable(Keyboard::Comma));
+        assert!(!is_shiftable(Keyboard::Space));
+        assert!(!is_shiftable(Keyboard::LeftShift));
+        assert!(!is_shiftable(Keyboard::F1));
+    }
+}
@@ -0,0 +1,717 @@
+//! Versioned binary encoding of a keymap, for storing in flash, transferring over USB or
+//! producing with host tools
+//!
+//! All numbers are little-endian. The header is the magic `RMK`, the format version, the layer
+//! count (`u8`), the key count (`u16`), the mod-tap timings (`u64` each) and a flags byte, bit 0
+//! set for `retro_tap`. It is followed by every layer's bindings in key order, each a tag byte and
+//! its payload, then every layer's timings, the number of keys with their own timings (`u16`) and
+//! each of those keys' index (`u16`) and timings, and finally a CRC-32 (IEEE) of everything before
+//! it. Timings are a byte with a bit set for each of `mod_timeout`, `tap_release` and
+//! `tap_repeat` that is set, followed by those (`u64` each).
+
+use super::mods::Mods;
+use super::modtap::{ModTapConfig, Timing};
+use super::mouse::{MouseAction, BUTTONS};
+use super::unicode::UnicodeMode;
+use super::Consumer;
+use super::Desktop;
+use super::KeyShorthand;
+use super::Keyboard;
+use super::Layer;
+
+pub const MAGIC: [u8; 3] = *b"RMK";
+/// Bumped whenever the header or a tag changes, so older firmware reports an unsupported version
+/// rather than an invalid binding
+pub const VERSION: u8 = 4;
+
+const HEADER_LEN: usize = MAGIC.len() + 1 + 1 + 2 + 3 * 8 + 1;
+const CHECKSUM_LEN: usize = 4;
+const FLAG_RETRO_TAP: u8 = 1 << 0;
+/// Tag and the largest payload, a code point
+const MAX_BINDING_LEN: usize = 1 + 4;
+/// Mask and every timing
+const MAX_TIMING_LEN: usize = 1 + 3 * 8;
+
+const TAG_KB: u8 = 0;
+const TAG_LA: u8 = 1;
+const TAG_MT: u8 = 2;
+const TAG_MK: u8 = 3;
+const TAG_AS: u8 = 4;
+const TAG_UC: u8 = 5;
+const TAG_UC_MODE: u8 = 6;
+const TAG_LEAD: u8 = 7;
+const TAG_CAPS_WORD: u8 = 8;
+const TAG_MS: u8 = 9;
+const TAG_MS_BUTTON: u8 = 10;
+const TAG_CC: u8 = 11;
+const TAG_SYS: u8 = 12;
+const TAG_LT: u8 = 13;
+const TAG_MMT: u8 = 14;
+
+/// Buffer size which fits any keymap with these dimensions
+pub const fn max_len(size: usize, layers: usize) -> usize {
+    HEADER_LEN
+        + size * layers * MAX_BINDING_LEN
+        + layers * MAX_TIMING_LEN
+        + 2
+        + size * (2 + MAX_TIMING_LEN)
+        + CHECKSUM_LEN
+}
+
+/// The timings of a keymap besides its bindings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings<const SIZE: usize, const LAYERS: usize> {
+    pub config: ModTapConfig,
+    /// Each layer's, over `config`
+    pub layers: [Timing; LAYERS],
+    /// Each key's on every layer, over its layer's
+    pub keys: [Timing; SIZE],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    BufferTooSmall,
+    /// More layers or keys than the header can hold
+    TooLarge,
+    /// Layers given to `encode_layers` with different numbers of keys, or not one timing each
+    UnevenLayers,
+    /// Timings given to `encode_layers` for a key index outside the layers
+    KeyOutOfRange(usize),
+    /// Macros and strings refer to data outside the keymap, so cannot be encoded, nor can mouse
+    /// buttons the report has no bit for
+    Unsupported {
+        layer: Layer,
+        index: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The layer or key count is not the keymap's
+    SizeMismatch,
+    Checksum,
+    /// Flags this version does not define
+    UnknownFlags(u8),
+    InvalidBinding {
+        layer: Layer,
+        index: usize,
+    },
+    /// A timing mask with unknown bits, or key timings for an index out of range
+    InvalidTiming,
+    TrailingData,
+    /// The settings stored before the keymap by `Keymap::persist` are out of range
+    InvalidSettings,
+    /// Stored by `Keymap::persist` from another compiled keymap
+    OtherKeymap,
+}
+
+/// CRC-32 (IEEE), bitwise as keymaps are small
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Continues a CRC-32 over more data, starting from `!0` and inverting the result
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
+        let end = self.len + bytes.len();
+        self.out
+            .get_mut(self.len..end)
+            .ok_or(EncodeError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        if self.data.len() < N {
+            return Err(DecodeError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into().unwrap_or([0; N]))
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+}
+
+fn mouse_action(action: MouseAction) -> (u8, u8) {
+    match action {
+        MouseAction::Up => (TAG_MS, 0),
+        MouseAction::Down => (TAG_MS, 1),
+        MouseAction::Left => (TAG_MS, 2),
+        MouseAction::Right => (TAG_MS, 3),
+        MouseAction::WheelUp => (TAG_MS, 4),
+        MouseAction::WheelDown => (TAG_MS, 5),
+        MouseAction::WheelLeft => (TAG_MS, 6),
+        MouseAction::WheelRight => (TAG_MS, 7),
+        MouseAction::Button(button) => (TAG_MS_BUTTON, button),
+    }
+}
+
+fn encode_binding(writer: &mut Writer, binding: KeyShorthand) -> Result<bool, EncodeError> {
+    match binding {
+        KeyShorthand::Kb(key) => writer.put(&[TAG_KB, key.into()])?,
+        KeyShorthand::La(layer) => writer.put(&[TAG_LA, layer])?,
+        KeyShorthand::MT(mod_, tap) => writer.put(&[TAG_MT, mod_.into(), tap.into()])?,
+        KeyShorthand::MMT(mods, tap) => writer.put(&[TAG_MMT, mods.bits(), tap.into()])?,
+        KeyShorthand::LT(layer, tap) => writer.put(&[TAG_LT, layer, tap.into()])?,
+        KeyShorthand::MK(mods, key) => writer.put(&[TAG_MK, mods.bits(), key.into()])?,
+        KeyShorthand::AS(key) => writer.put(&[TAG_AS, key.into()])?,
+        KeyShorthand::Uc(c) => {
+            writer.put(&[TAG_UC])?;
+            writer.put(&(c as u32).to_le_bytes())?
+        }
+        KeyShorthand::UcMode(mode) => {
+            let mode = match mode {
+                UnicodeMode::Linux => 0,
+                UnicodeMode::MacOs => 1,
+                UnicodeMode::WinCompose => 2,
+            };
+            writer.put(&[TAG_UC_MODE, mode])?
+        }
+        KeyShorthand::Lead => writer.put(&[TAG_LEAD])?,
+        KeyShorthand::CapsWord => writer.put(&[TAG_CAPS_WORD])?,
+        KeyShorthand::Ms(MouseAction::Button(button)) if !(1..=BUTTONS).contains(&button) => {
+            return Ok(false)
+        }
+        KeyShorthand::Ms(action) => {
+            let (tag, value) = mouse_action(action);
+            writer.put(&[tag, value])?
+        }
+        KeyShorthand::Cc(key) => {
+            writer.put(&[TAG_CC])?;
+            writer.put(&u16::from(key).to_le_bytes())?
+        }
+        KeyShorthand::Sys(key) => writer.put(&[TAG_SYS, key.into()])?,
+        KeyShorthand::Mac(_) | KeyShorthand::Str(_) => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn encode_timing(writer: &mut Writer, timing: &Timing) -> Result<(), EncodeError> {
+    let timings = [timing.mod_timeout, timing.tap_release, timing.tap_repeat];
+    let mask = (0..timings.len())
+        .filter(|bit| timings[*bit].is_some())
+        .fold(0, |mask, bit| mask | 1 << bit);
+    writer.put(&[mask])?;
+    for timing in timings.into_iter().flatten() {
+        writer.put(&timing.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn decode_timing(reader: &mut Reader) -> Result<Timing, DecodeError> {
+    let mask = reader.u8()?;
+    if mask & !0b111 != 0 {
+        return Err(DecodeError::InvalidTiming);
+    }
+    let mut timings = [None; 3];
+    for (bit, timing) in timings.iter_mut().enumerate() {
+        if mask & 1 << bit != 0 {
+            *timing = Some(u64::from_le_bytes(reader.take()?));
+        }
+    }
+    let [mod_timeout, tap_release, tap_repeat] = timings;
+    Ok(Timing {
+        mod_timeout,
+        tap_release,
+        tap_repeat,
+    })
+}
+
+/// Only codes which the usage page defines, rather than its default for unknown codes
+fn keyboard(code: u8) -> Option<Keyboard> {
+    let key = Keyboard::from(code);
+    (u8::from(key) == code).then_some(key)
+}
+
+fn decode_binding<const LAYERS: usize>(
+    reader: &mut Reader,
+) -> Result<Option<KeyShorthand>, DecodeError> {
+    let binding = match reader.u8()? {
+        TAG_KB => keyboard(reader.u8()?).map(KeyShorthand::Kb),
+        TAG_LA => {
+            let layer = reader.u8()?;
+            ((layer as usize) < LAYERS).then_some(KeyShorthand::La(layer))
+        }
+        TAG_MT => {
+            let [mod_, tap] = reader.take()?;
+            keyboard(mod_)
+                .zip(keyboard(tap))
+                .map(|(mod_, tap)| KeyShorthand::MT(mod_, tap))
+        }
+        TAG_MMT => {
+            let [mods, tap] = reader.take()?;
+            keyboard(tap).map(|tap| KeyShorthand::MMT(Mods::from_bits(mods), tap))
+        }
+        TAG_LT => {
+            let [layer, tap] = reader.take()?;
+            keyboard(tap)
+                .filter(|_| (layer as usize) < LAYERS)
+                .map(|tap| KeyShorthand::LT(layer, tap))
+        }
+        TAG_MK => {
+            let [mods, key] = reader.take()?;
+            keyboard(key).map(|key| KeyShorthand::MK(Mods::from_bits(mods), key))
+        }
+        TAG_AS => keyboard(reader.u8()?).map(KeyShorthand::AS),
+        TAG_UC => char::from_u32(u32::from_le_bytes(reader.take()?)).map(KeyShorthand::Uc),
+        TAG_UC_MODE => match reader.u8()? {
+            0 => Some(KeyShorthand::UcMode(UnicodeMode::Linux)),
+            1 => Some(KeyShorthand::UcMode(UnicodeMode::MacOs)),
+            2 => Some(KeyShorthand::UcMode(UnicodeMode::WinCompose)),
+            _ => None,
+        },
+        TAG_LEAD => Some(KeyShorthand::Lead),
+        TAG_CAPS_WORD => Some(KeyShorthand::CapsWord),
+        TAG_MS => match reader.u8()? {
+            0 => Some(MouseAction::Up),
+            1 => Some(MouseAction::Down),
+            2 => Some(MouseAction::Left),
+            3 => Some(MouseAction::Right),
+            4 => Some(MouseAction::WheelUp),
+            5 => Some(MouseAction::WheelDown),
+            6 => Some(MouseAction::WheelLeft),
+            7 => Some(MouseAction::WheelRight),
+            _ => None,
+        }
+        .map(KeyShorthand::Ms),
+        TAG_MS_BUTTON => {
+            let button = reader.u8()?;
+            (1..=BUTTONS)
+                .contains(&button)
+                .then_some(KeyShorthand::Ms(MouseAction::Button(button)))
+        }
+        TAG_CC => {
+            let code = u16::from_le_bytes(reader.take()?);
+            let key = Consumer::from(code);
+            (u16::from(key) == code).then_some(KeyShorthand::Cc(key))
+        }
+        TAG_SYS => {
+            let code = reader.u8()?;
+            let key = Desktop::from(code);
+            (u8::from(key) == code).then_some(KeyShorthand::Sys(key))
+        }
+        _ => None,
+    };
+    Ok(binding)
+}
+
+/// Encodes into `out`, returning the encoded length
+pub fn encode<const SIZE: usize, const LAYERS: usize>(
+    bindings: &[[KeyShorthand; SIZE]; LAYERS],
+    timings: &Timings<SIZE, LAYERS>,
+    out: &mut [u8],
+) -> Result<usize, EncodeError> {
+    let keys = timings.keys.iter().copied().enumerate();
+    encode_layers(bindings, &timings.config, &timings.layers, keys, out)
+}
+
+/// Like `encode`, for layers whose size is only known at runtime, as in host tools. Key timings
+/// are by key index, and only those which set a timing are stored.
+pub fn encode_layers<L: AsRef<[KeyShorthand]>>(
+    bindings: &[L],
+    config: &ModTapConfig,
+    layer_timings: &[Timing],
+    key_timings: impl IntoIterator<Item = (usize, Timing)>,
+    out: &mut [u8],
+) -> Result<usize, EncodeError> {
+    let size = bindings.first().map_or(0, |keys| keys.as_ref().len());
+    if bindings.iter().any(|keys| keys.as_ref().len() != size)
+        || layer_timings.len() != bindings.len()
+    {
+        return Err(EncodeError::UnevenLayers);
+    }
+    let layers = u8::try_from(bindings.len()).map_err(|_| EncodeError::TooLarge)?;
+    let size = u16::try_from(size).map_err(|_| EncodeError::TooLarge)?;
+    let mut writer = Writer { out, len: 0 };
+    writer.put(&MAGIC)?;
+    writer.put(&[VERSION, layers])?;
+    writer.put(&size.to_le_bytes())?;
+    writer.put(&config.mod_timeout.to_le_bytes())?;
+    writer.put(&config.tap_release.to_le_bytes())?;
+    writer.put(&config.tap_repeat.to_le_bytes())?;
+    let flags = if config.retro_tap { FLAG_RETRO_TAP } else { 0 };
+    writer.put(&[flags])?;
+    for (layer, keys) in bindings.iter().enumerate() {
+        for (index, binding) in keys.as_ref().iter().enumerate() {
+            if !encode_binding(&mut writer, *binding)? {
+                let layer = layer as Layer;
+                return Err(EncodeError::Unsupported { layer, index });
+            }
+        }
+    }
+    for timing in layer_timings {
+        encode_timing(&mut writer, timing)?;
+    }
+    // The count goes before the keys, so is filled in after them
+    let count_at = writer.len;
+    writer.put(&[0, 0])?;
+    let mut count: u16 = 0;
+    for (index, timing) in key_timings {
+        if index >= size as usize {
+            return Err(EncodeError::KeyOutOfRange(index));
+        }
+        if timing != Timing::NONE {
+            writer.put(&(index as u16).to_le_bytes())?;
+            encode_timing(&mut writer, &timing)?;
+            count = count.checked_add(1).ok_or(EncodeError::TooLarge)?;
+        }
+    }
+    writer.out[count_at..count_at + 2].copy_from_slice(&count.to_le_bytes());
+    let checksum = crc32(&writer.out[..writer.len]);
+    writer.put(&checksum.to_le_bytes())?;
+    Ok(writer.len)
+}
+
+/// Decodes a whole keymap, checking it has exactly these dimensions
+pub fn decode<const SIZE: usize, const LAYERS: usize>(
+    data: &[u8],
+) -> Result<([[KeyShorthand; SIZE]; LAYERS], Timings<SIZE, LAYERS>), DecodeError> {
+    if data.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(DecodeError::Truncated);
+    }
+    let (body, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+    let mut reader = Reader { data: body };
+    if reader.take::<3>()? != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    if crc32(body).to_le_bytes() != checksum {
+        return Err(DecodeError::Checksum);
+    }
+    let layers = reader.u8()?;
+    let size = u16::from_le_bytes(reader.take()?);
+    if layers as usize != LAYERS || size as usize != SIZE {
+        return Err(DecodeError::SizeMismatch);
+    }
+    let mod_timeout = u64::from_le_bytes(reader.take()?);
+    let tap_release = u64::from_le_bytes(reader.take()?);
+    let tap_repeat = u64::from_le_bytes(reader.take()?);
+    let flags = reader.u8()?;
+    if flags & !FLAG_RETRO_TAP != 0 {
+        return Err(DecodeError::UnknownFlags(flags));
+    }
+    let config = ModTapConfig {
+        mod_timeout,
+        tap_release,
+        tap_repeat,
+        retro_tap: flags & FLAG_RETRO_TAP != 0,
+    };
+    let mut bindings = [[KeyShorthand::Kb(Keyboard::NoEventIndicated); SIZE]; LAYERS];
+    for (layer, keys) in bindings.iter_mut().enumerate() {
+        for (index, binding) in keys.iter_mut().enumerate() {
+            let layer = layer as Layer;
+            *binding = decode_binding::<LAYERS>(&mut reader)?
+                .ok_or(DecodeError::InvalidBinding { layer, index })?;
+        }
+    }
+    let mut timings = Timings {
+        config,
+        layers: [Timing::NONE; LAYERS],
+        keys: [Timing::NONE; SIZE],
+    };
+    for timing in &mut timings.layers {
+        *timing = decode_timing(&mut reader)?;
+    }
+    for _ in 0..u16::from_le_bytes(reader.take()?) {
+        let index = u16::from_le_bytes(reader.take()?) as usize;
+        let timing = decode_timing(&mut reader)?;
+        *timings
+            .keys
+            .get_mut(index)
+            .ok_or(DecodeError::InvalidTiming)? = timing;
+    }
+    if !reader.data.is_empty() {
+        return Err(DecodeError::TrailingData);
+    }
+    Ok((bindings, timings))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::prelude::*;
+    use super::*;
+
+    const CONFIG: ModTapConfig = ModTapConfig {
+        mod_timeout: 200,
+        tap_release: 100,
+        tap_repeat: 500,
+        retro_tap: false,
+    };
+
+    /// `CONFIG` without layer or key timings
+    fn timings<const SIZE: usize, const LAYERS: usize>() -> Timings<SIZE, LAYERS> {
+        Timings {
+            config: CONFIG,
+            layers: [Timing::NONE; LAYERS],
+            keys: [Timing::NONE; SIZE],
+        }
+    }
+
+    fn encoded<const SIZE: usize, const LAYERS: usize>(
+        bindings: &[[KeyShorthand; SIZE]; LAYERS],
+    ) -> std::vec::Vec<u8> {
+        let mut out = std::vec![0; max_len(SIZE, LAYERS)];
+        let len = encode(bindings, &timings(), &mut out).unwrap();
+        out.truncate(len);
+        out
+    }
+
+    /// Replaces the checksum after editing the body
+    fn resign(data: &mut [u8]) {
+        let len = data.len() - CHECKSUM_LEN;
+        let checksum = crc32(&data[..len]);
+        data[len..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn fixture() {
+        let data = encoded(&[[Kb(A), La(1)], [MT(LSFT, B), Cc(VOLU)]]);
+        #[rustfmt::skip]
+        let body: &[u8] = &[
+            b'R', b'M', b'K', VERSION, 2, 2, 0,
+            200, 0, 0, 0, 0, 0, 0, 0,
+            100, 0, 0, 0, 0, 0, 0, 0,
+            244, 1, 0, 0, 0, 0, 0, 0,
+            0,
+            TAG_KB, 0x04, TAG_LA, 1,
+            TAG_MT, 0xE1, 0x05, TAG_CC, 0xE9, 0x00,
+            0, 0,
+            0, 0,
+        ];
+        assert_eq!(&data[..data.len() - CHECKSUM_LEN], body);
+        assert_eq!(data[data.len() - CHECKSUM_LEN..], crc32(body).to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let bindings = [
+            [
+                Kb(A),
+                La(2),
+                MT(LCTL, Escape),
+                MK(Mods::LCTL.union(Mods::LALT), DeleteForward),
+                AS(Keyboard1),
+                Uc('€'),
+            ],
+            [
+                UcMode(super::super::unicode::UnicodeMode::MacOs),
+                Lead,
+                CapsWord,
+                Ms(MS_U),
+                Ms(BTN2),
+                Sys(SLEP),
+            ],
+            [
+                Cc(MPLY),
+                LT(1, Space),
+                MMT(Mods::HYPER, Escape),
+                Kb(___),
+                Kb(___),
+                Kb(___),
+            ],
+        ];
+        let data = encoded(&bindings);
+        assert_eq!(decode::<6, 3>(&data), Ok((bindings, timings())));
+    }
+
+    #[test]
+    fn retro_tap() {
+        let mut timings = timings();
+        timings.config.retro_tap = true;
+        let mut data = [0; max_len(1, 1)];
+        let len = encode(&[[MT(LSFT, A)]], &timings, &mut data).unwrap();
+        assert_eq!(data[HEADER_LEN - 1], FLAG_RETRO_TAP);
+        assert_eq!(decode::<1, 1>(&data[..len]), Ok(([[MT(LSFT, A)]], timings)));
+
+        data[HEADER_LEN - 1] = 0x81;
+        resign(&mut data[..len]);
+        assert_eq!(
+            decode::<1, 1>(&data[..len]),
+            Err(DecodeError::UnknownFlags(0x81))
+        );
+    }
+
+    #[test]
+    fn layer_and_key_timings() {
+        let bindings = [[MT(LSFT, A), MT(LCTL, B), Kb(C)], [Kb(D), Kb(E), Kb(F)]];
+        let slow = Timing {
+            mod_timeout: Some(300),
+            tap_repeat: Some(0),
+            ..Timing::NONE
+        };
+        let timings = Timings {
+            config: CONFIG,
+            layers: [
+                Timing::NONE,
+                Timing {
+                    tap_release: Some(50),
+                    ..Timing::NONE
+                },
+            ],
+            keys: [Timing::NONE, slow, Timing::NONE],
+        };
+        let mut data = [0; max_len(3, 2)];
+        let len = encode(&bindings, &timings, &mut data).unwrap();
+        let data = &mut data[..len];
+        #[rustfmt::skip]
+        let tail: &[u8] = &[
+            0,
+            0b010, 50, 0, 0, 0, 0, 0, 0, 0,
+            1, 0,
+            1, 0, 0b101, 44, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            &data[len - CHECKSUM_LEN - tail.len()..len - CHECKSUM_LEN],
+            tail
+        );
+        assert_eq!(decode::<3, 2>(data), Ok((bindings, timings)));
+
+        // The mask, then the index of the key timings
+        let mask = len - CHECKSUM_LEN - 17;
+        data[mask] = 0b1000;
+        resign(data);
+        assert_eq!(decode::<3, 2>(data), Err(DecodeError::InvalidTiming));
+        data[mask] = 0b101;
+        data[mask - 2] = 3;
+        resign(data);
+        assert_eq!(decode::<3, 2>(data), Err(DecodeError::InvalidTiming));
+    }
+
+    #[test]
+    fn rejects() {
+        let mut data = encoded(&[[Kb(A), La(1)], [Kb(B), Kb(C)]]);
+        assert_eq!(decode::<2, 2>(&data[..10]), Err(DecodeError::Truncated));
+        assert_eq!(decode::<3, 2>(&data), Err(DecodeError::SizeMismatch));
+        assert_eq!(decode::<2, 3>(&data), Err(DecodeError::SizeMismatch));
+
+        let mut trailing = data.clone();
+        trailing.insert(trailing.len() - CHECKSUM_LEN, 0);
+        resign(&mut trailing);
+        assert_eq!(decode::<2, 2>(&trailing), Err(DecodeError::TrailingData));
+
+        data[HEADER_LEN + 3] = 2;
+        assert_eq!(decode::<2, 2>(&data), Err(DecodeError::Checksum));
+        resign(&mut data);
+        assert_eq!(
+            decode::<2, 2>(&data),
+            Err(DecodeError::InvalidBinding { layer: 0, index: 1 })
+        );
+
+        data[HEADER_LEN + 3] = 1;
+        data[HEADER_LEN + 1] = 0xA5;
+        resign(&mut data);
+        assert_eq!(
+            decode::<2, 2>(&data),
+            Err(DecodeError::InvalidBinding { layer: 0, index: 0 })
+        );
+
+        let mut buttons = encoded(&[[Ms(BTN5)]]);
+        buttons[HEADER_LEN + 1] = 6;
+        resign(&mut buttons);
+        assert_eq!(
+            decode::<1, 1>(&buttons),
+            Err(DecodeError::InvalidBinding { layer: 0, index: 0 })
+        );
+
+        data[3] = 9;
+        assert_eq!(
+            decode::<2, 2>(&data),
+            Err(DecodeError::UnsupportedVersion(9))
+        );
+        data[0] = b'X';
+        assert_eq!(decode::<2, 2>(&data), Err(DecodeError::BadMagic));
+    }
+
+    #[test]
+    fn corrupt_data_does_not_panic() {
+        let data = encoded(&[[Kb(A), Uc('x')], [Cc(VOLU), MT(LSFT, B)]]);
+        for len in 0..data.len() {
+            assert!(decode::<2, 2>(&data[..len]).is_err());
+        }
+        for index in 0..data.len() - CHECKSUM_LEN {
+            for value in [0x00, 0x01, 0x7F, 0xFF] {
+                let mut data = data.clone();
+                data[index] = value;
+                resign(&mut data);
+                let _ = decode::<2, 2>(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_errors() {
+        let mut out = [0; 8];
+        assert_eq!(
+            encode(&[[Kb(A)]], &timings(), &mut out),
+            Err(EncodeError::BufferTooSmall)
+        );
+        let mut out = [0; max_len(2, 1)];
+        assert_eq!(
+            encode(&[[Kb(A), Str("hi")]], &timings(), &mut out),
+            Err(EncodeError::Unsupported { layer: 0, index: 1 })
+        );
+        assert_eq!(
+            encode(&[[Ms(MouseAction::Button(6))]], &timings(), &mut out),
+            Err(EncodeError::Unsupported { layer: 0, index: 0 })
+        );
+        let none = [Timing::NONE; 2];
+        let layers = [std::vec![Kb(A), Kb(B)], std::vec![Kb(C)]];
+        assert_eq!(
+            encode_layers(&layers, &CONFIG, &none, [], &mut out),
+            Err(EncodeError::UnevenLayers)
+        );
+        assert_eq!(
+            encode_layers(&[[Kb(A)], [Kb(B)]], &CONFIG, &none[..1], [], &mut out),
+            Err(EncodeError::UnevenLayers)
+        );
+        assert_eq!(
+            encode_layers(&[[Kb(A)]], &CONFIG, &none[..1], [(1, none[0])], &mut out),
+            Err(EncodeError::KeyOutOfRange(1))
+        );
+        let mut slices = [0; max_len(1, 2)];
+        let len = encode_layers(&[[Kb(A)], [La(0)]], &CONFIG, &none, [], &mut slices).unwrap();
+        assert_eq!(slices[..len], encoded(&[[Kb(A)], [La(0)]]));
+    }
+}
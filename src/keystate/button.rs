@@ -4,16 +4,16 @@ use super::Keyish;
 use super::Shared;
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct Unpressed {
-    pub(super) key: Keyboard,
+pub struct Unpressed<Code = Keyboard> {
+    pub(super) key: Code,
 }
 #[derive(Debug, PartialEq, Eq)]
-pub struct Pressed {
-    pub(super) key: Keyboard,
+pub struct Pressed<Code = Keyboard> {
+    pub(super) key: Code,
 }
 
-impl KeyState<Unpressed> {
-    fn press(&self) -> KeyState<Pressed> {
+impl<Code: Copy> KeyState<Unpressed<Code>> {
+    fn press(&self) -> KeyState<Pressed<Code>> {
         KeyState {
             state: Pressed {
                 key: self.state.key,
@@ -23,8 +23,8 @@ impl KeyState<Unpressed> {
     }
 }
 
-impl KeyState<Pressed> {
-    fn release(&self) -> KeyState<Unpressed> {
+impl<Code: Copy> KeyState<Pressed<Code>> {
+    fn release(&self) -> KeyState<Unpressed<Code>> {
         KeyState {
             state: Unpressed {
                 key: self.state.key,
@@ -34,26 +34,34 @@ impl KeyState<Pressed> {
     }
 }
 
+/// A key sending `Code` while held, by default a keyboard usage
 #[derive(Debug, PartialEq, Eq)]
-pub enum ButtonState {
-    Unpressed(KeyState<Unpressed>),
-    Pressed(KeyState<Pressed>),
+pub enum ButtonState<Code = Keyboard> {
+    Unpressed(KeyState<Unpressed<Code>>),
+    Pressed(KeyState<Pressed<Code>>),
 }
 
-impl Keyish for ButtonState {
+impl<Code> Keyish for ButtonState<Code> {
     fn is_finished(&self) -> bool {
         matches!(self, ButtonState::Unpressed(_))
     }
 }
 
-impl ButtonState {
-    pub fn new(key: Keyboard) -> Self {
+impl<Code: Copy> ButtonState<Code> {
+    pub fn new(key: Code) -> Self {
         Self::Unpressed(KeyState {
             state: Unpressed { key },
             shared: Shared,
         })
     }
 
+    pub fn key(&self) -> Code {
+        match self {
+            ButtonState::Unpressed(KeyState { state, .. }) => state.key,
+            ButtonState::Pressed(KeyState { state, .. }) => state.key,
+        }
+    }
+
     pub fn key_transition(&mut self, pressed: bool) {
         match &self {
             Self::Unpressed(state) if pressed => *self = Self::Pressed(state.press()),
@@ -62,7 +70,7 @@ impl ButtonState {
         };
     }
 
-    pub fn get_key(&self) -> Option<Keyboard> {
+    pub fn get_key(&self) -> Option<Code> {
         match self {
             ButtonState::Unpressed(_) => None,
             ButtonState::Pressed(KeyState { state, .. }) => Some(state.key),
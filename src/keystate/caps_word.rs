@@ -0,0 +1,270 @@
+//! Caps Word: shifts letters until the end of the current word
+
+use heapless::Vec;
+
+use super::host::HostConfig;
+use super::keycode::KeyCode;
+use super::mods::Mods;
+use super::rollover;
+use super::Duration;
+use super::Instant;
+use super::Keyboard;
+
+/// Keys which neither get shifted nor end the word by default
+pub const DEFAULT_CONTINUE: &[Keyboard] = &[
+    Keyboard::Keyboard1,
+    Keyboard::Keyboard2,
+    Keyboard::Keyboard3,
+    Keyboard::Keyboard4,
+    Keyboard::Keyboard5,
+    Keyboard::Keyboard6,
+    Keyboard::Keyboard7,
+    Keyboard::Keyboard8,
+    Keyboard::Keyboard9,
+    Keyboard::Keyboard0,
+    Keyboard::DeleteBackspace,
+    Keyboard::DeleteForward,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapsWordConfig {
+    /// Keys other than letters, `-` and Shift which do not end the word
+    pub continue_keys: &'static [Keyboard],
+    /// Time without any key held after which Caps Word turns off, `0` for never
+    pub timeout: Duration,
+}
+
+impl Default for CapsWordConfig {
+    fn default() -> Self {
+        CapsWordConfig {
+            continue_keys: DEFAULT_CONTINUE,
+            timeout: 0,
+        }
+    }
+}
+
+/// Typed keys remembered from the last scan, to tell which key was pressed last
+const HELD: usize = 8;
+
+/// The keymap-wide Caps Word mode
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum CapsWord {
+    #[default]
+    Off,
+    On {
+        timeout: Instant,
+        /// The typed keys held in the last scan
+        held: Vec<Keyboard, HELD>,
+        /// The most recently pressed typed key, which alone decides whether the report is shifted
+        last: Option<Keyboard>,
+    },
+}
+
+impl CapsWord {
+    pub fn toggle(&mut self, now: Instant, config: &CapsWordConfig) {
+        *self = match self {
+            CapsWord::Off => CapsWord::On {
+                timeout: now.saturating_add(config.timeout),
+                held: Vec::new(),
+                last: None,
+            },
+            CapsWord::On { .. } => CapsWord::Off,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        matches!(self, CapsWord::On { .. })
+    }
+
+    /// Shifts the letters and turns `-` into `_` among the pressed keys, or turns off if any key
+    /// ends the word, including modifiers other than shift as in Ctrl+Backspace. The shift only
+    /// goes out while the most recently pressed key wants it, so a digit rolled after a letter
+    /// is not shifted. Returns `false` if the extra shift did not fit.
+    pub fn apply<Code: KeyCode, const N: usize>(
+        &mut self,
+        now: Instant,
+        config: &CapsWordConfig,
+        host: &HostConfig,
+        keys: &mut Vec<Code, N>,
+    ) -> bool {
+        let CapsWord::On {
+            timeout,
+            held,
+            last,
+        } = self
+        else {
+            return true;
+        };
+        let stroke = |c| host.layout.strokes(c).first().copied().unwrap_or_default();
+        let (minus, underscore) = (stroke('-'), stroke('_'));
+        let is_minus = |key: Keyboard| minus.mods.is_empty() && key == minus.key;
+
+        let neutral = |key: Keyboard| {
+            key == Keyboard::NoEventIndicated
+                || Mods::from_key(key).is_some_and(|mods| Mods::SHIFT.contains(mods))
+        };
+        let ends = |key: Keyboard| {
+            !neutral(key)
+                && !host.layout.is_letter(key)
+                && !is_minus(key)
+                && !config.continue_keys.contains(&key)
+        };
+        let mut typed = keys.iter().filter_map(|code| code.to_keyboard());
+        if typed.any(ends) {
+            *self = CapsWord::Off;
+            return true;
+        }
+
+        let typed = keys
+            .iter()
+            .filter_map(|code| code.to_keyboard())
+            .filter(|key| !neutral(*key));
+        let mut now_held = Vec::new();
+        for key in typed {
+            if !held.contains(&key) {
+                *last = Some(key);
+            }
+            now_held.push(key).ok();
+        }
+        *held = now_held;
+        if !held.is_empty() {
+            *timeout = now.saturating_add(config.timeout);
+        } else if config.timeout != 0 && *timeout <= now {
+            *self = CapsWord::Off;
+            return true;
+        }
+
+        let mut mods = Mods::NONE;
+        for code in keys.iter_mut() {
+            match code.to_keyboard() {
+                Some(key) if host.layout.is_letter(key) && *last == Some(key) => mods |= Mods::LSFT,
+                Some(key) if is_minus(key) => {
+                    if let Some(replacement) = Code::from_keyboard(underscore.key) {
+                        *code = replacement;
+                        if *last == Some(key) {
+                            mods |= underscore.mods;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        let mut fits = true;
+        for key in mods.keys().filter_map(Code::from_keyboard) {
+            if !keys.contains(&key) {
+                fits &= rollover::push(keys, key);
+            }
+        }
+        fits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::host::HostLayout;
+    use super::*;
+    use Keyboard::*;
+
+    fn applied(
+        caps_word: &mut CapsWord,
+        now: Instant,
+        host: &HostConfig,
+        keys: &[Keyboard],
+    ) -> std::vec::Vec<Keyboard> {
+        let config = CapsWordConfig {
+            timeout: 10,
+            ..Default::default()
+        };
+        let mut keys: Vec<Keyboard, 8> = Vec::from_slice(keys).unwrap();
+        assert!(caps_word.apply(now, &config, host, &mut keys));
+        keys.into_iter().collect()
+    }
+
+    #[test]
+    fn shifts_word() {
+        let host = HostConfig::default();
+        let mut caps_word = CapsWord::Off;
+        assert_eq!(applied(&mut caps_word, 0, &host, &[A]), [A]);
+        caps_word.toggle(0, &CapsWordConfig::default());
+        assert!(caps_word.is_on());
+        assert_eq!(applied(&mut caps_word, 1, &host, &[A]), [A, LeftShift]);
+        assert_eq!(
+            applied(&mut caps_word, 2, &host, &[Minus]),
+            [Minus, LeftShift]
+        );
+        assert_eq!(applied(&mut caps_word, 3, &host, &[Keyboard1]), [Keyboard1]);
+        assert_eq!(
+            applied(&mut caps_word, 4, &host, &[LeftShift, B]),
+            [LeftShift, B]
+        );
+        assert!(caps_word.is_on());
+        assert_eq!(applied(&mut caps_word, 5, &host, &[C, Space]), [C, Space]);
+        assert!(!caps_word.is_on());
+        assert_eq!(applied(&mut caps_word, 6, &host, &[D]), [D]);
+    }
+
+    #[test]
+    fn rolled_keys() {
+        let host = HostConfig::default();
+        let mut caps_word = CapsWord::Off;
+        caps_word.toggle(0, &CapsWordConfig::default());
+        assert_eq!(applied(&mut caps_word, 1, &host, &[A]), [A, LeftShift]);
+        assert_eq!(
+            applied(&mut caps_word, 2, &host, &[A, Keyboard1]),
+            [A, Keyboard1]
+        );
+        assert_eq!(applied(&mut caps_word, 3, &host, &[Keyboard1]), [Keyboard1]);
+        assert_eq!(
+            applied(&mut caps_word, 4, &host, &[B, Keyboard1]),
+            [B, Keyboard1, LeftShift]
+        );
+        assert_eq!(applied(&mut caps_word, 5, &host, &[Keyboard1]), [Keyboard1]);
+        assert!(caps_word.is_on());
+    }
+
+    #[test]
+    fn other_modifiers_end_word() {
+        let host = HostConfig::default();
+        let mut caps_word = CapsWord::Off;
+        caps_word.toggle(0, &CapsWordConfig::default());
+        assert_eq!(
+            applied(&mut caps_word, 1, &host, &[RightShift, A]),
+            [RightShift, A, LeftShift]
+        );
+        assert_eq!(
+            applied(&mut caps_word, 2, &host, &[LeftControl, DeleteBackspace]),
+            [LeftControl, DeleteBackspace]
+        );
+        assert!(!caps_word.is_on());
+    }
+
+    #[test]
+    fn times_out() {
+        let host = HostConfig::default();
+        let mut caps_word = CapsWord::Off;
+        caps_word.toggle(0, &CapsWordConfig::default());
+        assert_eq!(applied(&mut caps_word, 1, &host, &[A]), [A, LeftShift]);
+        assert_eq!(applied(&mut caps_word, 10, &host, &[]), []);
+        assert!(caps_word.is_on());
+        assert_eq!(applied(&mut caps_word, 11, &host, &[]), []);
+        assert!(!caps_word.is_on());
+    }
+
+    #[test]
+    fn host_layout() {
+        let host = HostConfig {
+            layout: HostLayout::De,
+            ..Default::default()
+        };
+        let mut caps_word = CapsWord::Off;
+        caps_word.toggle(0, &CapsWordConfig::default());
+        assert_eq!(
+            applied(&mut caps_word, 1, &host, &[ForwardSlash]),
+            [ForwardSlash, LeftShift]
+        );
+        assert_eq!(applied(&mut caps_word, 2, &host, &[Minus]), [Minus]);
+        assert!(!caps_word.is_on());
+    }
+}
@@ -0,0 +1,218 @@
+//! Key behaviours defined outside this crate, e.g. in a board's firmware, bound with
+//! `KeyShorthand::Custom`
+
+use core::fmt::Debug;
+
+use heapless::Vec;
+
+use super::keycode::KeyCode;
+use super::mods::Mods;
+use super::Instant;
+use super::KeymapFlags;
+use super::Layer;
+
+/// A key's behaviour, holding its own state, as each key keeps a copy of its binding
+pub trait Behaviour<Code>: Copy + Eq + Debug {
+    /// Called on the scan the key is pressed, before `tick`
+    fn press(&mut self, _context: &mut Context<Code>) {}
+
+    /// Called on the scan the key is released, before `tick`
+    fn release(&mut self, _context: &mut Context<Code>) {}
+
+    /// Called every scan the key is on the current layer. Keys are only reported for the scan
+    /// they are pressed in, so a held key presses its codes here.
+    fn tick(&mut self, _context: &mut Context<Code>) {}
+
+    /// Whether the key is done once released, so it can move to another layer and the MCU go
+    /// to sleep
+    fn is_finished(&self) -> bool {
+        true
+    }
+}
+
+/// The behaviour of keymaps without custom keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoCustom {}
+
+impl<Code> Behaviour<Code> for NoCustom {}
+
+/// The held layers, most recent last, as `La` keys push and remove them
+pub trait LayerStack {
+    fn held(&self) -> &[Layer];
+
+    /// Holds `layer` on top of the others, returns `false` if it does not exist
+    fn hold(&mut self, layer: Layer) -> bool;
+
+    fn release(&mut self, layer: Layer);
+}
+
+impl<const N: usize> LayerStack for Vec<Layer, N> {
+    fn held(&self) -> &[Layer] {
+        self
+    }
+
+    fn hold(&mut self, layer: Layer) -> bool {
+        if layer as usize >= N {
+            return false;
+        }
+        self.release(layer);
+        self.push(layer).is_ok()
+    }
+
+    fn release(&mut self, layer: Layer) {
+        self.retain(|held| *held != layer);
+    }
+}
+
+/// Where a custom key sends its codes
+pub trait Sink<Code> {
+    fn press(&mut self, code: Code);
+}
+
+/// Collects codes like `Keymap::pressed_keys`, setting `KeymapFlags::rollover` if they do not fit
+pub(super) struct Pressed<'a, Code, const ROLLOVER: usize> {
+    pub(super) keys: &'a mut Vec<Code, ROLLOVER>,
+    pub(super) flags: &'a mut KeymapFlags,
+    pub(super) real_mods: &'a mut Mods,
+}
+
+impl<Code: KeyCode, const ROLLOVER: usize> Sink<Code> for Pressed<'_, Code, ROLLOVER> {
+    fn press(&mut self, code: Code) {
+        super::press_held(self.keys, self.flags, self.real_mods, code);
+    }
+}
+
+/// What a custom key can see and do during a scan
+pub struct Context<'a, Code> {
+    /// The time of the scan
+    pub now: Instant,
+    pub layers: &'a mut dyn LayerStack,
+    pub sink: &'a mut dyn Sink<Code>,
+}
+
+impl<Code> Context<'_, Code> {
+    /// Reports `code` as pressed for this scan
+    pub fn press(&mut self, code: Code) {
+        self.sink.press(code);
+    }
+}
+
+/// A custom key and whether it is held
+#[derive(Debug, PartialEq, Eq)]
+pub struct CustomState<Custom> {
+    custom: Custom,
+    pressed: bool,
+}
+
+impl<Custom> CustomState<Custom> {
+    pub fn new(custom: Custom) -> Self {
+        CustomState {
+            custom,
+            pressed: false,
+        }
+    }
+
+    pub fn custom_transition<Code>(&mut self, pressed: bool, context: &mut Context<Code>)
+    where
+        Custom: Behaviour<Code>,
+    {
+        match (self.pressed, pressed) {
+            (false, true) => self.custom.press(context),
+            (true, false) => self.custom.release(context),
+            _ => (),
+        }
+        self.pressed = pressed;
+        self.custom.tick(context);
+    }
+
+    /// Like `Keyish::is_finished`, which cannot name `Code`
+    pub fn is_finished<Code>(&self) -> bool
+    where
+        Custom: Behaviour<Code>,
+    {
+        !self.pressed && Behaviour::<Code>::is_finished(&self.custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prelude::*;
+    use super::super::Keyboard;
+    use super::super::Keymap;
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Board {
+        /// Toggles a layer on each press
+        Toggle(Layer),
+        /// Sends the key for three scans from each press
+        Pulse { key: Keyboard, left: u8 },
+    }
+
+    impl Behaviour<Keyboard> for Board {
+        fn press(&mut self, context: &mut Context<Keyboard>) {
+            match self {
+                Board::Toggle(layer) if context.layers.held().contains(layer) => {
+                    context.layers.release(*layer)
+                }
+                Board::Toggle(layer) => {
+                    context.layers.hold(*layer);
+                }
+                Board::Pulse { left, .. } => *left = 3,
+            }
+        }
+
+        fn tick(&mut self, context: &mut Context<Keyboard>) {
+            if let Board::Pulse {
+                key,
+                left: left @ 1..,
+            } = self
+            {
+                context.press(*key);
+                *left -= 1;
+            }
+        }
+
+        fn is_finished(&self) -> bool {
+            !matches!(self, Board::Pulse { left: 1.., .. })
+        }
+    }
+
+    #[test]
+    fn custom_keys() {
+        let toggle = Custom(Board::Toggle(1));
+        let pulse = Custom(Board::Pulse { key: A, left: 0 });
+        let mut keymap: Keymap<3, 2, 8, Keyboard, Board> =
+            Keymap::new([[toggle, pulse, Kb(B)], [toggle, Kb(___), Kb(C)]], 2, 4, 6);
+
+        keymap.process([true, false, true], 1);
+        assert_eq!(keymap.pressed_keys, [C]);
+        keymap.process([false, false, false], 2);
+        keymap.process([false, false, true], 3);
+        assert_eq!(keymap.pressed_keys, [C]);
+        keymap.process([true, false, false], 4);
+        keymap.process([false, false, true], 5);
+        assert_eq!(keymap.pressed_keys, [B]);
+
+        keymap.process([false, true, false], 10);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false, false], 11);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false, false], 12);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false, false], 13);
+        assert_eq!(keymap.pressed_keys, []);
+    }
+
+    #[test]
+    fn layer_stack() {
+        let mut layers: Vec<Layer, 3> = Vec::new();
+        assert!(layers.hold(2));
+        assert!(layers.hold(0));
+        assert!(layers.hold(2));
+        assert!(!layers.hold(3));
+        assert_eq!(layers.held(), [0, 2]);
+        layers.release(0);
+        assert_eq!(layers.held(), [2]);
+    }
+}
@@ -0,0 +1,343 @@
+//! What the host does with the keys we send it, i.e. the keyboard layout selected in the OS
+
+use heapless::Vec;
+
+use super::macros::Stroke;
+use super::mods::Mods;
+use super::unicode::UnicodeMode;
+use super::Keyboard;
+use super::Keyboard::*;
+
+/// Settings describing the host, used when typing text
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HostConfig {
+    pub layout: HostLayout,
+    pub unicode: UnicodeMode,
+}
+
+/// The keyboard layout the host OS has selected
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HostLayout {
+    /// US ANSI QWERTY
+    #[default]
+    Us,
+    /// UK ISO QWERTY
+    Uk,
+    /// German ISO QWERTZ
+    De,
+    /// French ISO AZERTY
+    Fr,
+}
+
+/// Strokes needed to type one character, more than one for dead keys
+pub type CharStrokes = Vec<Stroke, 2>;
+
+const fn plain(key: Keyboard) -> Stroke {
+    Stroke::new(Mods::NONE, key)
+}
+const fn shift(key: Keyboard) -> Stroke {
+    Stroke::new(Mods::LSFT, key)
+}
+const fn altgr(key: Keyboard) -> Stroke {
+    Stroke::new(Mods::ALTGR, key)
+}
+
+const LETTERS: [Keyboard; 26] = [
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+];
+const DIGITS: [Keyboard; 10] = [
+    Keyboard0, Keyboard1, Keyboard2, Keyboard3, Keyboard4, Keyboard5, Keyboard6, Keyboard7,
+    Keyboard8, Keyboard9,
+];
+
+impl HostLayout {
+    /// The strokes typing `c` on this layout, empty if `c` cannot be typed
+    pub fn strokes(self, c: char) -> CharStrokes {
+        let mut strokes = CharStrokes::new();
+        let (stroke, dead) = match self.stroke(c) {
+            Some(Typed::Live(stroke)) => (stroke, false),
+            Some(Typed::Dead(stroke)) => (stroke, true),
+            None => return strokes,
+        };
+        strokes.push(stroke).ok();
+        if dead {
+            strokes.push(plain(Space)).ok();
+        }
+        strokes
+    }
+
+    /// Whether the key types a letter on this layout
+    pub fn is_letter(self, key: Keyboard) -> bool {
+        match self {
+            HostLayout::Fr if key == M => false,
+            HostLayout::Fr if key == Semicolon => true,
+            _ => LETTERS.contains(&key),
+        }
+    }
+
+    fn stroke(self, c: char) -> Option<Typed> {
+        // Common to all supported layouts
+        match c {
+            ' ' => return Some(Typed::Live(plain(Space))),
+            '\n' => return Some(Typed::Live(plain(ReturnEnter))),
+            '\t' => return Some(Typed::Live(plain(Tab))),
+            _ => (),
+        }
+        match self {
+            HostLayout::Us => us(c),
+            HostLayout::Uk => uk(c),
+            HostLayout::De => de(c),
+            HostLayout::Fr => fr(c),
+        }
+    }
+}
+
+/// A key which types its character, or a dead key needing a space after it
+enum Typed {
+    Live(Stroke),
+    Dead(Stroke),
+}
+use Typed::{Dead, Live};
+
+fn letter(c: char) -> Option<(Keyboard, bool)> {
+    if c.is_ascii_lowercase() {
+        Some((LETTERS[(c as u8 - b'a') as usize], false))
+    } else if c.is_ascii_uppercase() {
+        Some((LETTERS[(c as u8 - b'A') as usize], true))
+    } else {
+        None
+    }
+}
+
+fn digit(c: char) -> Option<Keyboard> {
+    c.to_digit(10).map(|d| DIGITS[d as usize])
+}
+
+/// Letters, with the positions of some swapped around
+fn letters(c: char, swap: &[(Keyboard, Keyboard)]) -> Option<Typed> {
+    let (key, shifted) = letter(c)?;
+    let key = swap
+        .iter()
+        .find_map(|&(from, to)| (from == key).then_some(to))
+        .unwrap_or(key);
+    Some(Live(if shifted { shift(key) } else { plain(key) }))
+}
+
+fn us(c: char) -> Option<Typed> {
+    if let Some(key) = digit(c) {
+        return Some(Live(plain(key)));
+    }
+    let stroke = match c {
+        '!' => shift(Keyboard1),
+        '@' => shift(Keyboard2),
+        '#' => shift(Keyboard3),
+        '$' => shift(Keyboard4),
+        '%' => shift(Keyboard5),
+        '^' => shift(Keyboard6),
+        '&' => shift(Keyboard7),
+        '*' => shift(Keyboard8),
+        '(' => shift(Keyboard9),
+        ')' => shift(Keyboard0),
+        '-' => plain(Minus),
+        '_' => shift(Minus),
+        '=' => plain(Equal),
+        '+' => shift(Equal),
+        '[' => plain(LeftBrace),
+        '{' => shift(LeftBrace),
+        ']' => plain(RightBrace),
+        '}' => shift(RightBrace),
+        '\\' => plain(Backslash),
+        '|' => shift(Backslash),
+        ';' => plain(Semicolon),
+        ':' => shift(Semicolon),
+        '\'' => plain(Apostrophe),
+        '"' => shift(Apostrophe),
+        '`' => plain(Grave),
+        '~' => shift(Grave),
+        ',' => plain(Comma),
+        '<' => shift(Comma),
+        '.' => plain(Dot),
+        '>' => shift(Dot),
+        '/' => plain(ForwardSlash),
+        '?' => shift(ForwardSlash),
+        _ => return letters(c, &[]),
+    };
+    Some(Live(stroke))
+}
+
+fn uk(c: char) -> Option<Typed> {
+    let stroke = match c {
+        '"' => shift(Keyboard2),
+        '£' => shift(Keyboard3),
+        '€' => altgr(Keyboard4),
+        '@' => shift(Apostrophe),
+        '#' => plain(NonUSHash),
+        '~' => shift(NonUSHash),
+        '\\' => plain(NonUSBackslash),
+        '|' => shift(NonUSBackslash),
+        '¬' => shift(Grave),
+        _ => return us(c),
+    };
+    Some(Live(stroke))
+}
+
+fn de(c: char) -> Option<Typed> {
+    if let Some(key) = digit(c) {
+        return Some(Live(plain(key)));
+    }
+    let typed = match c {
+        '!' => Live(shift(Keyboard1)),
+        '"' => Live(shift(Keyboard2)),
+        '§' => Live(shift(Keyboard3)),
+        '$' => Live(shift(Keyboard4)),
+        '%' => Live(shift(Keyboard5)),
+        '&' => Live(shift(Keyboard6)),
+        '/' => Live(shift(Keyboard7)),
+        '(' => Live(shift(Keyboard8)),
+        ')' => Live(shift(Keyboard9)),
+        '=' => Live(shift(Keyboard0)),
+        '{' => Live(altgr(Keyboard7)),
+        '[' => Live(altgr(Keyboard8)),
+        ']' => Live(altgr(Keyboard9)),
+        '}' => Live(altgr(Keyboard0)),
+        'ß' => Live(plain(Minus)),
+        '?' => Live(shift(Minus)),
+        '\\' => Live(altgr(Minus)),
+        '´' => Dead(plain(Equal)),
+        '`' => Dead(shift(Equal)),
+        'ü' => Live(plain(LeftBrace)),
+        'Ü' => Live(shift(LeftBrace)),
+        '+' => Live(plain(RightBrace)),
+        '*' => Live(shift(RightBrace)),
+        '~' => Live(altgr(RightBrace)),
+        'ö' => Live(plain(Semicolon)),
+        'Ö' => Live(shift(Semicolon)),
+        'ä' => Live(plain(Apostrophe)),
+        'Ä' => Live(shift(Apostrophe)),
+        '#' => Live(plain(NonUSHash)),
+        '\'' => Live(shift(NonUSHash)),
+        '^' => Dead(plain(Grave)),
+        '°' => Live(shift(Grave)),
+        '<' => Live(plain(NonUSBackslash)),
+        '>' => Live(shift(NonUSBackslash)),
+        '|' => Live(altgr(NonUSBackslash)),
+        ',' => Live(plain(Comma)),
+        ';' => Live(shift(Comma)),
+        '.' => Live(plain(Dot)),
+        ':' => Live(shift(Dot)),
+        '-' => Live(plain(ForwardSlash)),
+        '_' => Live(shift(ForwardSlash)),
+        '@' => Live(altgr(Q)),
+        '€' => Live(altgr(E)),
+        _ => return letters(c, &[(Y, Z), (Z, Y)]),
+    };
+    Some(typed)
+}
+
+fn fr(c: char) -> Option<Typed> {
+    if let Some(key) = digit(c) {
+        return Some(Live(shift(key)));
+    }
+    let typed = match c {
+        '²' => Live(plain(Grave)),
+        '&' => Live(plain(Keyboard1)),
+        'é' => Live(plain(Keyboard2)),
+        '~' => Dead(altgr(Keyboard2)),
+        '"' => Live(plain(Keyboard3)),
+        '#' => Live(altgr(Keyboard3)),
+        '\'' => Live(plain(Keyboard4)),
+        '{' => Live(altgr(Keyboard4)),
+        '(' => Live(plain(Keyboard5)),
+        '[' => Live(altgr(Keyboard5)),
+        '-' => Live(plain(Keyboard6)),
+        '|' => Live(altgr(Keyboard6)),
+        'è' => Live(plain(Keyboard7)),
+        '`' => Dead(altgr(Keyboard7)),
+        '_' => Live(plain(Keyboard8)),
+        '\\' => Live(altgr(Keyboard8)),
+        'ç' => Live(plain(Keyboard9)),
+        '^' => Live(altgr(Keyboard9)),
+        'à' => Live(plain(Keyboard0)),
+        '@' => Live(altgr(Keyboard0)),
+        ')' => Live(plain(Minus)),
+        '°' => Live(shift(Minus)),
+        ']' => Live(altgr(Minus)),
+        '=' => Live(plain(Equal)),
+        '+' => Live(shift(Equal)),
+        '}' => Live(altgr(Equal)),
+        '$' => Live(plain(RightBrace)),
+        '£' => Live(shift(RightBrace)),
+        'ù' => Live(plain(Apostrophe)),
+        '%' => Live(shift(Apostrophe)),
+        '*' => Live(plain(NonUSHash)),
+        'µ' => Live(shift(NonUSHash)),
+        '<' => Live(plain(NonUSBackslash)),
+        '>' => Live(shift(NonUSBackslash)),
+        ',' => Live(plain(M)),
+        '?' => Live(shift(M)),
+        ';' => Live(plain(Comma)),
+        '.' => Live(shift(Comma)),
+        ':' => Live(plain(Dot)),
+        '/' => Live(shift(Dot)),
+        '!' => Live(plain(ForwardSlash)),
+        '§' => Live(shift(ForwardSlash)),
+        '€' => Live(altgr(E)),
+        _ => return letters(c, &[(A, Q), (Q, A), (Z, W), (W, Z), (M, Semicolon)]),
+    };
+    Some(typed)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    fn strokes(layout: HostLayout, c: char) -> std::vec::Vec<Stroke> {
+        layout.strokes(c).into_iter().collect()
+    }
+
+    #[test]
+    fn letters_and_digits() {
+        assert_eq!(strokes(HostLayout::Us, 'a'), [plain(A)]);
+        assert_eq!(strokes(HostLayout::Us, 'Z'), [shift(Z)]);
+        assert_eq!(strokes(HostLayout::Us, '7'), [plain(Keyboard7)]);
+        assert_eq!(strokes(HostLayout::De, 'z'), [plain(Y)]);
+        assert_eq!(strokes(HostLayout::De, 'Y'), [shift(Z)]);
+        assert_eq!(strokes(HostLayout::Fr, 'a'), [plain(Q)]);
+        assert_eq!(strokes(HostLayout::Fr, 'M'), [shift(Semicolon)]);
+        assert_eq!(strokes(HostLayout::Fr, '7'), [shift(Keyboard7)]);
+    }
+
+    #[test]
+    fn punctuation() {
+        assert_eq!(strokes(HostLayout::Us, '@'), [shift(Keyboard2)]);
+        assert_eq!(strokes(HostLayout::Uk, '@'), [shift(Apostrophe)]);
+        assert_eq!(strokes(HostLayout::Uk, '!'), [shift(Keyboard1)]);
+        assert_eq!(strokes(HostLayout::De, '@'), [altgr(Q)]);
+        assert_eq!(strokes(HostLayout::De, '-'), [plain(ForwardSlash)]);
+        assert_eq!(strokes(HostLayout::Fr, '@'), [altgr(Keyboard0)]);
+        assert_eq!(strokes(HostLayout::Fr, ','), [plain(M)]);
+    }
+
+    #[test]
+    fn dead_keys() {
+        assert_eq!(strokes(HostLayout::De, '^'), [plain(Grave), plain(Space)]);
+        assert_eq!(strokes(HostLayout::Us, '^'), [shift(Keyboard6)]);
+    }
+
+    #[test]
+    fn is_letter() {
+        assert!(HostLayout::Us.is_letter(M));
+        assert!(!HostLayout::Us.is_letter(Semicolon));
+        assert!(!HostLayout::Fr.is_letter(M));
+        assert!(HostLayout::Fr.is_letter(Semicolon));
+    }
+
+    #[test]
+    fn untypeable() {
+        assert_eq!(strokes(HostLayout::Us, 'é'), []);
+        assert_eq!(strokes(HostLayout::Fr, 'é'), [plain(Keyboard2)]);
+        assert_eq!(strokes(HostLayout::De, '\u{1F600}'), []);
+    }
+}
@@ -0,0 +1,80 @@
+//! What a keymap sends for its keys, so it can drive outputs other than a USB keyboard
+
+use core::fmt::Debug;
+
+use super::Keyboard;
+
+/// A key code the keymap presses, `Keyboard` usages for a USB HID keyboard
+///
+/// `Kb`, `MT` and `LT` keys send codes of this type. Keys the keymap types itself, such as
+/// `MK`, `AS`, macros, strings and leader sequences, are keyboard usages and are sent as
+/// `from_keyboard` converts them. Features which look at the pressed keys, Caps Word, key
+/// overrides and the leader key, see them as `to_keyboard` converts them and ignore the rest.
+pub trait KeyCode: Copy + Eq + Debug {
+    /// `None` if the output has no such key, which then is not sent
+    fn from_keyboard(key: Keyboard) -> Option<Self>;
+
+    /// `None` if the code is not a keyboard key
+    fn to_keyboard(self) -> Option<Keyboard>;
+}
+
+impl KeyCode for Keyboard {
+    fn from_keyboard(key: Keyboard) -> Option<Self> {
+        Some(key)
+    }
+
+    fn to_keyboard(self) -> Option<Keyboard> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prelude::*;
+    use super::super::Keymap;
+    use super::*;
+
+    /// MIDI notes, with nothing typed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Note(u8);
+
+    impl KeyCode for Note {
+        fn from_keyboard(_key: Keyboard) -> Option<Self> {
+            None
+        }
+
+        fn to_keyboard(self) -> Option<Keyboard> {
+            None
+        }
+    }
+
+    #[test]
+    fn notes() {
+        let mut keymap: Keymap<3, 2, 8, Note> = Keymap::new(
+            [
+                [Kb(Note(60)), LT(1, Note(62)), Str("typed")],
+                [Kb(Note(64)), MT(Note(0), Note(1)), Cc(VOLU)],
+            ],
+            2,
+            4,
+            6,
+        );
+
+        keymap.process([true, false, false], 1);
+        assert_eq!(keymap.pressed_keys, [Note(60)]);
+        keymap.process([false, true, false], 2);
+        keymap.process([false, true, false], 4);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, true, true], 5);
+        assert_eq!(keymap.pressed_keys, [Note(64)]);
+        assert_eq!(keymap.consumer_keys, [VOLU]);
+        keymap.process([false, false, false], 6);
+
+        keymap.process([false, true, false], 10);
+        keymap.process([false, false, false], 11);
+        assert_eq!(keymap.pressed_keys, [Note(62)]);
+        // Typed text has no notes
+        keymap.process([false, false, true], 20);
+        assert_eq!(keymap.pressed_keys, []);
+    }
+}
@@ -72,11 +72,59 @@ impl LayerState {
     }
 }
 
+/// A condition on the held layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerCondition {
+    /// The layer is held or the default layer
+    Active(Layer),
+    All(&'static [LayerCondition]),
+    Any(&'static [LayerCondition]),
+    Not(&'static LayerCondition),
+}
+
+impl LayerCondition {
+    pub fn holds(&self, default: Layer, layers: &[Layer]) -> bool {
+        match self {
+            LayerCondition::Active(layer) => *layer == default || layers.contains(layer),
+            LayerCondition::All(conditions) => conditions.iter().all(|c| c.holds(default, layers)),
+            LayerCondition::Any(conditions) => conditions.iter().any(|c| c.holds(default, layers)),
+            LayerCondition::Not(condition) => !condition.holds(default, layers),
+        }
+    }
+}
+
+/// Activates `layer` while `condition` holds, e.g. the tri-layer
+/// `LayerRule::new(All(&[Active(1), Active(2)]), 3)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerRule {
+    pub condition: LayerCondition,
+    pub layer: Layer,
+}
+
+impl LayerRule {
+    pub const fn new(condition: LayerCondition, layer: Layer) -> Self {
+        LayerRule { condition, layer }
+    }
+}
+
+/// The layer keys resolve to: the last matching rule's, otherwise the last held layer, otherwise
+/// the default layer
+pub fn current_layer(default: Layer, layers: &[Layer], rules: &[LayerRule]) -> Layer {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.condition.holds(default, layers))
+        .map(|rule| rule.layer)
+        .unwrap_or_else(|| layers.last().copied().unwrap_or(default))
+}
+
 pub fn active_layer<const LAYERS: usize, Map: Copy>(
+    default: Layer,
     layers: &Vec<Layer, LAYERS>,
+    rules: &[LayerRule],
     keymaps: [Map; LAYERS],
 ) -> Map {
-    keymaps[layers.last().copied().unwrap_or(0) as usize]
+    keymaps[current_layer(default, layers, rules) as usize]
 }
 
 #[cfg(test)]
@@ -141,4 +189,37 @@ mod tests {
         layer1_.layer_transition(false, &mut layers);
         assert_eq!(layers, []);
     }
+
+    #[test]
+    fn tri_layer() {
+        use LayerCondition::*;
+        const RULES: &[LayerRule] = &[LayerRule::new(All(&[Active(1), Active(2)]), 3)];
+        let mut lower = LayerState::new(1);
+        let mut raise = LayerState::new(2);
+        let mut layers = Vec::<Layer, 4>::new();
+
+        assert_eq!(active_layer(0, &layers, RULES, [0, 1, 2, 3]), 0);
+        lower.layer_transition(true, &mut layers);
+        assert_eq!(active_layer(0, &layers, RULES, [0, 1, 2, 3]), 1);
+        raise.layer_transition(true, &mut layers);
+        assert_eq!(active_layer(0, &layers, RULES, [0, 1, 2, 3]), 3);
+        lower.layer_transition(false, &mut layers);
+        assert_eq!(active_layer(0, &layers, RULES, [0, 1, 2, 3]), 2);
+    }
+
+    #[test]
+    fn conditions() {
+        use LayerCondition::*;
+        const RULES: &[LayerRule] = &[
+            LayerRule::new(Any(&[Active(1), Active(2)]), 3),
+            LayerRule::new(All(&[Active(2), Not(&Active(1))]), 4),
+        ];
+        assert_eq!(current_layer(0, &[], RULES), 0);
+        assert_eq!(current_layer(0, &[1], RULES), 3);
+        assert_eq!(current_layer(0, &[2], RULES), 4);
+        assert_eq!(current_layer(0, &[2, 1], RULES), 3);
+        assert_eq!(current_layer(0, &[5], &[]), 5);
+        assert_eq!(current_layer(2, &[], RULES), 4);
+        assert_eq!(current_layer(1, &[], &[]), 1);
+    }
 }
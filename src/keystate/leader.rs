@@ -0,0 +1,212 @@
+//! Leader key: after pressing it, the next few keys are looked up in a table of sequences
+
+use heapless::Vec;
+
+use super::host::HostConfig;
+use super::macros::{Macro, MacroState, Stroke};
+use super::Duration;
+use super::Instant;
+use super::Keyboard;
+use super::Keyish;
+
+/// Longest sequence that can be matched
+pub const MAX_SEQUENCE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeaderSequence {
+    pub keys: &'static [Keyboard],
+    pub action: Macro,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LeaderConfig {
+    pub sequences: &'static [LeaderSequence],
+    /// Time without a key press after which the sequence so far is matched or abandoned
+    pub timeout: Duration,
+}
+
+impl LeaderConfig {
+    fn exact(&self, keys: &[Keyboard]) -> Option<&'static LeaderSequence> {
+        self.sequences.iter().find(|sequence| sequence.keys == keys)
+    }
+
+    /// Whether any sequence is strictly longer than `keys` and starts with them
+    fn extends(&self, keys: &[Keyboard]) -> bool {
+        self.sequences
+            .iter()
+            .any(|sequence| sequence.keys.len() > keys.len() && sequence.keys.starts_with(keys))
+    }
+}
+
+/// The keymap-wide leader mode, started by a leader key
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Leader {
+    #[default]
+    Idle,
+    Listening {
+        keys: Vec<Keyboard, MAX_SEQUENCE>,
+        timeout: Instant,
+    },
+    Playing(MacroState<Macro>),
+}
+
+impl Leader {
+    pub fn start(&mut self, now: Instant, config: &LeaderConfig) {
+        *self = Leader::Listening {
+            keys: Vec::new(),
+            timeout: now + config.timeout,
+        }
+    }
+
+    /// Keys pressed while listening are swallowed by the leader
+    pub fn is_listening(&self) -> bool {
+        matches!(self, Leader::Listening { .. })
+    }
+
+    /// Abandons the sequence, for keys which cannot be part of one such as macros
+    pub fn cancel(&mut self) {
+        if self.is_listening() {
+            *self = Leader::Idle;
+        }
+    }
+
+    /// A key was pressed while listening
+    pub fn key(&mut self, key: Keyboard, now: Instant, config: &LeaderConfig, host: &HostConfig) {
+        let Leader::Listening { keys, timeout } = self else {
+            return;
+        };
+        if keys.push(key).is_err() {
+            *self = Leader::Idle;
+            return;
+        }
+        *timeout = now + config.timeout;
+        if config.extends(keys) {
+            return;
+        }
+        match config.exact(keys) {
+            Some(sequence) => self.play(sequence.action, host),
+            None => *self = Leader::Idle,
+        }
+    }
+
+    /// Checks for timeouts and moves any playing action along, once per scan
+    pub fn tick(&mut self, now: Instant, config: &LeaderConfig, host: &HostConfig) {
+        match self {
+            Leader::Idle => (),
+            Leader::Listening { keys, timeout } if *timeout <= now => match config.exact(keys) {
+                Some(sequence) => self.play(sequence.action, host),
+                None => *self = Leader::Idle,
+            },
+            Leader::Listening { .. } => (),
+            Leader::Playing(state) => {
+                state.macro_transition(false, host);
+                if state.is_finished() {
+                    *self = Leader::Idle
+                }
+            }
+        }
+    }
+
+    pub fn get_stroke(&self, host: &HostConfig) -> Option<Stroke> {
+        match self {
+            Leader::Playing(state) => state.get_stroke(host),
+            _ => None,
+        }
+    }
+
+    fn play(&mut self, action: Macro, host: &HostConfig) {
+        let mut state = MacroState::new(action);
+        state.macro_transition(true, host);
+        *self = Leader::Playing(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    const SEQUENCES: &[LeaderSequence] = &[
+        LeaderSequence {
+            keys: &[Keyboard::G, Keyboard::S],
+            action: Macro::Text("gs"),
+        },
+        LeaderSequence {
+            keys: &[Keyboard::G],
+            action: Macro::Text("g"),
+        },
+        LeaderSequence {
+            keys: &[Keyboard::G, Keyboard::S, Keyboard::T],
+            action: Macro::Text("gst"),
+        },
+        LeaderSequence {
+            keys: &[Keyboard::X],
+            action: Macro::Text("x"),
+        },
+    ];
+    const CONFIG: LeaderConfig = LeaderConfig {
+        sequences: SEQUENCES,
+        timeout: 10,
+    };
+
+    #[test]
+    fn unique_match_plays_immediately() {
+        let host = HostConfig::default();
+        let mut leader = Leader::Idle;
+        leader.start(0, &CONFIG);
+        assert!(leader.is_listening());
+        leader.key(Keyboard::X, 1, &CONFIG, &host);
+        assert_eq!(leader.get_stroke(&host), Some(Stroke::key(Keyboard::X)));
+        leader.tick(2, &CONFIG, &host);
+        assert_eq!(leader.get_stroke(&host), Some(Stroke::EMPTY));
+        leader.tick(3, &CONFIG, &host);
+        assert_eq!(leader.get_stroke(&host), None);
+        assert_eq!(leader, Leader::Idle);
+    }
+
+    #[test]
+    fn unknown_prefix_rejected() {
+        let host = HostConfig::default();
+        let mut leader = Leader::Idle;
+        leader.start(0, &CONFIG);
+        leader.key(Keyboard::G, 1, &CONFIG, &host);
+        assert!(leader.is_listening());
+        leader.key(Keyboard::A, 2, &CONFIG, &host);
+        assert_eq!(leader, Leader::Idle);
+    }
+
+    #[test]
+    fn ambiguous_match_waits_for_timeout() {
+        let host = HostConfig::default();
+        let mut leader = Leader::Idle;
+        leader.start(0, &CONFIG);
+        leader.key(Keyboard::G, 5, &CONFIG, &host);
+        leader.key(Keyboard::S, 10, &CONFIG, &host);
+        leader.tick(19, &CONFIG, &host);
+        assert!(leader.is_listening());
+        leader.tick(20, &CONFIG, &host);
+        assert_eq!(leader.get_stroke(&host), Some(Stroke::key(Keyboard::G)));
+    }
+
+    #[test]
+    fn cancel() {
+        let host = HostConfig::default();
+        let mut leader = Leader::Idle;
+        leader.start(0, &CONFIG);
+        leader.key(Keyboard::G, 1, &CONFIG, &host);
+        leader.cancel();
+        assert_eq!(leader, Leader::Idle);
+    }
+
+    #[test]
+    fn timeout_without_match() {
+        let host = HostConfig::default();
+        let mut leader = Leader::Idle;
+        leader.start(0, &CONFIG);
+        leader.tick(9, &CONFIG, &host);
+        assert!(leader.is_listening());
+        leader.tick(10, &CONFIG, &host);
+        assert_eq!(leader, Leader::Idle);
+    }
+}
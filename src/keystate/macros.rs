@@ -0,0 +1,298 @@
+use super::host::HostConfig;
+use super::mods::Mods;
+use super::unicode::Unicode;
+use super::KeyState;
+use super::Keyboard;
+use super::Keyish;
+use super::Shared;
+
+/// Modifiers and (optionally) a key, held down for one report
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stroke {
+    pub mods: Mods,
+    /// `NoEventIndicated` for just the modifiers
+    pub key: Keyboard,
+}
+
+impl Stroke {
+    pub const EMPTY: Stroke = Stroke::new(Mods::NONE, Keyboard::NoEventIndicated);
+
+    pub const fn new(mods: Mods, key: Keyboard) -> Self {
+        Stroke { mods, key }
+    }
+
+    pub const fn key(key: Keyboard) -> Self {
+        Stroke::new(Mods::NONE, key)
+    }
+
+    /// The keys to report, modifiers first
+    pub fn keys(self) -> impl Iterator<Item = Keyboard> {
+        self.mods
+            .keys()
+            .chain(core::iter::once(self.key))
+            .filter(|key| *key != Keyboard::NoEventIndicated)
+    }
+}
+
+/// Something that can be played back one report at a time
+pub trait Sequence {
+    /// The stroke to send at `step`, or `None` once the sequence is over
+    fn stroke(&self, step: usize, host: &HostConfig) -> Option<Stroke>;
+}
+
+/// Each stroke is pressed for one report then released for one report, so that repeated keys
+/// register as separate presses
+impl Sequence for [Stroke] {
+    fn stroke(&self, step: usize, _host: &HostConfig) -> Option<Stroke> {
+        let stroke = self.get(step / 2)?;
+        Some(if step.is_multiple_of(2) {
+            *stroke
+        } else {
+            Stroke::EMPTY
+        })
+    }
+}
+
+/// Types the text using the host's layout, characters the layout cannot type are skipped
+impl Sequence for str {
+    fn stroke(&self, step: usize, host: &HostConfig) -> Option<Stroke> {
+        let mut remaining = step / 2;
+        for c in self.chars() {
+            let strokes = host.layout.strokes(c);
+            match strokes.get(remaining) {
+                Some(stroke) if step.is_multiple_of(2) => return Some(*stroke),
+                Some(_) => return Some(Stroke::EMPTY),
+                None => remaining -= strokes.len(),
+            }
+        }
+        None
+    }
+}
+
+/// The different kinds of macro a key can play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Macro {
+    Strokes(&'static [Stroke]),
+    Text(&'static str),
+    Unicode(Unicode),
+}
+
+impl Sequence for Macro {
+    fn stroke(&self, step: usize, host: &HostConfig) -> Option<Stroke> {
+        match self {
+            Macro::Strokes(strokes) => strokes.stroke(step, host),
+            Macro::Text(text) => text.stroke(step, host),
+            Macro::Unicode(unicode) => unicode.stroke(step, host),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unpressed<Seq> {
+    sequence: Seq,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Playing<Seq> {
+    sequence: Seq,
+    step: usize,
+}
+#[derive(Debug, PartialEq, Eq)]
+pub struct Done<Seq> {
+    sequence: Seq,
+}
+
+impl<Seq: Copy> KeyState<Unpressed<Seq>> {
+    fn play(&self) -> KeyState<Playing<Seq>> {
+        KeyState {
+            state: Playing {
+                sequence: self.state.sequence,
+                step: 0,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl<Seq: Copy> KeyState<Playing<Seq>> {
+    fn next(&self) -> KeyState<Playing<Seq>> {
+        KeyState {
+            state: Playing {
+                sequence: self.state.sequence,
+                step: self.state.step + 1,
+            },
+            shared: self.shared,
+        }
+    }
+    fn finish(&self) -> KeyState<Done<Seq>> {
+        KeyState {
+            state: Done {
+                sequence: self.state.sequence,
+            },
+            shared: self.shared,
+        }
+    }
+    fn release(&self) -> KeyState<Unpressed<Seq>> {
+        KeyState {
+            state: Unpressed {
+                sequence: self.state.sequence,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+impl<Seq: Copy> KeyState<Done<Seq>> {
+    fn release(&self) -> KeyState<Unpressed<Seq>> {
+        KeyState {
+            state: Unpressed {
+                sequence: self.state.sequence,
+            },
+            shared: self.shared,
+        }
+    }
+}
+
+/// Plays back the whole sequence once per press, even if the key is released before the end
+#[derive(Debug, PartialEq, Eq)]
+pub enum MacroState<Seq> {
+    Unpressed(KeyState<Unpressed<Seq>>),
+    Playing(KeyState<Playing<Seq>>),
+    /// Finished playing, waiting for release
+    Done(KeyState<Done<Seq>>),
+}
+
+impl<Seq> Keyish for MacroState<Seq> {
+    fn is_finished(&self) -> bool {
+        matches!(self, MacroState::Unpressed(_))
+    }
+}
+
+impl<Seq: Sequence + Copy> MacroState<Seq> {
+    pub fn new(sequence: Seq) -> Self {
+        Self::Unpressed(KeyState {
+            state: Unpressed { sequence },
+            shared: Shared,
+        })
+    }
+
+    pub fn macro_transition(&mut self, pressed: bool, host: &HostConfig) {
+        match &self {
+            Self::Unpressed(state) if pressed => *self = Self::Playing(state.play()),
+            Self::Unpressed(_state) => (),
+
+            Self::Playing(state) => {
+                let next = state.next();
+                if next.state.sequence.stroke(next.state.step, host).is_some() {
+                    *self = Self::Playing(next)
+                } else if pressed {
+                    *self = Self::Done(state.finish())
+                } else {
+                    *self = Self::Unpressed(state.release())
+                }
+            }
+
+            Self::Done(state) if !pressed => *self = Self::Unpressed(state.release()),
+            Self::Done(_state) => (),
+        }
+    }
+
+    pub fn get_stroke(&self, host: &HostConfig) -> Option<Stroke> {
+        match self {
+            Self::Playing(state) => state.state.sequence.stroke(state.state.step, host),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::host::HostLayout;
+    use super::*;
+
+    #[test]
+    fn strokes_macro() {
+        const STROKES: &[Stroke] = &[
+            Stroke::new(Mods::LCTL, Keyboard::C),
+            Stroke::key(Keyboard::C),
+        ];
+        let host = HostConfig::default();
+        let mut state = MacroState::new(Macro::Strokes(STROKES));
+
+        assert_eq!(state.get_stroke(&host), None);
+        assert!(state.is_finished());
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), Some(STROKES[0]));
+        state.macro_transition(false, &host);
+        assert_eq!(state.get_stroke(&host), Some(Stroke::EMPTY));
+        assert!(!state.is_finished());
+        state.macro_transition(false, &host);
+        assert_eq!(state.get_stroke(&host), Some(STROKES[1]));
+        state.macro_transition(false, &host);
+        assert_eq!(state.get_stroke(&host), Some(Stroke::EMPTY));
+        state.macro_transition(false, &host);
+        assert_eq!(state.get_stroke(&host), None);
+        assert!(state.is_finished());
+    }
+
+    #[test]
+    fn held_after_playing() {
+        let host = HostConfig::default();
+        let mut state = MacroState::new(Macro::Text("a"));
+
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), Some(Stroke::key(Keyboard::A)));
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), Some(Stroke::EMPTY));
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), None);
+        assert!(!state.is_finished());
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), None);
+        state.macro_transition(false, &host);
+        assert!(state.is_finished());
+        state.macro_transition(true, &host);
+        assert_eq!(state.get_stroke(&host), Some(Stroke::key(Keyboard::A)));
+    }
+
+    #[test]
+    fn text_uses_host_layout() {
+        let us = HostConfig::default();
+        let de = HostConfig {
+            layout: HostLayout::De,
+            ..Default::default()
+        };
+        let text = "y^é!";
+        let played = |host| {
+            (0..)
+                .map_while(|step| text.stroke(step, host))
+                .collect::<std::vec::Vec<_>>()
+        };
+
+        assert_eq!(
+            played(&us),
+            [
+                Stroke::key(Keyboard::Y),
+                Stroke::EMPTY,
+                Stroke::new(Mods::LSFT, Keyboard::Keyboard6),
+                Stroke::EMPTY,
+                Stroke::new(Mods::LSFT, Keyboard::Keyboard1),
+                Stroke::EMPTY,
+            ]
+        );
+        assert_eq!(
+            played(&de),
+            [
+                Stroke::key(Keyboard::Z),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::Grave),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::Space),
+                Stroke::EMPTY,
+                Stroke::new(Mods::LSFT, Keyboard::Keyboard1),
+                Stroke::EMPTY,
+            ]
+        );
+    }
+}
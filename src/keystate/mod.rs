@@ -1,11 +1,34 @@
+use custom::NoCustom;
 use heapless::Vec;
+use keycode::KeyCode;
+pub use usbd_human_interface_device::page::Consumer;
+pub use usbd_human_interface_device::page::Desktop;
 pub use usbd_human_interface_device::page::Keyboard;
 
+pub mod action;
+pub mod autoshift;
+pub mod binary;
 pub mod button;
+pub mod caps_word;
+pub mod custom;
+pub mod host;
+pub mod keycode;
 pub mod layer;
+pub mod leader;
+pub mod macros;
+pub mod mods;
 pub mod modtap;
+pub mod mouse;
+pub mod overrides;
 /// Shorthand for `use keystate::Key::*` and for using Kb, La, MT to create a keymap
 pub mod prelude;
+pub mod qmk;
+pub mod queue;
+pub mod report;
+pub mod rollover;
+pub mod settings;
+pub mod shape;
+pub mod unicode;
 
 /// Shared state
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -23,119 +46,857 @@ trait Keyish {
     fn is_finished(&self) -> bool;
 }
 
+/// Consumer keys held at once, as many as a multiple-code consumer control report holds
+pub const CONSUMER_ROLLOVER: usize = 4;
+/// System control keys held at once
+pub const SYSTEM_ROLLOVER: usize = 1;
+
 type Layer = u8;
 type Duration = u64;
 type Instant = u64;
 
-/// Shorthand for `use keystate::Key::*` and for using K, L, MT to create a keymap
-#[derive(Debug, Clone, Copy)]
-pub enum KeyShorthand {
-    Kb(Keyboard),
+/// Shorthand for `use keystate::Key::*` and for using K, L, MT to create a keymap, sending
+/// `Code`s, by default keyboard usages, with `Custom` keys, by default none
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyShorthand<Code = Keyboard, Custom = NoCustom> {
+    Kb(Code),
     La(Layer),
-    MT(Keyboard, Keyboard),
+    MT(Code, Code),
+    /// Mod-tap holding several modifiers, e.g. `MMT(Mods::HYPER, Escape)`
+    MMT(mods::Mods, Code),
+    /// Layer-tap, holds the layer like `La` when held for `mod_timeout` and taps the key otherwise
+    LT(Layer, Code),
+    /// Modified key, sends the modifiers together with the key, see `prelude::Sft` and the like
+    MK(mods::Mods, Keyboard),
+    /// Auto-shift, sends the shifted key when held for `Keymap::auto_shift_config`'s timeout
+    AS(Keyboard),
+    /// Keystroke macro
+    Mac(&'static [macros::Stroke]),
+    /// Send string, typed according to `Keymap::host`
+    Str(&'static str),
+    /// Code point, typed according to `Keymap::host`
+    Uc(char),
+    /// Selects the host's Unicode input method
+    UcMode(unicode::UnicodeMode),
+    /// Starts a leader sequence, looked up in `Keymap::leader_config`
+    Lead,
+    /// Toggles Caps Word
+    CapsWord,
+    /// Mouse key, reported in `Keymap::mouse_report`
+    Ms(mouse::MouseAction),
+    /// Consumer control key (media, volume), reported in `Keymap::consumer_keys`
+    Cc(Consumer),
+    /// System control key (power, sleep), reported in `Keymap::system_keys`
+    Sys(Desktop),
+    /// A key with a behaviour defined outside this crate, see `custom::Behaviour`
+    Custom(Custom),
 }
 
 /// Actual keys containing key-state
 #[derive(Debug, PartialEq, Eq)]
-enum Key {
-    Button(button::ButtonState),
+enum Key<Code, Custom> {
+    Button(button::ButtonState<Code>),
+    ModButton(button::ButtonState<macros::Stroke>),
     Layer(layer::LayerState),
-    ModTap(modtap::ModTapState<Keyboard, Keyboard>),
+    ModTap(modtap::ModTapState<modtap::Hold<Code>, Code>),
+    LayerTap(modtap::ModTapState<Layer, Code>),
+    AutoShift(autoshift::AutoShiftState),
+    /// A `Kb` key auto-shifting because `AutoShiftConfig::global` is set
+    GlobalAutoShift(autoshift::AutoShiftState),
+    Macro(macros::MacroState<macros::Macro>),
+    Action(action::ActionState),
+    Mouse(button::ButtonState<mouse::MouseAction>),
+    Consumer(button::ButtonState<Consumer>),
+    System(button::ButtonState<Desktop>),
+    Custom(custom::CustomState<Custom>),
 }
-impl Key {
-    fn new(key: KeyShorthand) -> Self {
+impl<Code: KeyCode, Custom: custom::Behaviour<Code>> Key<Code, Custom> {
+    fn new(key: KeyShorthand<Code, Custom>) -> Self {
         match key {
             KeyShorthand::Kb(key) => Key::Button(button::ButtonState::new(key)),
             KeyShorthand::La(layer) => Key::Layer(layer::LayerState::new(layer)),
-            KeyShorthand::MT(mod_, tap) => Key::ModTap(modtap::ModTapState::new(mod_, tap)),
+            KeyShorthand::MK(mods, key) => {
+                Key::ModButton(button::ButtonState::new(macros::Stroke::new(mods, key)))
+            }
+            KeyShorthand::MT(mod_, tap) => {
+                Key::ModTap(modtap::ModTapState::new(modtap::Hold::Key(mod_), tap))
+            }
+            KeyShorthand::MMT(mods, tap) => {
+                Key::ModTap(modtap::ModTapState::new(modtap::Hold::Mods(mods), tap))
+            }
+            KeyShorthand::LT(layer, tap) => Key::LayerTap(modtap::ModTapState::new(layer, tap)),
+            KeyShorthand::AS(key) => Key::AutoShift(autoshift::AutoShiftState::new(key)),
+            KeyShorthand::Mac(strokes) => {
+                Key::Macro(macros::MacroState::new(macros::Macro::Strokes(strokes)))
+            }
+            KeyShorthand::Str(text) => {
+                Key::Macro(macros::MacroState::new(macros::Macro::Text(text)))
+            }
+            KeyShorthand::Uc(c) => Key::Macro(macros::MacroState::new(macros::Macro::Unicode(
+                unicode::Unicode(c),
+            ))),
+            KeyShorthand::UcMode(mode) => Key::new_action(action::Action::UnicodeMode(mode)),
+            KeyShorthand::Lead => Key::new_action(action::Action::Leader),
+            KeyShorthand::CapsWord => Key::new_action(action::Action::CapsWord),
+            KeyShorthand::Ms(action) => Key::Mouse(button::ButtonState::new(action)),
+            KeyShorthand::Cc(key) => Key::Consumer(button::ButtonState::new(key)),
+            KeyShorthand::Sys(key) => Key::System(button::ButtonState::new(key)),
+            KeyShorthand::Custom(custom) => Key::Custom(custom::CustomState::new(custom)),
         }
     }
 }
-impl Keyish for Key {
+impl<Code: KeyCode, Custom: custom::Behaviour<Code>> Key<Code, Custom> {
+    fn new_action(action: action::Action) -> Self {
+        Key::Action(action::ActionState::new(action))
+    }
+
+    /// Swaps plain keys for auto-shifting ones and back, only while the key is finished
+    fn set_global_auto_shift(&mut self, global: bool) {
+        match self {
+            Key::Button(state) if global => {
+                let code = state.key();
+                let shiftable = code.to_keyboard().filter(|key| {
+                    autoshift::is_shiftable(*key) && Code::from_keyboard(*key) == Some(code)
+                });
+                if let Some(key) = shiftable {
+                    *self = Key::GlobalAutoShift(autoshift::AutoShiftState::new(key))
+                }
+            }
+            Key::GlobalAutoShift(state) if !global => {
+                if let Some(code) = Code::from_keyboard(state.key()) {
+                    *self = Key::Button(button::ButtonState::new(code))
+                }
+            }
+            _ => (),
+        }
+    }
+}
+impl<Code: KeyCode, Custom: custom::Behaviour<Code>> Keyish for Key<Code, Custom> {
     fn is_finished(&self) -> bool {
         match self {
             Key::Button(button) => button.is_finished(),
+            Key::ModButton(button) => button.is_finished(),
             Key::Layer(layer) => layer.is_finished(),
             Key::ModTap(mod_tap) => mod_tap.is_finished(),
+            Key::LayerTap(layer_tap) => layer_tap.is_finished(),
+            Key::AutoShift(auto_shift) => auto_shift.is_finished(),
+            Key::GlobalAutoShift(auto_shift) => auto_shift.is_finished(),
+            Key::Macro(macro_) => macro_.is_finished(),
+            Key::Action(action) => action.is_finished(),
+            Key::Mouse(mouse) => mouse.is_finished(),
+            Key::Consumer(consumer) => consumer.is_finished(),
+            Key::System(system) => system.is_finished(),
+            Key::Custom(custom) => custom.is_finished::<Code>(),
         }
     }
 }
 #[derive(Debug, PartialEq, Eq)]
-struct Keys<const LAYERS: usize> {
+struct Keys<Code, Custom, const LAYERS: usize> {
     current: Layer,
-    layers: [Key; LAYERS],
+    layers: [Key<Code, Custom>; LAYERS],
+    /// Pressed during a leader sequence, so not sent until released
+    captured: bool,
+    bindings: [KeyShorthand<Code, Custom>; LAYERS],
+    /// `bindings` changed while the key was held, so `layers` is rebuilt once it is finished
+    rebind: bool,
+    /// Over the layer's timings, see `Keymap::set_key_timing`
+    timing: modtap::Timing,
 }
 
 #[derive(Debug, Default)]
 pub struct KeymapFlags {
+    /// More keys were pressed in the last scan than could be reported, see `rollover`
     pub rollover: bool,
 }
+/// The keymap engine, pressing `Code`s, by default keyboard usages, see `keycode::KeyCode`, with
+/// `Custom` keys, see `custom::Behaviour`
 #[derive(Debug)]
-pub struct Keymap<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize> {
-    modtap_config: modtap::ModTapConfig,
+pub struct Keymap<
+    const SIZE: usize,
+    const LAYERS: usize,
+    const ROLLOVER: usize,
+    Code = Keyboard,
+    Custom = NoCustom,
+> {
+    /// `retro_tap` is off by default
+    pub modtap_config: modtap::ModTapConfig,
+    /// Over `modtap_config`, see `set_layer_timing`
+    layer_timings: [modtap::Timing; LAYERS],
+    /// The timeout defaults to `mod_timeout`
+    pub auto_shift_config: autoshift::AutoShiftConfig,
     layers: Vec<Layer, LAYERS>,
-    keys: [Keys<LAYERS>; SIZE],
-    pub pressed_keys: Vec<Keyboard, ROLLOVER>,
+    keys: [Keys<Code, Custom, LAYERS>; SIZE],
+    /// As of the last scan, to tell which keys were just pressed
+    keypresses: [bool; SIZE],
+    /// The index of the most recently pressed key, see `weak_stroke`
+    last_pressed: Option<usize>,
+    pub pressed_keys: Vec<Code, ROLLOVER>,
+    /// The modifiers in `pressed_keys` pressed by keys rather than typed by the keymap
+    real_mods: mods::Mods,
+    /// The modifiers taken out of `pressed_keys` by `overrides`
+    masked_mods: mods::Mods,
+    /// The auto-shift keys another key interrupted this scan, with the keys of the scan before,
+    /// see `reports`
+    tapped_report: Option<report::Report<ROLLOVER>>,
     pub flags: KeymapFlags,
+    /// The host asked for the boot protocol, so `pressed_keys` has to fit a boot report, see
+    /// `rollover::boot`
+    pub boot_protocol: bool,
+    pub host: host::HostConfig,
+    pub leader_config: leader::LeaderConfig,
+    leader: leader::Leader,
+    pub caps_word_config: caps_word::CapsWordConfig,
+    caps_word: caps_word::CapsWord,
+    /// Applied to `pressed_keys` after all keys are processed, leaving out what macros, strings
+    /// and the leader type
+    pub overrides: &'static [overrides::KeyOverride],
+    /// Layers activated by conditions on the held layers, e.g. tri-layer, see `set_layer_rules`
+    layer_rules: &'static [layer::LayerRule],
+    /// Layer used while no other is held, see `set_default_layer`
+    default_layer: Layer,
+    /// Bindings, timings or settings changed at runtime since `take_changed`
+    changed: bool,
+    pub mouse_config: mouse::MouseConfig,
+    mouse: mouse::Mouse,
+    /// The mouse report to send after this scan, if any
+    pub mouse_report: Option<mouse::MouseReport>,
+    pub consumer_keys: Vec<Consumer, CONSUMER_ROLLOVER>,
+    pub system_keys: Vec<Desktop, SYSTEM_ROLLOVER>,
+}
+
+fn press<Code, const ROLLOVER: usize>(
+    pressed_keys: &mut Vec<Code, ROLLOVER>,
+    flags: &mut KeymapFlags,
+    key: Code,
+) {
+    if pressed_keys.push(key).is_err() {
+        flags.rollover = true;
+    }
 }
 
-impl<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize> Keymap<SIZE, LAYERS, ROLLOVER> {
+/// Like `press`, keeping modifiers over other keys, see `rollover::push`
+fn press_key<Code: KeyCode, const ROLLOVER: usize>(
+    pressed_keys: &mut Vec<Code, ROLLOVER>,
+    flags: &mut KeymapFlags,
+    key: Code,
+) {
+    if !rollover::push(pressed_keys, key) {
+        flags.rollover = true;
+    }
+}
+
+/// Like `press_key`, for a key bound to a code, so a modifier counts as held, see
+/// `report::Report::real_mods`
+fn press_held<Code: KeyCode, const ROLLOVER: usize>(
+    pressed_keys: &mut Vec<Code, ROLLOVER>,
+    flags: &mut KeymapFlags,
+    real_mods: &mut mods::Mods,
+    key: Code,
+) {
+    press_key(pressed_keys, flags, key);
+    if let Some(held) = key.to_keyboard().and_then(mods::Mods::from_key) {
+        *real_mods |= held;
+    }
+}
+
+/// Moves a mod-tap or layer-tap on, noting whether another key was just pressed. Returns its
+/// tap while pressed, for a leader sequence to capture.
+fn modtap_step<ModState: Copy, Code: KeyCode>(
+    state: &mut modtap::ModTapState<ModState, Code>,
+    pressed: bool,
+    interrupted: bool,
+    now: Instant,
+    modtap_config: &modtap::ModTapConfig,
+) -> Option<Keyboard> {
+    if interrupted {
+        state.interrupt();
+    }
+    state.modtap_transition(pressed, now, modtap_config);
+    state
+        .tap_state()
+        .to_keyboard()
+        .filter(|_| !state.is_finished())
+}
+
+/// A modified key's stroke, whose modifiers only go out with the most recently pressed key, as in
+/// QMK, so that holding `Sft(K1)` does not shift the next key. A stroke of only modifiers is kept
+/// whole. See `report::Report::weak_mods`.
+fn weak_stroke(stroke: macros::Stroke, last: bool) -> macros::Stroke {
+    if last || stroke.key == Keyboard::NoEventIndicated {
+        stroke
+    } else {
+        macros::Stroke::key(stroke.key)
+    }
+}
+
+/// Presses a key the keymap typed itself, if the output has it
+fn press_keyboard<Code: KeyCode, const ROLLOVER: usize>(
+    pressed_keys: &mut Vec<Code, ROLLOVER>,
+    flags: &mut KeymapFlags,
+    key: Keyboard,
+) {
+    if let Some(code) = Code::from_keyboard(key) {
+        press_key(pressed_keys, flags, code);
+    }
+}
+
+impl<
+        const SIZE: usize,
+        const LAYERS: usize,
+        const ROLLOVER: usize,
+        Code: KeyCode,
+        Custom: custom::Behaviour<Code>,
+    > Keymap<SIZE, LAYERS, ROLLOVER, Code, Custom>
+{
     pub fn new(
-        keymap: [[KeyShorthand; SIZE]; LAYERS],
+        keymap: [[KeyShorthand<Code, Custom>; SIZE]; LAYERS],
         mod_timeout: Duration,
         tap_release: Duration,
         tap_repeat: Duration,
     ) -> Self {
-        let keys: [Keys<LAYERS>; SIZE] = core::array::from_fn(|key| Keys {
+        let keys: [Keys<Code, Custom, LAYERS>; SIZE] = core::array::from_fn(|key| Keys {
             current: 0,
             layers: core::array::from_fn(|layer| Key::new(keymap[layer][key])),
+            captured: false,
+            bindings: core::array::from_fn(|layer| keymap[layer][key]),
+            rebind: false,
+            timing: modtap::Timing::NONE,
         });
         Keymap {
             modtap_config: modtap::ModTapConfig {
                 mod_timeout,
                 tap_release,
                 tap_repeat,
+                retro_tap: false,
+            },
+            layer_timings: [modtap::Timing::NONE; LAYERS],
+            auto_shift_config: autoshift::AutoShiftConfig {
+                timeout: mod_timeout,
+                global: false,
             },
             keys,
+            keypresses: [false; SIZE],
+            last_pressed: None,
             layers: Default::default(),
             pressed_keys: Default::default(),
+            real_mods: Default::default(),
+            masked_mods: Default::default(),
+            tapped_report: None,
             flags: Default::default(),
+            boot_protocol: false,
+            host: Default::default(),
+            leader_config: Default::default(),
+            leader: Default::default(),
+            caps_word_config: Default::default(),
+            caps_word: Default::default(),
+            overrides: &[],
+            layer_rules: &[],
+            default_layer: 0,
+            changed: false,
+            mouse_config: Default::default(),
+            mouse: Default::default(),
+            mouse_report: None,
+            consumer_keys: Default::default(),
+            system_keys: Default::default(),
         }
     }
 
+    pub fn get_binding(&self, layer: Layer, index: usize) -> Option<KeyShorthand<Code, Custom>> {
+        self.keys.get(index)?.bindings.get(layer as usize).copied()
+    }
+
+    /// Changes a binding, taking effect once the key is finished if it is in use. Returns `false`
+    /// if `layer` or `index` is out of range, or the binding activates a layer out of range.
+    pub fn set_binding(
+        &mut self,
+        layer: Layer,
+        index: usize,
+        action: KeyShorthand<Code, Custom>,
+    ) -> bool {
+        if let KeyShorthand::La(target) | KeyShorthand::LT(target, _) = action {
+            if target as usize >= LAYERS {
+                return false;
+            }
+        }
+        let Some(key) = self.keys.get_mut(index) else {
+            return false;
+        };
+        let Some(binding) = key.bindings.get_mut(layer as usize) else {
+            return false;
+        };
+        *binding = action;
+        if key.layers[layer as usize].is_finished() {
+            key.layers[layer as usize] = Key::new(action);
+        } else {
+            key.rebind = true;
+        }
+        self.changed = true;
+        true
+    }
+
+    /// Sets the timings of the timed keys on `layer`, e.g. the mod-taps of a home-row layer.
+    /// Returns `false` if the layer is out of range.
+    pub fn set_layer_timing(&mut self, layer: Layer, timing: modtap::Timing) -> bool {
+        let Some(layer_timing) = self.layer_timings.get_mut(layer as usize) else {
+            return false;
+        };
+        *layer_timing = timing;
+        self.changed = true;
+        true
+    }
+
+    /// Sets the timings of the key at `index` on every layer, over those of the layer, e.g. a
+    /// longer `mod_timeout` for a pinky. Returns `false` if the index is out of range.
+    pub fn set_key_timing(&mut self, index: usize, timing: modtap::Timing) -> bool {
+        let Some(key) = self.keys.get_mut(index) else {
+            return false;
+        };
+        key.timing = timing;
+        self.changed = true;
+        true
+    }
+
+    /// Sets the layer rules. Returns `false`, leaving them unchanged, if a rule activates a layer
+    /// out of range.
+    pub fn set_layer_rules(&mut self, rules: &'static [layer::LayerRule]) -> bool {
+        if rules.iter().any(|rule| rule.layer as usize >= LAYERS) {
+            return false;
+        }
+        self.layer_rules = rules;
+        true
+    }
+
+    /// Whether bindings, timings or settings changed since the last call, e.g. to persist them
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+
+    pub fn default_layer(&self) -> Layer {
+        self.default_layer
+    }
+
+    /// Sets the layer used while no other is held. Returns `false` if it is out of range.
+    pub fn set_default_layer(&mut self, layer: Layer) -> bool {
+        if layer as usize >= LAYERS {
+            return false;
+        }
+        self.default_layer = layer;
+        self.changed = true;
+        true
+    }
+
+    pub fn settings(&self) -> settings::Settings {
+        settings::Settings {
+            default_layer: self.default_layer,
+            auto_shift: self.auto_shift_config.global,
+            layout: self.host.layout,
+            unicode: self.host.unicode,
+        }
+    }
+
+    /// Returns `false` if the default layer is out of range
+    pub fn apply_settings(&mut self, settings: settings::Settings) -> bool {
+        if settings.default_layer as usize >= LAYERS {
+            return false;
+        }
+        self.default_layer = settings.default_layer;
+        self.auto_shift_config.global = settings.auto_shift;
+        self.host.layout = settings.layout;
+        self.host.unicode = settings.unicode;
+        self.changed = true;
+        true
+    }
+
+    /// The most reports `reports` gives after one scan
+    pub const MAX_REPORTS: usize = 2;
+
+    pub fn caps_word_active(&self) -> bool {
+        self.caps_word.is_on()
+    }
+
     pub fn process(&mut self, keypresses: [bool; SIZE], now: Instant) {
-        self.pressed_keys.clear();
-        for (key, pressed) in self.keys.iter_mut().zip(keypresses) {
-            if key.layers[key.current as usize].is_finished() {
-                key.current = self.layers.last().copied().unwrap_or(0)
+        let previous_keys = core::mem::take(&mut self.pressed_keys);
+        let previous_mods = (self.real_mods, self.masked_mods);
+        let mut tapped_keys: Vec<Code, ROLLOVER> = Vec::new();
+        self.consumer_keys.clear();
+        self.system_keys.clear();
+        self.real_mods = mods::Mods::NONE;
+        self.flags = KeymapFlags::default();
+        self.leader.tick(now, &self.leader_config, &self.host);
+        let mut mouse_keys = mouse::MouseKeys::default();
+        // Typed by macros and the leader, added after `overrides` which are only for pressed keys
+        let mut typed: Vec<Keyboard, ROLLOVER> = Vec::new();
+        let mut interrupted = false;
+        for (index, (pressed, before)) in keypresses.iter().zip(self.keypresses).enumerate() {
+            if *pressed && !before {
+                interrupted = true;
+                self.last_pressed = Some(index);
+            }
+        }
+        self.keypresses = keypresses;
+        for (index, (key, pressed)) in self.keys.iter_mut().zip(keypresses).enumerate() {
+            let last = self.last_pressed == Some(index);
+            let finished = key.layers[key.current as usize].is_finished();
+            if finished && key.rebind {
+                key.layers[key.current as usize] = Key::new(key.bindings[key.current as usize]);
+                key.rebind = false;
+            }
+            if finished {
+                key.current =
+                    layer::current_layer(self.default_layer, &self.layers, self.layer_rules);
+                key.layers[key.current as usize]
+                    .set_global_auto_shift(self.auto_shift_config.global);
             };
+            let capture = finished && self.leader.is_listening();
+            let modtap_config = self
+                .modtap_config
+                .with(&self.layer_timings[key.current as usize])
+                .with(&key.timing);
             match &mut key.layers[key.current as usize] {
                 Key::Button(state) => {
                     state.key_transition(pressed);
-                    if let Some(key) = state.get_key() {
-                        if self.pressed_keys.push(key).is_err() {
-                            self.flags.rollover = true;
+                    if let Some(pressed_key) = state.get_key() {
+                        if let Some(typed) = pressed_key.to_keyboard().filter(|_| capture) {
+                            key.captured = true;
+                            self.leader.key(typed, now, &self.leader_config, &self.host);
+                        }
+                        if !key.captured {
+                            press_held(
+                                &mut self.pressed_keys,
+                                &mut self.flags,
+                                &mut self.real_mods,
+                                pressed_key,
+                            );
+                        }
+                    }
+                }
+                Key::ModButton(state) => {
+                    state.key_transition(pressed);
+                    if let Some(stroke) = state.get_key() {
+                        if capture {
+                            key.captured = true;
+                            self.leader
+                                .key(stroke.key, now, &self.leader_config, &self.host);
+                        }
+                        if !key.captured {
+                            for pressed_key in weak_stroke(stroke, last).keys() {
+                                press_keyboard(
+                                    &mut self.pressed_keys,
+                                    &mut self.flags,
+                                    pressed_key,
+                                );
+                            }
                         }
                     }
                 }
                 Key::Layer(state) => state.layer_transition(pressed, &mut self.layers),
                 Key::ModTap(state) => {
-                    state.modtap_transition(pressed, now, &self.modtap_config);
-                    if let Some(key) = state.get_key() {
-                        if self.pressed_keys.push(key).is_err() {
-                            self.flags.rollover = true;
+                    let typed = modtap_step(state, pressed, interrupted, now, &modtap_config);
+                    if let Some(typed) = typed.filter(|_| capture) {
+                        key.captured = true;
+                        self.leader.key(typed, now, &self.leader_config, &self.host);
+                    }
+                    if !key.captured {
+                        for pressed_key in state.get_key() {
+                            press_held(
+                                &mut self.pressed_keys,
+                                &mut self.flags,
+                                &mut self.real_mods,
+                                pressed_key,
+                            );
+                        }
+                    }
+                }
+                Key::LayerTap(state) => {
+                    let was_held = state.held().is_some();
+                    let typed = modtap_step(state, pressed, interrupted, now, &modtap_config);
+                    if let Some(typed) = typed.filter(|_| capture) {
+                        key.captured = true;
+                        self.leader.key(typed, now, &self.leader_config, &self.host);
+                    }
+                    let layer = state.mod_state();
+                    match (was_held, state.held().is_some()) {
+                        (false, true) => {
+                            self.layers.retain(|held| *held != layer);
+                            self.layers.push(layer).ok();
+                        }
+                        (true, false) => self.layers.retain(|held| *held != layer),
+                        _ => (),
+                    }
+                    if let Some(pressed_key) = state.tapped() {
+                        if !key.captured {
+                            press_held(
+                                &mut self.pressed_keys,
+                                &mut self.flags,
+                                &mut self.real_mods,
+                                pressed_key,
+                            );
                         }
                     }
                 }
+                Key::AutoShift(state) | Key::GlobalAutoShift(state) => {
+                    let tapped = interrupted && pressed && state.interrupt();
+                    state.autoshift_transition(pressed, now, &self.auto_shift_config);
+                    if capture && !state.is_finished() {
+                        key.captured = true;
+                        self.leader
+                            .key(state.key(), now, &self.leader_config, &self.host);
+                    }
+                    if !key.captured {
+                        let stroke = state.get_stroke().map(|stroke| weak_stroke(stroke, last));
+                        for key in stroke.into_iter().flat_map(|s| s.keys()) {
+                            if let Some(code) = Code::from_keyboard(key).filter(|_| tapped) {
+                                tapped_keys.push(code).ok();
+                            }
+                            press_keyboard(&mut self.pressed_keys, &mut self.flags, key);
+                        }
+                    }
+                }
+                Key::Macro(state) => {
+                    state.macro_transition(pressed, &self.host);
+                    if capture && !state.is_finished() {
+                        key.captured = true;
+                        self.leader.cancel();
+                    }
+                    if !key.captured {
+                        for key in state
+                            .get_stroke(&self.host)
+                            .into_iter()
+                            .flat_map(|s| s.keys())
+                        {
+                            if typed.push(key).is_err() {
+                                self.flags.rollover = true;
+                            }
+                        }
+                    }
+                }
+                Key::Mouse(state) => {
+                    state.key_transition(pressed);
+                    if let Some(action) = state.get_key() {
+                        mouse_keys.press(action);
+                    }
+                }
+                Key::Consumer(state) => {
+                    state.key_transition(pressed);
+                    if let Some(pressed_key) = state.get_key() {
+                        press(&mut self.consumer_keys, &mut self.flags, pressed_key);
+                    }
+                }
+                Key::System(state) => {
+                    state.key_transition(pressed);
+                    if let Some(pressed_key) = state.get_key() {
+                        press(&mut self.system_keys, &mut self.flags, pressed_key);
+                    }
+                }
+                Key::Custom(state) => {
+                    let mut sink = custom::Pressed {
+                        keys: &mut self.pressed_keys,
+                        flags: &mut self.flags,
+                        real_mods: &mut self.real_mods,
+                    };
+                    let mut context = custom::Context {
+                        now,
+                        layers: &mut self.layers,
+                        sink: &mut sink,
+                    };
+                    state.custom_transition(pressed, &mut context);
+                }
+                Key::Action(state) => match state.action_transition(pressed) {
+                    Some(action::Action::UnicodeMode(mode)) => {
+                        self.host.unicode = mode;
+                        self.changed = true;
+                    }
+                    Some(action::Action::Leader) => self.leader.start(now, &self.leader_config),
+                    Some(action::Action::CapsWord) => {
+                        self.caps_word.toggle(now, &self.caps_word_config)
+                    }
+                    None => (),
+                },
+            }
+            if key.layers[key.current as usize].is_finished() {
+                key.captured = false;
+            }
+        }
+        for key in self
+            .leader
+            .get_stroke(&self.host)
+            .into_iter()
+            .flat_map(|s| s.keys())
+        {
+            if typed.push(key).is_err() {
+                self.flags.rollover = true;
+            }
+        }
+        self.mouse_report = self.mouse.update(&mouse_keys, now, &self.mouse_config);
+        self.masked_mods = overrides::masked(self.overrides, &self.pressed_keys);
+        if !overrides::apply(self.overrides, &mut self.pressed_keys) {
+            self.flags.rollover = true;
+        }
+        for key in typed {
+            press_keyboard(&mut self.pressed_keys, &mut self.flags, key);
+        }
+        if !self.caps_word.apply(
+            now,
+            &self.caps_word_config,
+            &self.host,
+            &mut self.pressed_keys,
+        ) {
+            self.flags.rollover = true;
+        }
+        if self.boot_protocol && rollover::boot(&mut self.pressed_keys, self.flags.rollover) {
+            self.flags.rollover = true;
+        }
+        // The interrupted keys in a report of their own, as hosts read the keys of one report in
+        // usage order rather than in the order they were pressed
+        self.tapped_report = (!tapped_keys.is_empty()).then(|| {
+            let (real_mods, masked_mods) = previous_mods;
+            let keys = previous_keys.iter().chain(&tapped_keys);
+            report_of(keys, real_mods, masked_mods)
+        });
+    }
+
+    /// The keyboard keys pressed after this scan, for a HID report
+    pub fn report(&self) -> report::Report<ROLLOVER> {
+        report_of(self.pressed_keys.iter(), self.real_mods, self.masked_mods)
+    }
+
+    /// The reports to send after this scan in order, `report` and at most `MAX_REPORTS - 1`
+    /// before it
+    pub fn reports(&self) -> impl Iterator<Item = report::Report<ROLLOVER>> {
+        self.tapped_report
+            .clone()
+            .into_iter()
+            .chain(core::iter::once(self.report()))
+    }
+}
+
+/// A report of `keys`, with the modifiers among them sent as weak modifiers unless they are real
+fn report_of<'a, Code: KeyCode + 'a, const ROLLOVER: usize>(
+    keys: impl Iterator<Item = &'a Code>,
+    real_mods: mods::Mods,
+    masked_mods: mods::Mods,
+) -> report::Report<ROLLOVER> {
+    let mut report = report::Report {
+        real_mods,
+        masked_mods,
+        ..Default::default()
+    };
+    let mut sent = mods::Mods::NONE;
+    for key in keys.filter_map(|code| code.to_keyboard()) {
+        match mods::Mods::from_key(key) {
+            Some(held) => sent |= held,
+            None if key == Keyboard::NoEventIndicated || report.keys.contains(&key) => (),
+            None => {
+                report.keys.push(key).ok();
+            }
+        }
+    }
+    report.weak_mods = sent.difference(real_mods.difference(masked_mods));
+    report
+}
+
+/// The binary format holds keyboard usages
+impl<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize>
+    Keymap<SIZE, LAYERS, ROLLOVER, Keyboard>
+{
+    /// Decodes a keymap encoded by `encode`
+    pub fn decode(data: &[u8]) -> Result<Self, binary::DecodeError> {
+        let (bindings, timings) = binary::decode(data)?;
+        let config = timings.config;
+        let mut keymap = Self::new(
+            bindings,
+            config.mod_timeout,
+            config.tap_release,
+            config.tap_repeat,
+        );
+        keymap.load(bindings, timings);
+        keymap.changed = false;
+        Ok(keymap)
+    }
+
+    fn load(
+        &mut self,
+        bindings: [[KeyShorthand; SIZE]; LAYERS],
+        timings: binary::Timings<SIZE, LAYERS>,
+    ) {
+        for (layer, bindings) in bindings.into_iter().enumerate() {
+            for (index, binding) in bindings.into_iter().enumerate() {
+                self.set_binding(layer as Layer, index, binding);
             }
         }
+        self.modtap_config = timings.config;
+        self.layer_timings = timings.layers;
+        for (key, timing) in self.keys.iter_mut().zip(timings.keys) {
+            key.timing = timing;
+        }
+    }
+
+    /// Encodes the bindings and timings into `out`, returning the encoded length
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, binary::EncodeError> {
+        let bindings: [[KeyShorthand; SIZE]; LAYERS] = core::array::from_fn(|layer| {
+            core::array::from_fn(|index| self.keys[index].bindings[layer])
+        });
+        let timings = binary::Timings {
+            config: self.modtap_config,
+            layers: self.layer_timings,
+            keys: core::array::from_fn(|index| self.keys[index].timing),
+        };
+        binary::encode(&bindings, &timings, out)
+    }
+
+    /// Hash of the bindings and timings, using `buf` to encode them. Taken of the compiled keymap
+    /// as the `origin` for `persist` and `restore`.
+    pub fn fingerprint(&self, buf: &mut [u8]) -> Result<u32, binary::EncodeError> {
+        let len = self.encode(buf)?;
+        Ok(binary::crc32(&buf[..len]))
+    }
+
+    /// Encodes `origin` and the settings followed by the bindings and timings, for a settings store
+    pub fn persist(&self, origin: u32, out: &mut [u8]) -> Result<usize, binary::EncodeError> {
+        let (header, out) = out
+            .split_first_chunk_mut::<{ PERSIST_HEADER_LEN }>()
+            .ok_or(binary::EncodeError::BufferTooSmall)?;
+        let (stored_origin, settings) = header.split_at_mut(4);
+        stored_origin.copy_from_slice(&origin.to_le_bytes());
+        settings.copy_from_slice(&self.settings().to_bytes());
+        Ok(PERSIST_HEADER_LEN + self.encode(out)?)
+    }
+
+    /// Applies data encoded by `persist` onto this keymap, keeping what is not stored, like layer
+    /// rules and overrides. Data persisted with another `origin`, i.e. from before the compiled
+    /// keymap changed, is rejected. On error the keymap is left as it was.
+    pub fn restore(&mut self, origin: u32, data: &[u8]) -> Result<(), binary::DecodeError> {
+        let (header, data) = data
+            .split_first_chunk::<{ PERSIST_HEADER_LEN }>()
+            .ok_or(binary::DecodeError::Truncated)?;
+        let (stored_origin, settings) = header.split_at(4);
+        if stored_origin != origin.to_le_bytes() {
+            return Err(binary::DecodeError::OtherKeymap);
+        }
+        let (bindings, timings) = binary::decode(data)?;
+        let settings = settings::Settings::from_bytes(settings.try_into().unwrap())
+            .filter(|settings| (settings.default_layer as usize) < LAYERS)
+            .ok_or(binary::DecodeError::InvalidSettings)?;
+        self.load(bindings, timings);
+        self.apply_settings(settings);
+        self.changed = false;
+        Ok(())
     }
 }
 
+/// Bytes `Keymap::persist` writes before the encoded keymap: the origin and the settings
+const PERSIST_HEADER_LEN: usize = 4 + settings::Settings::LEN;
+
+/// Space for `Keymap::persist` of a keymap with `size` keys and `layers` layers
+pub const fn max_persist_len(size: usize, layers: usize) -> usize {
+    PERSIST_HEADER_LEN + binary::max_len(size, layers)
+}
+
 #[cfg(test)]
 mod tests {
     use super::prelude::*;
     use super::Key;
+    use super::Keyish;
     use super::Keymap;
     use super::Keys;
 
@@ -155,7 +916,11 @@ mod tests {
             keymap.keys[0],
             Keys {
                 current: 0,
-                layers: [Key::new(Kb(A)), Key::new(Kb(B))]
+                layers: [Key::new(Kb(A)), Key::new(Kb(B))],
+                captured: false,
+                bindings: [Kb(A), Kb(B)],
+                rebind: false,
+                timing: super::modtap::Timing::NONE,
             }
         );
 
@@ -171,7 +936,11 @@ mod tests {
             keymap.keys[0],
             Keys {
                 current: 0,
-                layers: [Key::new(Kb(A)), Key::new(Kb(B))]
+                layers: [Key::new(Kb(A)), Key::new(Kb(B))],
+                captured: false,
+                bindings: [Kb(A), Kb(B)],
+                rebind: false,
+                timing: super::modtap::Timing::NONE,
             }
         );
 
@@ -187,7 +956,11 @@ mod tests {
                         state: super::button::Pressed { key: B },
                         shared: super::Shared
                     }))
-                ]
+                ],
+                captured: false,
+                bindings: [Kb(A), Kb(B)],
+                rebind: false,
+                timing: super::modtap::Timing::NONE,
             }
         );
 
@@ -200,4 +973,515 @@ mod tests {
         keymap.process([true, false, true], 8);
         assert_eq!(keymap.pressed_keys, [B, M]);
     }
+
+    #[test]
+    fn send_string() {
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[Str("Zy"), Kb(A)]], 2, 4, 6);
+        keymap.host.layout = super::host::HostLayout::De;
+
+        keymap.process([true, true], 1);
+        assert_eq!(keymap.pressed_keys, [A, LeftShift, Y]);
+        keymap.process([false, true], 2);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, true], 3);
+        assert_eq!(keymap.pressed_keys, [A, Z]);
+        keymap.process([false, true], 4);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false], 5);
+        assert_eq!(keymap.pressed_keys, []);
+        assert!(keymap.keys[0].layers[0].is_finished());
+    }
+
+    #[test]
+    fn leader_swallows_sequence() {
+        use super::leader::{LeaderConfig, LeaderSequence};
+        use super::macros::Macro;
+        const SEQUENCES: &[LeaderSequence] = &[LeaderSequence {
+            keys: &[G, S],
+            action: Macro::Text("x"),
+        }];
+        let mut keymap: Keymap<3, 1, 32> = Keymap::new([[Lead, Kb(G), MT(LSFT, S)]], 2, 4, 6);
+        keymap.leader_config = LeaderConfig {
+            sequences: SEQUENCES,
+            timeout: 100,
+        };
+
+        keymap.process([true, false, false], 1);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, true, false], 2);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, true, true], 3);
+        assert_eq!(keymap.pressed_keys, [X]);
+        keymap.process([false, true, true], 4);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, true, true], 5);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, false, false], 6);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, true, false], 7);
+        assert_eq!(keymap.pressed_keys, [G]);
+    }
+
+    #[test]
+    fn leader_cancelled_by_macro() {
+        use super::leader::{LeaderConfig, LeaderSequence};
+        use super::macros::Macro;
+        const SEQUENCES: &[LeaderSequence] = &[LeaderSequence {
+            keys: &[G, S],
+            action: Macro::Text("x"),
+        }];
+        let mut keymap: Keymap<4, 1, 32> = Keymap::new([[Lead, Kb(G), Str("hi"), Kb(S)]], 2, 4, 6);
+        keymap.leader_config = LeaderConfig {
+            sequences: SEQUENCES,
+            timeout: 100,
+        };
+
+        keymap.process([true, false, false, false], 1);
+        keymap.process([false, true, false, false], 2);
+        keymap.process([false, false, true, false], 3);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, false, true, false], 4);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, false, false, false], 5);
+        keymap.process([false, false, false, false], 6);
+        keymap.process([false, false, false, true], 7);
+        assert_eq!(keymap.pressed_keys, [S]);
+    }
+
+    #[test]
+    fn key_override() {
+        use super::macros::Stroke;
+        use super::mods::Mods;
+        use super::overrides::KeyOverride;
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[Kb(LSFT), Kb(DeleteBackspace)]], 2, 4, 6);
+        const OVERRIDES: &[KeyOverride] = &[KeyOverride::new(
+            Mods::LSFT,
+            DeleteBackspace,
+            Stroke::key(DeleteForward),
+        )];
+        keymap.overrides = OVERRIDES;
+
+        keymap.process([true, false], 1);
+        assert_eq!(keymap.pressed_keys, [LeftShift]);
+        keymap.process([true, true], 2);
+        assert_eq!(keymap.pressed_keys, [DeleteForward]);
+        keymap.process([true, false], 3);
+        assert_eq!(keymap.pressed_keys, [LeftShift]);
+        keymap.process([false, true], 4);
+        assert_eq!(keymap.pressed_keys, [DeleteBackspace]);
+    }
+
+    #[test]
+    fn key_override_skips_typed_keys() {
+        use super::macros::Stroke;
+        use super::mods::Mods;
+        use super::overrides::KeyOverride;
+        let mut keymap: Keymap<1, 1, 32> = Keymap::new([[Str("<")]], 2, 4, 6);
+        const OVERRIDES: &[KeyOverride] =
+            &[KeyOverride::new(Mods::LSFT, Comma, Stroke::key(Semicolon))];
+        keymap.overrides = OVERRIDES;
+
+        keymap.process([true], 1);
+        assert_eq!(keymap.pressed_keys, [LeftShift, Comma]);
+        assert_eq!(keymap.report().masked_mods, Mods::NONE);
+    }
+
+    #[test]
+    fn caps_word() {
+        let mut keymap: Keymap<3, 1, 32> = Keymap::new([[CapsWord, Kb(A), Kb(Space)]], 2, 4, 6);
+
+        keymap.process([true, false, false], 1);
+        assert!(keymap.caps_word_active());
+        keymap.process([false, true, false], 2);
+        assert_eq!(keymap.pressed_keys, [A, LeftShift]);
+        keymap.process([false, true, true], 3);
+        assert_eq!(keymap.pressed_keys, [A, Space]);
+        assert!(!keymap.caps_word_active());
+        keymap.process([false, true, false], 4);
+        assert_eq!(keymap.pressed_keys, [A]);
+    }
+
+    #[test]
+    fn auto_shift() {
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[AS(A), Kb(B)]], 2, 4, 6);
+
+        keymap.process([true, true], 1);
+        assert_eq!(keymap.pressed_keys, [B]);
+        keymap.process([false, true], 2);
+        assert_eq!(keymap.pressed_keys, [A, B]);
+        keymap.process([true, false], 3);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, false], 5);
+        assert_eq!(keymap.pressed_keys, [LeftShift, A]);
+        keymap.process([false, false], 6);
+
+        keymap.auto_shift_config.global = true;
+        keymap.process([false, true], 7);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, true], 9);
+        assert_eq!(keymap.pressed_keys, [LeftShift, B]);
+        keymap.process([false, false], 10);
+        keymap.auto_shift_config.global = false;
+        keymap.process([false, true], 11);
+        assert_eq!(keymap.pressed_keys, [B]);
+    }
+
+    #[test]
+    fn auto_shift_rolled() {
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[Kb(Space), Kb(A)]], 2, 4, 6);
+        keymap.auto_shift_config.global = true;
+
+        keymap.process([false, true], 1);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, true], 2);
+        assert_eq!(keymap.pressed_keys, [Space, A]);
+        keymap.process([true, true], 5);
+        assert_eq!(keymap.pressed_keys, [Space, A]);
+        keymap.process([true, false], 6);
+        assert_eq!(keymap.pressed_keys, [Space]);
+    }
+
+    #[test]
+    fn auto_shift_rolled_reports() {
+        use super::queue::ReportQueue;
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[AS(S), Kb(A)]], 2, 4, 6);
+        let mut queue: ReportQueue<super::report::Report<32>, 8> = ReportQueue::default();
+        for (pressed, now) in [([true, false], 1), ([true, true], 2), ([false, false], 3)] {
+            keymap.process(pressed, now);
+            for report in keymap.reports() {
+                queue.push(report);
+            }
+        }
+        let mut sent = std::vec::Vec::new();
+        queue.flush(|report| {
+            sent.push(report.keys.clone());
+            true
+        });
+        assert_eq!(sent, [[S].as_slice(), &[S, A], &[]]);
+    }
+
+    #[test]
+    fn mouse_keys() {
+        use super::mouse::{MouseConfig, MouseProfile, MouseReport};
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[Ms(MS_R), Ms(BTN1)]], 2, 4, 6);
+        keymap.mouse_config = MouseConfig {
+            pointer: MouseProfile::Constant { speed: 2.0 },
+            ..Default::default()
+        };
+
+        keymap.process([false, false], 1);
+        assert_eq!(keymap.mouse_report, None);
+        keymap.process([true, false], 2);
+        assert_eq!(keymap.pressed_keys, []);
+        assert_eq!(
+            keymap.mouse_report,
+            Some(MouseReport {
+                x: 2,
+                ..Default::default()
+            })
+        );
+        keymap.process([false, true], 3);
+        assert_eq!(
+            keymap.mouse_report,
+            Some(MouseReport {
+                buttons: 1,
+                ..Default::default()
+            })
+        );
+        keymap.process([false, true], 4);
+        assert_eq!(keymap.mouse_report, None);
+    }
+
+    #[test]
+    fn consumer_and_system_keys() {
+        let mut keymap: Keymap<3, 1, 32> = Keymap::new([[Cc(VOLU), Sys(SLEP), Kb(A)]], 2, 4, 6);
+
+        keymap.process([true, false, true], 1);
+        assert_eq!(keymap.pressed_keys, [A]);
+        assert_eq!(keymap.consumer_keys, [VOLU]);
+        assert_eq!(keymap.system_keys, []);
+        keymap.process([false, true, false], 2);
+        assert_eq!(keymap.pressed_keys, []);
+        assert_eq!(keymap.consumer_keys, []);
+        assert_eq!(keymap.system_keys, [SLEP]);
+    }
+
+    #[test]
+    fn modified_keys() {
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new(
+            [[Sft(K1), MK(Mods::LCTL.union(Mods::LALT), DeleteForward)]],
+            2,
+            4,
+            6,
+        );
+
+        keymap.process([true, false], 1);
+        assert_eq!(keymap.pressed_keys, [LeftShift, Keyboard1]);
+        keymap.process([true, true], 2);
+        assert_eq!(
+            keymap.pressed_keys,
+            [Keyboard1, LeftControl, LeftAlt, DeleteForward]
+        );
+        let report = keymap.report();
+        assert_eq!(report.weak_mods, Mods::LCTL | Mods::LALT);
+        assert_eq!(report.keys, [Keyboard1, DeleteForward]);
+        keymap.process([false, true], 3);
+        assert_eq!(keymap.pressed_keys, [LeftControl, LeftAlt, DeleteForward]);
+        keymap.process([false, false], 4);
+        assert_eq!(keymap.pressed_keys, []);
+    }
+
+    #[test]
+    fn tri_layer() {
+        use super::layer::{LayerCondition::*, LayerRule};
+        let mut keymap: Keymap<3, 4, 32> = Keymap::new(
+            [
+                [La(1), La(2), Kb(A)],
+                [La(1), La(2), Kb(B)],
+                [La(1), La(2), Kb(C)],
+                [La(1), La(2), Kb(D)],
+            ],
+            2,
+            4,
+            6,
+        );
+        const RULES: &[LayerRule] = &[LayerRule::new(All(&[Active(1), Active(2)]), 3)];
+        const INVALID: &[LayerRule] = &[LayerRule::new(Active(1), 4)];
+        assert!(!keymap.set_layer_rules(INVALID));
+        assert!(keymap.set_layer_rules(RULES));
+
+        keymap.process([true, false, true], 1);
+        assert_eq!(keymap.pressed_keys, [B]);
+        keymap.process([true, true, false], 2);
+        keymap.process([true, true, true], 3);
+        assert_eq!(keymap.pressed_keys, [D]);
+        keymap.process([false, true, false], 4);
+        keymap.process([false, true, true], 5);
+        assert_eq!(keymap.pressed_keys, [C]);
+    }
+
+    #[test]
+    fn layer_tap() {
+        let mut keymap: Keymap<2, 2, 32> =
+            Keymap::new([[LT(1, Space), Kb(A)], [Kb(___), Kb(B)]], 2, 4, 6);
+
+        keymap.process([true, false], 1);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, false], 3);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, true], 4);
+        assert_eq!(keymap.pressed_keys, [B]);
+        keymap.process([false, false], 5);
+        keymap.process([false, true], 6);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false], 7);
+
+        keymap.process([true, false], 10);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([false, false], 11);
+        assert_eq!(keymap.pressed_keys, [Space]);
+    }
+
+    #[test]
+    fn retro_tap() {
+        let mut keymap: Keymap<2, 1, 32> = Keymap::new([[MT(LSFT, F), Kb(J)]], 2, 4, 6);
+        keymap.modtap_config.retro_tap = true;
+
+        keymap.process([true, false], 1);
+        keymap.process([true, false], 3);
+        assert_eq!(keymap.pressed_keys, [LSFT]);
+        keymap.process([false, false], 4);
+        assert_eq!(keymap.pressed_keys, [F]);
+        keymap.process([false, false], 20);
+
+        keymap.process([true, false], 21);
+        keymap.process([true, true], 23);
+        assert_eq!(keymap.pressed_keys, [LSFT, J]);
+        keymap.process([true, false], 24);
+        keymap.process([false, false], 25);
+        assert_eq!(keymap.pressed_keys, []);
+    }
+
+    #[test]
+    fn mods_tap() {
+        let mut keymap: Keymap<2, 1, 32> =
+            Keymap::new([[MMT(super::mods::Mods::HYPER, Escape), Kb(T)]], 2, 4, 6);
+        keymap.process([true, false], 1);
+        keymap.process([true, true], 3);
+        assert_eq!(keymap.pressed_keys, [LCTL, LSFT, LALT, LGUI, T]);
+        assert_eq!(keymap.report().real_mods, super::mods::Mods::HYPER);
+        keymap.process([false, false], 4);
+        assert_eq!(keymap.pressed_keys, []);
+
+        keymap.process([true, false], 10);
+        keymap.process([false, false], 11);
+        assert_eq!(keymap.pressed_keys, [Escape]);
+    }
+
+    #[test]
+    fn timings() {
+        let mut keymap: Keymap<3, 2, 32> = Keymap::new(
+            [
+                [MT(LSFT, A), MT(LCTL, S), La(1)],
+                [MT(LSFT, A), MT(LCTL, S), Kb(___)],
+            ],
+            2,
+            4,
+            6,
+        );
+        let slow = super::modtap::Timing {
+            mod_timeout: Some(5),
+            ..super::modtap::Timing::NONE
+        };
+        assert!(keymap.set_key_timing(0, slow));
+        assert!(keymap.take_changed());
+        assert!(!keymap.set_key_timing(3, slow));
+        assert!(!keymap.take_changed());
+        assert!(keymap.set_layer_timing(
+            1,
+            super::modtap::Timing {
+                mod_timeout: Some(10),
+                ..super::modtap::Timing::NONE
+            }
+        ));
+        assert!(keymap.take_changed());
+        assert!(!keymap.set_layer_timing(2, slow));
+        assert!(!keymap.take_changed());
+
+        keymap.process([true, true, false], 1);
+        keymap.process([true, true, false], 3);
+        assert_eq!(keymap.pressed_keys, [LCTL]);
+        keymap.process([true, true, false], 6);
+        assert_eq!(keymap.pressed_keys, [LSFT, LCTL]);
+        keymap.process([false, false, false], 7);
+        keymap.process([false, false, false], 20);
+
+        keymap.process([false, false, true], 21);
+        keymap.process([true, true, true], 22);
+        keymap.process([true, true, true], 27);
+        assert_eq!(keymap.pressed_keys, [LSFT]);
+        keymap.process([true, true, true], 32);
+        assert_eq!(keymap.pressed_keys, [LSFT, LCTL]);
+    }
+
+    #[test]
+    fn set_binding() {
+        let mut keymap: Keymap<2, 2, 32> = Keymap::new([[Kb(A), La(1)], [Kb(B), Kb(___)]], 2, 4, 6);
+        assert_eq!(keymap.get_binding(1, 0), Some(Kb(B)));
+        assert_eq!(keymap.get_binding(2, 0), None);
+        assert_eq!(keymap.get_binding(0, 2), None);
+        assert!(!keymap.set_binding(2, 0, Kb(C)));
+        assert!(!keymap.set_binding(0, 2, Kb(C)));
+        assert!(!keymap.set_binding(0, 0, La(2)));
+        assert!(!keymap.set_binding(0, 0, LT(9, C)));
+        assert_eq!(keymap.get_binding(0, 0), Some(Kb(A)));
+
+        assert!(keymap.set_binding(1, 0, Kb(C)));
+        keymap.process([true, false], 1);
+        assert_eq!(keymap.pressed_keys, [A]);
+        assert!(keymap.set_binding(0, 0, Kb(D)));
+        assert_eq!(keymap.get_binding(0, 0), Some(Kb(D)));
+        keymap.process([true, false], 2);
+        assert_eq!(keymap.pressed_keys, [A]);
+        keymap.process([false, false], 3);
+        assert_eq!(keymap.pressed_keys, []);
+        keymap.process([true, false], 4);
+        assert_eq!(keymap.pressed_keys, [D]);
+        keymap.process([false, true], 5);
+        keymap.process([true, true], 6);
+        assert_eq!(keymap.pressed_keys, [C]);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut keymap: Keymap<2, 2, 32> = Keymap::new([[Kb(A), La(1)], [Kb(B), Kb(___)]], 2, 4, 6);
+        keymap.set_binding(1, 1, Kb(C));
+        keymap.modtap_config.retro_tap = true;
+        let slow = super::modtap::Timing {
+            mod_timeout: Some(3),
+            ..super::modtap::Timing::NONE
+        };
+        keymap.set_layer_timing(1, slow);
+        keymap.set_key_timing(0, slow);
+        let mut data = [0; super::binary::max_len(2, 2)];
+        let len = keymap.encode(&mut data).unwrap();
+
+        let mut decoded: Keymap<2, 2, 32> = Keymap::decode(&data[..len]).unwrap();
+        assert_eq!(decoded.get_binding(1, 1), Some(Kb(C)));
+        assert_eq!(decoded.modtap_config, keymap.modtap_config);
+        assert_eq!(decoded.layer_timings, [super::modtap::Timing::NONE, slow]);
+        assert_eq!(
+            decoded.keys.each_ref().map(|key| key.timing),
+            [slow, super::modtap::Timing::NONE]
+        );
+        decoded.process([false, true], 1);
+        decoded.process([true, true], 2);
+        assert_eq!(decoded.pressed_keys, [B]);
+    }
+
+    #[test]
+    fn persist_restore() {
+        use super::macros::Stroke;
+        use super::mods::Mods;
+        use super::overrides::KeyOverride;
+        use super::settings::Settings;
+        let mut keymap: Keymap<2, 2, 32> = Keymap::new([[Kb(A), La(1)], [Kb(B), Kb(___)]], 2, 4, 6);
+        assert!(!keymap.take_changed());
+        assert!(!keymap.set_default_layer(2));
+        assert!(!keymap.take_changed());
+        assert!(keymap.set_default_layer(1));
+        assert_eq!(keymap.default_layer(), 1);
+        assert!(keymap.take_changed());
+        assert!(!keymap.apply_settings(Settings {
+            default_layer: 2,
+            ..Default::default()
+        }));
+        let settings = Settings {
+            default_layer: 1,
+            auto_shift: true,
+            ..Default::default()
+        };
+        assert!(keymap.apply_settings(settings));
+        assert!(keymap.take_changed());
+        assert!(!keymap.take_changed());
+        keymap.set_binding(0, 0, Kb(C));
+        assert!(keymap.take_changed());
+
+        let mut data = [0; super::max_persist_len(2, 2)];
+        const OVERRIDES: &[KeyOverride] =
+            &[KeyOverride::new(Mods::NONE, Comma, Stroke::key(Semicolon))];
+        let compiled = || {
+            let mut keymap: Keymap<2, 2, 32> =
+                Keymap::new([[Kb(A), La(1)], [Kb(B), Kb(___)]], 2, 4, 6);
+            keymap.overrides = OVERRIDES;
+            keymap
+        };
+        let origin = compiled().fingerprint(&mut data).unwrap();
+        let len = keymap.persist(origin, &mut data).unwrap();
+        let mut restored = compiled();
+        restored.restore(origin, &data[..len]).unwrap();
+        assert_eq!(restored.settings(), settings);
+        assert!(!restored.take_changed());
+        restored.process([true, false], 1);
+        restored.process([false, false], 2);
+        assert_eq!(restored.pressed_keys, [B]);
+        // What is not stored stays as compiled
+        restored.set_binding(1, 1, Kb(Comma));
+        restored.process([false, true], 3);
+        restored.process([false, false], 4);
+        assert_eq!(restored.pressed_keys, [Semicolon]);
+
+        let mut other = compiled();
+        assert_eq!(
+            other.restore(origin ^ 1, &data[..len]),
+            Err(super::binary::DecodeError::OtherKeymap)
+        );
+        assert_eq!(other.default_layer(), 0);
+        data[4] = 2;
+        assert_eq!(
+            other.restore(origin, &data[..len]),
+            Err(super::binary::DecodeError::InvalidSettings)
+        );
+        assert_eq!(other.get_binding(0, 0), Some(Kb(A)));
+    }
 }
@@ -0,0 +1,148 @@
+//! Sets of modifiers, laid out like the modifier byte of a HID keyboard report
+
+use super::Keyboard;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mods(u8);
+
+impl Mods {
+    pub const NONE: Mods = Mods(0);
+    pub const LCTL: Mods = Mods(1 << 0);
+    pub const LSFT: Mods = Mods(1 << 1);
+    pub const LALT: Mods = Mods(1 << 2);
+    pub const LGUI: Mods = Mods(1 << 3);
+    pub const RCTL: Mods = Mods(1 << 4);
+    pub const RSFT: Mods = Mods(1 << 5);
+    pub const RALT: Mods = Mods(1 << 6);
+    pub const RGUI: Mods = Mods(1 << 7);
+
+    /// Either shift
+    pub const SHIFT: Mods = Mods(Self::LSFT.0 | Self::RSFT.0);
+    /// AltGr, as used by most non-US layouts for the third level
+    pub const ALTGR: Mods = Self::RALT;
+    /// Control, Shift and Alt, for shortcuts no application uses
+    pub const MEH: Mods = Mods(Self::LCTL.0 | Self::LSFT.0 | Self::LALT.0);
+    /// Control, Shift, Alt and GUI
+    pub const HYPER: Mods = Mods(Self::MEH.0 | Self::LGUI.0);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Mods(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn union(self, other: Mods) -> Self {
+        Mods(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Mods) -> Self {
+        Mods(self.0 & other.0)
+    }
+
+    pub const fn difference(self, other: Mods) -> Self {
+        Mods(self.0 & !other.0)
+    }
+
+    /// All of `other` is in `self`
+    pub const fn contains(self, other: Mods) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Some of `other` is in `self`
+    pub const fn intersects(self, other: Mods) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Both the left and right versions of each modifier in the set
+    pub const fn either_side(self) -> Self {
+        let sides = (self.0 | self.0 >> 4) & 0x0F;
+        Mods(sides | sides << 4)
+    }
+
+    /// The modifier for a modifier key, `None` for any other key
+    pub fn from_key(key: Keyboard) -> Option<Self> {
+        let code: u8 = key.into();
+        let left_control: u8 = Keyboard::LeftControl.into();
+        let right_gui: u8 = Keyboard::RightGUI.into();
+        (left_control..=right_gui)
+            .contains(&code)
+            .then(|| Mods(1 << (code - left_control)))
+    }
+
+    /// The modifiers among some pressed keys
+    pub fn from_keys(keys: impl IntoIterator<Item = Keyboard>) -> Self {
+        keys.into_iter()
+            .filter_map(Mods::from_key)
+            .fold(Mods::NONE, Mods::union)
+    }
+
+    /// The modifier keys in this set, left before right, in HID order
+    pub fn keys(self) -> impl Iterator<Item = Keyboard> {
+        let left_control: u8 = Keyboard::LeftControl.into();
+        (0..8)
+            .filter(move |bit| self.0 & (1 << bit) != 0)
+            .map(move |bit| Keyboard::from(left_control + bit))
+    }
+}
+
+impl core::ops::BitOr for Mods {
+    type Output = Mods;
+
+    fn bitor(self, other: Mods) -> Mods {
+        self.union(other)
+    }
+}
+
+impl core::ops::BitOrAssign for Mods {
+    fn bitor_assign(&mut self, other: Mods) {
+        *self = self.union(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn from_key() {
+        assert_eq!(Mods::from_key(Keyboard::LeftControl), Some(Mods::LCTL));
+        assert_eq!(Mods::from_key(Keyboard::RightShift), Some(Mods::RSFT));
+        assert_eq!(Mods::from_key(Keyboard::RightGUI), Some(Mods::RGUI));
+        assert_eq!(Mods::from_key(Keyboard::A), None);
+        assert_eq!(Mods::from_key(Keyboard::ExSel), None);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Mods::NONE.keys().collect::<Vec<_>>(), []);
+        assert_eq!(
+            (Mods::RALT | Mods::LSFT).keys().collect::<Vec<_>>(),
+            [Keyboard::LeftShift, Keyboard::RightAlt]
+        );
+    }
+
+    #[test]
+    fn set_operations() {
+        let mods = Mods::LCTL | Mods::LSFT;
+        assert!(mods.contains(Mods::LCTL));
+        assert!(!mods.contains(Mods::LCTL | Mods::LALT));
+        assert!(mods.intersects(Mods::SHIFT));
+        assert!(!mods.intersects(Mods::RSFT));
+        assert_eq!(mods.difference(Mods::SHIFT), Mods::LCTL);
+        assert_eq!(mods.intersection(Mods::SHIFT), Mods::LSFT);
+        assert_eq!(Mods::RSFT.either_side(), Mods::SHIFT);
+        assert_eq!(
+            Mods::from_keys([Keyboard::A, Keyboard::LeftAlt, Keyboard::RightGUI]),
+            Mods::LALT | Mods::RGUI
+        );
+    }
+}
@@ -1,11 +1,12 @@
+use super::keycode::KeyCode;
+use super::mods::Mods;
 use super::Duration;
 use super::Instant;
 use super::KeyState;
-use super::Keyboard;
 use super::Keyish;
 use super::Shared;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ModTapConfig {
     /// Time before a held key becomes a `mod` instead of a `tap`
     pub mod_timeout: Duration,
@@ -15,6 +16,38 @@ pub struct ModTapConfig {
     /// Time during which another press counts as a multi-tap (i.e. goes into the tap-state without
     /// waiting for the `mod_timeout`
     pub tap_repeat: Duration,
+    /// Send the `tap` when a key held past `mod_timeout` is released without another key being
+    /// pressed meanwhile
+    pub retro_tap: bool,
+}
+
+impl ModTapConfig {
+    /// This config with the timings `timing` sets in place of its own
+    pub fn with(&self, timing: &Timing) -> ModTapConfig {
+        ModTapConfig {
+            mod_timeout: timing.mod_timeout.unwrap_or(self.mod_timeout),
+            tap_release: timing.tap_release.unwrap_or(self.tap_release),
+            tap_repeat: timing.tap_repeat.unwrap_or(self.tap_repeat),
+            retro_tap: self.retro_tap,
+        }
+    }
+}
+
+/// Timings for some keys or a layer, `None` to keep the `ModTapConfig` ones. Only mod-tap and
+/// layer-tap keys use them, auto-shift keys keep `AutoShiftConfig::timeout`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub mod_timeout: Option<Duration>,
+    pub tap_release: Option<Duration>,
+    pub tap_repeat: Option<Duration>,
+}
+
+impl Timing {
+    pub const NONE: Timing = Timing {
+        mod_timeout: None,
+        tap_release: None,
+        tap_repeat: None,
+    };
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -27,11 +60,14 @@ pub struct ModTapWait<ModState, TapState> {
     mod_state: ModState,
     tap_state: TapState,
     tap_timeout: Instant,
+    /// Another key was pressed since this one, so it is no retro tap
+    interrupted: bool,
 }
 #[derive(Debug, PartialEq, Eq)]
 pub struct Mod<ModState, TapState> {
     mod_state: ModState,
     tap_state: TapState,
+    interrupted: bool,
 }
 #[derive(Debug, PartialEq, Eq)]
 pub struct Tap<ModState, TapState> {
@@ -59,6 +95,7 @@ impl<ModState: Copy, TapState: Copy> KeyState<Unpressed<ModState, TapState>> {
                 mod_state: self.state.mod_state,
                 tap_state: self.state.tap_state,
                 tap_timeout,
+                interrupted: false,
             },
             shared: self.shared,
         }
@@ -71,6 +108,7 @@ impl<ModState: Copy, TapState: Copy> KeyState<ModTapWait<ModState, TapState>> {
             state: Mod {
                 mod_state: self.state.mod_state,
                 tap_state: self.state.tap_state,
+                interrupted: self.state.interrupted,
             },
             shared: self.shared,
         }
@@ -87,6 +125,21 @@ impl<ModState: Copy, TapState: Copy> KeyState<Mod<ModState, TapState>> {
             shared: self.shared,
         }
     }
+    fn retro_tap(
+        &self,
+        release_timeout: Instant,
+        again_timeout: Instant,
+    ) -> KeyState<Tap<ModState, TapState>> {
+        KeyState {
+            state: Tap {
+                mod_state: self.state.mod_state,
+                tap_state: self.state.tap_state,
+                release_timeout,
+                again_timeout,
+            },
+            shared: self.shared,
+        }
+    }
 }
 
 impl<ModState: Copy, TapState: Copy> KeyState<ModTapWait<ModState, TapState>> {
@@ -147,6 +200,7 @@ impl<ModState: Copy, TapState: Copy> KeyState<Tap<ModState, TapState>> {
                 mod_state: self.state.mod_state,
                 tap_state: self.state.tap_state,
                 tap_timeout,
+                interrupted: false,
             },
             shared: self.shared,
         }
@@ -203,6 +257,56 @@ impl<ModState: Copy, TapState: Copy> ModTapState<ModState, TapState> {
         })
     }
 
+    /// What the key sends when tapped
+    pub fn tap_state(&self) -> TapState {
+        match self {
+            Self::Unpressed(state) => state.state.tap_state,
+            Self::Wait(state) => state.state.tap_state,
+            Self::Mod(state) => state.state.tap_state,
+            Self::Tap(state) => state.state.tap_state,
+            Self::DoubleTapWait(state) => state.state.tap_state,
+            Self::DoubleTap(state) => state.state.tap_state,
+        }
+    }
+
+    /// What the key does when held
+    pub fn mod_state(&self) -> ModState {
+        match self {
+            Self::Unpressed(state) => state.state.mod_state,
+            Self::Wait(state) => state.state.mod_state,
+            Self::Mod(state) => state.state.mod_state,
+            Self::Tap(state) => state.state.mod_state,
+            Self::DoubleTapWait(state) => state.state.mod_state,
+            Self::DoubleTap(state) => state.state.mod_state,
+        }
+    }
+
+    /// The hold side, while it is active
+    pub fn held(&self) -> Option<ModState> {
+        match self {
+            Self::Mod(state) => Some(state.state.mod_state),
+            _ => None,
+        }
+    }
+
+    /// The tap side, while it is being sent
+    pub fn tapped(&self) -> Option<TapState> {
+        match self {
+            Self::Tap(state) => Some(state.state.tap_state),
+            Self::DoubleTap(state) => Some(state.state.tap_state),
+            _ => None,
+        }
+    }
+
+    /// Another key was pressed, so releasing this one after `mod_timeout` sends no retro tap
+    pub fn interrupt(&mut self) {
+        match self {
+            Self::Wait(state) => state.state.interrupted = true,
+            Self::Mod(state) => state.state.interrupted = true,
+            _ => (),
+        }
+    }
+
     pub fn modtap_transition(&mut self, pressed: bool, now: Instant, modtap_config: &ModTapConfig) {
         match &self {
             Self::Unpressed(state) if pressed => {
@@ -221,6 +325,12 @@ impl<ModState: Copy, TapState: Copy> ModTapState<ModState, TapState> {
             }
             Self::Wait(_state) => (),
 
+            Self::Mod(state) if !pressed && modtap_config.retro_tap && !state.state.interrupted => {
+                *self = Self::Tap(state.retro_tap(
+                    now + modtap_config.tap_release,
+                    now + modtap_config.tap_repeat,
+                ))
+            }
             Self::Mod(state) if !pressed => *self = Self::Unpressed(state.release()),
             Self::Mod(_state) => (),
 
@@ -256,18 +366,32 @@ impl<ModState: Copy, TapState: Copy> ModTapState<ModState, TapState> {
     }
 }
 
-impl ModTapState<Keyboard, Keyboard> {
-    pub fn get_key(&self) -> Option<Keyboard> {
-        match self {
-            // None
-            Self::Unpressed(_) => None,
-            Self::Wait(_) => None,
-            Self::DoubleTapWait(_) => None,
-            // Some
-            Self::Mod(state) => Some(state.state.mod_state),
-            Self::Tap(state) => Some(state.state.tap_state),
-            Self::DoubleTap(state) => Some(state.state.tap_state),
-        }
+/// What a mod-tap holds after `mod_timeout`, a key or several modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hold<Code> {
+    Key(Code),
+    Mods(Mods),
+}
+
+impl<Code: KeyCode> Hold<Code> {
+    /// The key, or each modifier
+    pub fn keys(self) -> impl Iterator<Item = Code> {
+        let (key, mods) = match self {
+            Hold::Key(key) => (Some(key), Mods::NONE),
+            Hold::Mods(mods) => (None, mods),
+        };
+        key.into_iter()
+            .chain(mods.keys().filter_map(Code::from_keyboard))
+    }
+}
+
+impl<Code: KeyCode> ModTapState<Hold<Code>, Code> {
+    /// The held keys, or the tapped key
+    pub fn get_key(&self) -> impl Iterator<Item = Code> {
+        self.held()
+            .into_iter()
+            .flat_map(Hold::keys)
+            .chain(self.tapped())
     }
 }
 
@@ -275,190 +399,254 @@ impl ModTapState<Keyboard, Keyboard> {
 mod tests {
     extern crate std;
 
+    use super::super::Keyboard;
     use super::*;
 
     #[test]
     fn get_keys_modtap_nothing() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 1,
             tap_release: 2,
             tap_repeat: 3,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 1, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 2, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 3, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 4, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
     }
 
     #[test]
     fn get_keys_modtap_tap() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 2,
             tap_release: 4, // Held for 4 ticks
             tap_repeat: 6,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 1, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 2, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 3, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 4, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 5, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         for i in 0..modtap_config.tap_repeat {
             state.modtap_transition(false, 6 + i, &modtap_config);
-            assert_eq!(state.get_key(), None);
+            assert_eq!(state.get_key().next(), None);
         }
     }
 
     #[test]
     fn get_keys_modtap_mod() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 2, // Needs to be held for at least 2 ticks
             tap_release: 4,
             tap_repeat: 6,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 1, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 2, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 3, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 4, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 5, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 6, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(false, 7, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         for i in 0..modtap_config.tap_repeat {
             state.modtap_transition(false, 8 + i, &modtap_config);
-            assert_eq!(state.get_key(), None);
+            assert_eq!(state.get_key().next(), None);
         }
     }
 
     #[test]
     /// Double-tap before tap_release
     fn get_keys_modtap_double_tap_quick() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 2,
             tap_release: 4, // Held for 4 ticks
             tap_repeat: 6,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 1, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(true, 2, &modtap_config);
         // Simulate release of double-tap
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         for i in 0..modtap_config.tap_repeat {
             state.modtap_transition(true, 3 + i, &modtap_config);
-            assert_eq!(state.get_key(), Some(Keyboard::T));
+            assert_eq!(state.get_key().next(), Some(Keyboard::T));
         }
         for i in modtap_config.tap_repeat..2 * modtap_config.tap_repeat {
             state.modtap_transition(false, 3 + i, &modtap_config);
-            assert_eq!(state.get_key(), None);
+            assert_eq!(state.get_key().next(), None);
         }
     }
 
     #[test]
     /// Double-tap after tap_release before tap_repeat ends
     fn get_keys_modtap_double_tap_slow() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 2,
             tap_release: 4, // Held for 4 ticks
             tap_repeat: 6,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(false, 1, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 2, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 3, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 4, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         state.modtap_transition(false, 5, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 6, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::T));
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
         for i in 0..modtap_config.tap_repeat {
             state.modtap_transition(true, 7 + i, &modtap_config);
-            assert_eq!(state.get_key(), Some(Keyboard::T));
+            assert_eq!(state.get_key().next(), Some(Keyboard::T));
         }
         for i in modtap_config.tap_repeat..2 * modtap_config.tap_repeat {
             state.modtap_transition(false, 7 + i, &modtap_config);
-            assert_eq!(state.get_key(), None);
+            assert_eq!(state.get_key().next(), None);
         }
     }
 
     #[test]
     fn get_keys_modtap_double_mod() {
-        let mut state = ModTapState::<Keyboard, Keyboard>::new(Keyboard::M, Keyboard::T);
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
         let modtap_config = ModTapConfig {
             mod_timeout: 2, // Needs to be held for at least 2 ticks
             tap_release: 4,
             tap_repeat: 6,
+            retro_tap: false,
         };
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 0, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 1, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 2, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 3, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 4, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 5, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 6, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(false, 7, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 8, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 9, &modtap_config);
-        assert_eq!(state.get_key(), None);
+        assert_eq!(state.get_key().next(), None);
         state.modtap_transition(true, 10, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 11, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 12, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 13, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         state.modtap_transition(true, 14, &modtap_config);
-        assert_eq!(state.get_key(), Some(Keyboard::M));
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
         for i in 0..modtap_config.tap_repeat {
             state.modtap_transition(false, 15 + i, &modtap_config);
-            assert_eq!(state.get_key(), None);
+            assert_eq!(state.get_key().next(), None);
         }
     }
+
+    #[test]
+    fn get_keys_mods_tap() {
+        let mut state = ModTapState::new(Hold::Mods(Mods::LCTL | Mods::LSFT), Keyboard::T);
+        let modtap_config = ModTapConfig {
+            mod_timeout: 2,
+            tap_release: 4,
+            tap_repeat: 6,
+            retro_tap: false,
+        };
+        let keys =
+            |state: &ModTapState<Hold<Keyboard>, Keyboard>| state.get_key().collect::<Vec<_>>();
+        state.modtap_transition(true, 0, &modtap_config);
+        assert_eq!(keys(&state), []);
+        state.modtap_transition(true, 2, &modtap_config);
+        assert_eq!(keys(&state), [Keyboard::LeftControl, Keyboard::LeftShift]);
+        state.modtap_transition(false, 3, &modtap_config);
+        assert_eq!(keys(&state), []);
+
+        state.modtap_transition(true, 20, &modtap_config);
+        state.modtap_transition(false, 21, &modtap_config);
+        assert_eq!(keys(&state), [Keyboard::T]);
+    }
+
+    #[test]
+    fn get_keys_modtap_retro_tap() {
+        let mut state = ModTapState::new(Hold::Key(Keyboard::M), Keyboard::T);
+        let modtap_config = ModTapConfig {
+            mod_timeout: 2,
+            tap_release: 4,
+            tap_repeat: 6,
+            retro_tap: true,
+        };
+        state.modtap_transition(true, 0, &modtap_config);
+        state.modtap_transition(true, 2, &modtap_config);
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
+        state.modtap_transition(false, 3, &modtap_config);
+        assert_eq!(state.get_key().next(), Some(Keyboard::T));
+        state.modtap_transition(false, 7, &modtap_config);
+        state.modtap_transition(false, 9, &modtap_config);
+        assert_eq!(state.get_key().next(), None);
+
+        // Used as a modifier
+        state.modtap_transition(true, 20, &modtap_config);
+        state.modtap_transition(true, 22, &modtap_config);
+        state.interrupt();
+        state.modtap_transition(false, 23, &modtap_config);
+        assert_eq!(state.get_key().next(), None);
+
+        // Another key pressed before `mod_timeout`
+        state.modtap_transition(true, 40, &modtap_config);
+        state.interrupt();
+        state.modtap_transition(true, 42, &modtap_config);
+        assert_eq!(state.get_key().next(), Some(Keyboard::M));
+        state.modtap_transition(false, 43, &modtap_config);
+        assert_eq!(state.get_key().next(), None);
+    }
 }
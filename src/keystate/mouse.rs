@@ -0,0 +1,326 @@
+//! Mouse keys: moving the pointer and scrolling from the keyboard
+
+use super::Duration;
+use super::Instant;
+
+/// What a mouse key does while held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// Buttons 1 to `BUTTONS`, i.e. left, right, middle, back and forward
+    Button(u8),
+}
+
+/// Mouse buttons a key can press
+pub const BUTTONS: u8 = 5;
+
+/// The mouse keys held during one scan
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseKeys {
+    pub buttons: u8,
+    x: i8,
+    y: i8,
+    wheel: i8,
+    pan: i8,
+}
+
+impl MouseKeys {
+    pub fn press(&mut self, action: MouseAction) {
+        match action {
+            MouseAction::Up => self.y -= 1,
+            MouseAction::Down => self.y += 1,
+            MouseAction::Left => self.x -= 1,
+            MouseAction::Right => self.x += 1,
+            MouseAction::WheelUp => self.wheel += 1,
+            MouseAction::WheelDown => self.wheel -= 1,
+            MouseAction::WheelLeft => self.pan -= 1,
+            MouseAction::WheelRight => self.pan += 1,
+            MouseAction::Button(button @ 1..=BUTTONS) => self.buttons |= 1 << (button - 1),
+            MouseAction::Button(_) => (),
+        }
+    }
+
+    fn pointer(&self) -> (i8, i8) {
+        (self.x.signum(), self.y.signum())
+    }
+
+    fn scroll(&self) -> (i8, i8) {
+        (self.wheel.signum(), self.pan.signum())
+    }
+}
+
+/// A relative mouse report, like a HID wheel mouse's
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseReport {
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+    pub wheel: i8,
+    pub pan: i8,
+}
+
+/// How the speed changes while a direction is held, speeds are in counts per report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseProfile {
+    Constant {
+        speed: f32,
+    },
+    /// From `start` up to `max` over `ramp` reports
+    Linear {
+        start: f32,
+        max: f32,
+        ramp: u16,
+    },
+    /// Speeds up by `accel` each report up to `max`, and when released keeps going, losing
+    /// `friction` (between 0 and 1) of its speed each report
+    Inertia {
+        accel: f32,
+        max: f32,
+        friction: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouseConfig {
+    pub pointer: MouseProfile,
+    pub wheel: MouseProfile,
+    /// Time between reports while moving, `0` for every scan
+    pub interval: Duration,
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        MouseConfig {
+            pointer: MouseProfile::Linear {
+                start: 0.5,
+                max: 4.0,
+                ramp: 500,
+            },
+            wheel: MouseProfile::Constant { speed: 0.02 },
+            interval: 0,
+        }
+    }
+}
+
+/// Speed below which inertia stops
+const STOPPED: f32 = 0.05;
+
+fn abs(value: f32) -> f32 {
+    if value < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Movement along two axes with sub-count precision
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Motion {
+    velocity: (f32, f32),
+    remainder: (f32, f32),
+    /// Reports since a direction was first held
+    held: u16,
+}
+
+impl Motion {
+    pub fn is_moving(&self) -> bool {
+        self.velocity != (0.0, 0.0)
+    }
+
+    /// Advances by one report in the held direction, returning the counts moved
+    pub fn step(&mut self, profile: &MouseProfile, direction: (i8, i8)) -> (i8, i8) {
+        let (dx, dy) = (direction.0 as f32, direction.1 as f32);
+        // Keep diagonals at the same speed as straight lines
+        let scale = if dx != 0.0 && dy != 0.0 {
+            core::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        };
+        self.held = if direction == (0, 0) {
+            0
+        } else {
+            self.held.saturating_add(1)
+        };
+        self.velocity = match *profile {
+            MouseProfile::Constant { speed } => (dx * speed * scale, dy * speed * scale),
+            MouseProfile::Linear { start, max, ramp } => {
+                let progress = (self.held.min(ramp) as f32 / ramp.max(1) as f32).min(1.0);
+                let speed = start + (max - start) * progress;
+                (dx * speed * scale, dy * speed * scale)
+            }
+            MouseProfile::Inertia {
+                accel,
+                max,
+                friction,
+            } => {
+                let axis = |velocity: f32, direction: f32| {
+                    let velocity = if direction == 0.0 {
+                        velocity * (1.0 - friction)
+                    } else {
+                        (velocity + direction * accel * scale).clamp(-max * scale, max * scale)
+                    };
+                    if direction == 0.0 && abs(velocity) < STOPPED {
+                        0.0
+                    } else {
+                        velocity
+                    }
+                };
+                (axis(self.velocity.0, dx), axis(self.velocity.1, dy))
+            }
+        };
+        if !self.is_moving() {
+            self.remainder = (0.0, 0.0);
+        }
+        let counts = |velocity: f32, remainder: &mut f32| {
+            let total = velocity + *remainder;
+            let counts = (total as i32).clamp(-127, 127);
+            *remainder = total - counts as f32;
+            counts as i8
+        };
+        (
+            counts(self.velocity.0, &mut self.remainder.0),
+            counts(self.velocity.1, &mut self.remainder.1),
+        )
+    }
+}
+
+/// The keymap-wide mouse, turning held mouse keys into reports
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Mouse {
+    pointer: Motion,
+    wheel: Motion,
+    buttons: u8,
+    next_report: Instant,
+}
+
+impl Mouse {
+    /// The report to send this scan, if any
+    pub fn update(
+        &mut self,
+        keys: &MouseKeys,
+        now: Instant,
+        config: &MouseConfig,
+    ) -> Option<MouseReport> {
+        let buttons_changed = keys.buttons != self.buttons;
+        self.buttons = keys.buttons;
+        let active = keys.pointer() != (0, 0)
+            || keys.scroll() != (0, 0)
+            || self.pointer.is_moving()
+            || self.wheel.is_moving();
+        if !active {
+            self.pointer = Motion::default();
+            self.wheel = Motion::default();
+        }
+        let due = active && self.next_report <= now;
+        if !due && !buttons_changed {
+            return None;
+        }
+        let mut report = MouseReport {
+            buttons: keys.buttons,
+            ..Default::default()
+        };
+        if due {
+            self.next_report = now + config.interval;
+            (report.x, report.y) = self.pointer.step(&config.pointer, keys.pointer());
+            (report.wheel, report.pan) = self.wheel.step(&config.wheel, keys.scroll());
+        }
+        let moved = (report.x, report.y, report.wheel, report.pan) != (0, 0, 0, 0);
+        (moved || buttons_changed).then_some(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn steps(profile: MouseProfile, directions: &[(i8, i8)]) -> Vec<(i8, i8)> {
+        let mut motion = Motion::default();
+        directions
+            .iter()
+            .map(|direction| motion.step(&profile, *direction))
+            .collect()
+    }
+
+    #[test]
+    fn constant() {
+        let profile = MouseProfile::Constant { speed: 1.5 };
+        assert_eq!(
+            steps(profile, &[(1, 0), (1, 0), (1, 0), (-1, 0), (0, 0)]),
+            [(1, 0), (2, 0), (1, 0), (-1, 0), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn constant_diagonal() {
+        let profile = MouseProfile::Constant { speed: 10.0 };
+        assert_eq!(steps(profile, &[(1, -1)]), [(7, -7)]);
+    }
+
+    #[test]
+    fn linear() {
+        let profile = MouseProfile::Linear {
+            start: 1.0,
+            max: 4.0,
+            ramp: 3,
+        };
+        assert_eq!(
+            steps(profile, &[(0, 1), (0, 1), (0, 1), (0, 1), (0, 0), (0, 1)]),
+            [(0, 2), (0, 3), (0, 4), (0, 4), (0, 0), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn inertia() {
+        let profile = MouseProfile::Inertia {
+            accel: 2.0,
+            max: 5.0,
+            friction: 0.5,
+        };
+        assert_eq!(
+            steps(profile, &[(1, 0), (1, 0), (1, 0), (1, 0), (0, 0), (0, 0)]),
+            [(2, 0), (4, 0), (5, 0), (5, 0), (2, 0), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn mouse_reports() {
+        let config = MouseConfig {
+            pointer: MouseProfile::Constant { speed: 3.0 },
+            wheel: MouseProfile::Constant { speed: 1.0 },
+            interval: 10,
+        };
+        let mut mouse = Mouse::default();
+        let mut keys = MouseKeys::default();
+        assert_eq!(mouse.update(&keys, 0, &config), None);
+
+        keys.press(MouseAction::Right);
+        keys.press(MouseAction::WheelDown);
+        let report = MouseReport {
+            x: 3,
+            wheel: -1,
+            ..Default::default()
+        };
+        assert_eq!(mouse.update(&keys, 1, &config), Some(report));
+        assert_eq!(mouse.update(&keys, 5, &config), None);
+        assert_eq!(mouse.update(&keys, 11, &config), Some(report));
+
+        keys = MouseKeys::default();
+        keys.press(MouseAction::Button(1));
+        let report = MouseReport {
+            buttons: 1,
+            ..Default::default()
+        };
+        assert_eq!(mouse.update(&keys, 12, &config), Some(report));
+        assert_eq!(mouse.update(&keys, 30, &config), None);
+    }
+}
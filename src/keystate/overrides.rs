@@ -0,0 +1,122 @@
+//! Key overrides: while some modifiers are held, a key sends something else instead
+
+use heapless::Vec;
+
+use super::keycode::KeyCode;
+use super::macros::Stroke;
+use super::mods::Mods;
+use super::rollover;
+use super::Keyboard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOverride {
+    /// Modifiers which all have to be held, either side counts
+    pub mods: Mods,
+    pub key: Keyboard,
+    /// Sent instead of `key`, with the held `mods` masked out
+    pub replacement: Stroke,
+}
+
+impl KeyOverride {
+    pub const fn new(mods: Mods, key: Keyboard, replacement: Stroke) -> Self {
+        KeyOverride {
+            mods,
+            key,
+            replacement,
+        }
+    }
+
+    fn matches<Code: KeyCode>(&self, held: Mods, keys: &[Code]) -> bool {
+        held.either_side().contains(self.mods.either_side())
+            && keys.iter().any(|code| code.to_keyboard() == Some(self.key))
+    }
+}
+
+/// The first override matching the pressed keys, with the held modifiers it masks
+fn find<'a, Code: KeyCode>(
+    overrides: &'a [KeyOverride],
+    keys: &[Code],
+) -> Option<(&'a KeyOverride, Mods)> {
+    let held = Mods::from_keys(keys.iter().filter_map(|code| code.to_keyboard()));
+    let over = overrides.iter().find(|over| over.matches(held, keys))?;
+    Some((over, held.intersection(over.mods.either_side())))
+}
+
+/// The held modifiers `apply` will take out of the pressed keys
+pub fn masked<Code: KeyCode>(overrides: &[KeyOverride], keys: &[Code]) -> Mods {
+    find(overrides, keys).map_or(Mods::NONE, |(_, masked)| masked)
+}
+
+/// Rewrites the pressed keys using the first matching override, returns `false` if the
+/// replacement did not fit
+pub fn apply<Code: KeyCode, const N: usize>(
+    overrides: &[KeyOverride],
+    keys: &mut Vec<Code, N>,
+) -> bool {
+    let Some((over, masked)) = find(overrides, keys) else {
+        return true;
+    };
+    keys.retain(|code| {
+        code.to_keyboard().is_none_or(|key| {
+            key != over.key && !Mods::from_key(key).is_some_and(|mods| masked.contains(mods))
+        })
+    });
+    let mut fits = true;
+    for key in over.replacement.keys().filter_map(Code::from_keyboard) {
+        if !keys.contains(&key) {
+            fits &= rollover::push(keys, key);
+        }
+    }
+    fits
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use Keyboard::*;
+
+    const OVERRIDES: &[KeyOverride] = &[
+        KeyOverride::new(Mods::LSFT, DeleteBackspace, Stroke::key(DeleteForward)),
+        KeyOverride::new(Mods::LSFT, Comma, Stroke::key(Semicolon)),
+        KeyOverride::new(Mods::LCTL.union(Mods::LALT), Q, Stroke::new(Mods::LGUI, Q)),
+    ];
+
+    fn applied(keys: &[Keyboard]) -> std::vec::Vec<Keyboard> {
+        let mut keys: Vec<Keyboard, 8> = Vec::from_slice(keys).unwrap();
+        assert!(apply(OVERRIDES, &mut keys));
+        keys.into_iter().collect()
+    }
+
+    #[test]
+    fn shift_backspace() {
+        assert_eq!(applied(&[LeftShift, DeleteBackspace]), [DeleteForward]);
+        assert_eq!(applied(&[DeleteBackspace, RightShift]), [DeleteForward]);
+        assert_eq!(applied(&[DeleteBackspace]), [DeleteBackspace]);
+        assert_eq!(applied(&[LeftShift, A]), [LeftShift, A]);
+    }
+
+    #[test]
+    fn other_mods_kept() {
+        assert_eq!(
+            applied(&[LeftControl, LeftShift, Comma]),
+            [LeftControl, Semicolon]
+        );
+    }
+
+    #[test]
+    fn masked_mods() {
+        assert_eq!(
+            masked(OVERRIDES, &[LeftControl, RightShift, Comma]),
+            Mods::RSFT
+        );
+        assert_eq!(masked(OVERRIDES, &[LeftControl, Comma]), Mods::NONE);
+    }
+
+    #[test]
+    fn all_mods_needed() {
+        assert_eq!(applied(&[LeftControl, Q]), [LeftControl, Q]);
+        assert_eq!(applied(&[RightAlt, LeftControl, Q]), [LeftGUI, Q]);
+    }
+}
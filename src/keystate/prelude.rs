@@ -2,6 +2,9 @@
 pub use super::KeyShorthand::*;
 pub use super::Keyboard::*;
 
+pub use super::mods::Mods;
+use super::KeyShorthand;
+
 pub const NOP: super::Keyboard = NoEventIndicated;
 pub const ___: super::Keyboard = NoEventIndicated;
 
@@ -33,3 +36,70 @@ pub const UP: super::Keyboard = UpArrow;
 pub const DOWN: super::Keyboard = DownArrow;
 
 pub const BSL: super::Keyboard = Backslash;
+
+pub const MS_U: super::mouse::MouseAction = super::mouse::MouseAction::Up;
+pub const MS_D: super::mouse::MouseAction = super::mouse::MouseAction::Down;
+pub const MS_L: super::mouse::MouseAction = super::mouse::MouseAction::Left;
+pub const MS_R: super::mouse::MouseAction = super::mouse::MouseAction::Right;
+pub const WH_U: super::mouse::MouseAction = super::mouse::MouseAction::WheelUp;
+pub const WH_D: super::mouse::MouseAction = super::mouse::MouseAction::WheelDown;
+pub const WH_L: super::mouse::MouseAction = super::mouse::MouseAction::WheelLeft;
+pub const WH_R: super::mouse::MouseAction = super::mouse::MouseAction::WheelRight;
+pub const BTN1: super::mouse::MouseAction = super::mouse::MouseAction::Button(1);
+pub const BTN2: super::mouse::MouseAction = super::mouse::MouseAction::Button(2);
+pub const BTN3: super::mouse::MouseAction = super::mouse::MouseAction::Button(3);
+pub const BTN4: super::mouse::MouseAction = super::mouse::MouseAction::Button(4);
+pub const BTN5: super::mouse::MouseAction = super::mouse::MouseAction::Button(5);
+
+pub const MUTE: super::Consumer = super::Consumer::Mute;
+pub const VOLU: super::Consumer = super::Consumer::VolumeIncrement;
+pub const VOLD: super::Consumer = super::Consumer::VolumeDecrement;
+pub const MPLY: super::Consumer = super::Consumer::PlayPause;
+pub const MSTP: super::Consumer = super::Consumer::Stop;
+pub const MNXT: super::Consumer = super::Consumer::ScanNextTrack;
+pub const MPRV: super::Consumer = super::Consumer::ScanPreviousTrack;
+pub const EJCT: super::Consumer = super::Consumer::Eject;
+
+pub const PWR: super::Desktop = super::Desktop::SystemPowerDown;
+pub const SLEP: super::Desktop = super::Desktop::SystemSleep;
+pub const WAKE: super::Desktop = super::Desktop::SystemWakeUp;
+
+/// Shift together with the key, e.g. `Sft(K1)` for `!`. Only keyboard keys can be modified, so
+/// several modifiers are written as `MK`, e.g. `MK(Mods::LCTL.union(Mods::LALT), DeleteForward)`.
+///
+/// ```compile_fail
+/// # use rmk_mekk_elek::keystate::prelude::*;
+/// let key = Sft(La(1));
+/// ```
+#[allow(non_snake_case)]
+pub const fn Sft(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::LSFT, key)
+}
+#[allow(non_snake_case)]
+pub const fn Ctl(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::LCTL, key)
+}
+#[allow(non_snake_case)]
+pub const fn Alt(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::LALT, key)
+}
+#[allow(non_snake_case)]
+pub const fn Gui(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::LGUI, key)
+}
+#[allow(non_snake_case)]
+pub const fn RSft(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::RSFT, key)
+}
+#[allow(non_snake_case)]
+pub const fn RCtl(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::RCTL, key)
+}
+#[allow(non_snake_case)]
+pub const fn RAlt(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::RALT, key)
+}
+#[allow(non_snake_case)]
+pub const fn RGui(key: super::Keyboard) -> KeyShorthand {
+    MK(Mods::RGUI, key)
+}
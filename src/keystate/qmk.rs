@@ -0,0 +1,199 @@
+//! QMK's 16-bit keycodes, as used by VIA and QMK keymaps
+//!
+//! Only keycodes with a matching `KeyShorthand` convert, anything else is `None`.
+
+use super::mods::Mods;
+use super::mouse::MouseAction;
+use super::Consumer;
+use super::Desktop;
+use super::KeyShorthand;
+use super::Keyboard;
+
+pub const KC_NO: u16 = 0x0000;
+pub const KC_TRANSPARENT: u16 = 0x0001;
+const QK_MOD_TAP: u16 = 0x2000;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_MOMENTARY: u16 = 0x5220;
+const QK_LEADER: u16 = 0x7C58;
+const QK_CAPS_WORD_TOGGLE: u16 = 0x7C73;
+
+/// QMK's own keycodes in the basic range, after the HID keyboard usages it shares
+const SYSTEM: &[(u8, Desktop)] = &[
+    (0xA5, Desktop::SystemPowerDown),
+    (0xA6, Desktop::SystemSleep),
+    (0xA7, Desktop::SystemWakeUp),
+];
+const CONSUMER: &[(u8, Consumer)] = &[
+    (0xA8, Consumer::Mute),
+    (0xA9, Consumer::VolumeIncrement),
+    (0xAA, Consumer::VolumeDecrement),
+    (0xAB, Consumer::ScanNextTrack),
+    (0xAC, Consumer::ScanPreviousTrack),
+    (0xAD, Consumer::Stop),
+    (0xAE, Consumer::PlayPause),
+    (0xB0, Consumer::Eject),
+];
+const MOUSE: &[(u8, MouseAction)] = &[
+    (0xCD, MouseAction::Up),
+    (0xCE, MouseAction::Down),
+    (0xCF, MouseAction::Left),
+    (0xD0, MouseAction::Right),
+    (0xD1, MouseAction::Button(1)),
+    (0xD2, MouseAction::Button(2)),
+    (0xD3, MouseAction::Button(3)),
+    (0xD4, MouseAction::Button(4)),
+    (0xD5, MouseAction::Button(5)),
+    (0xD9, MouseAction::WheelUp),
+    (0xDA, MouseAction::WheelDown),
+    (0xDB, MouseAction::WheelLeft),
+    (0xDC, MouseAction::WheelRight),
+];
+
+/// HID keyboard usages, except the error codes which QMK uses for its own
+fn basic(key: Keyboard) -> Option<u16> {
+    let code = u8::from(key);
+    (code == 0 || (0x04..=0xA4).contains(&code) || (0xE0..=0xE7).contains(&code))
+        .then_some(code as u16)
+}
+
+fn from_basic(code: u8) -> Option<Keyboard> {
+    let key = Keyboard::from(code);
+    (u8::from(key) == code && basic(key).is_some()).then_some(key)
+}
+
+/// QMK's five-bit modifiers: control, shift, alt and GUI, and whether they are the right ones
+fn qmk_mods(mods: Mods) -> Option<u16> {
+    let left = mods.bits() & 0x0F;
+    let right = mods.bits() >> 4;
+    match (left, right) {
+        (0, 0) => None,
+        (left, 0) => Some(left as u16),
+        (0, right) => Some(0x10 | right as u16),
+        _ => None,
+    }
+}
+
+fn from_qmk_mods(mods: u16) -> Mods {
+    let bits = (mods & 0x0F) as u8;
+    if mods & 0x10 != 0 {
+        Mods::from_bits(bits << 4)
+    } else {
+        Mods::from_bits(bits)
+    }
+}
+
+fn code_of<T: PartialEq>(table: &[(u8, T)], value: T) -> Option<u16> {
+    table
+        .iter()
+        .find(|(_, entry)| *entry == value)
+        .map(|(code, _)| *code as u16)
+}
+
+fn value_of<T: Copy>(table: &[(u8, T)], code: u8) -> Option<T> {
+    table
+        .iter()
+        .find(|(entry, _)| *entry == code)
+        .map(|(_, value)| *value)
+}
+
+pub fn to_keycode(binding: KeyShorthand) -> Option<u16> {
+    match binding {
+        KeyShorthand::Kb(key) => basic(key),
+        KeyShorthand::MK(mods, key) => Some(qmk_mods(mods)? << 8 | basic(key)?),
+        KeyShorthand::MT(mod_, tap) => {
+            let mods = qmk_mods(Mods::from_key(mod_)?)?;
+            Some(QK_MOD_TAP | mods << 8 | basic(tap)?)
+        }
+        KeyShorthand::MMT(mods, tap) => Some(QK_MOD_TAP | qmk_mods(mods)? << 8 | basic(tap)?),
+        KeyShorthand::LT(layer, tap) if layer < 0x10 => {
+            Some(QK_LAYER_TAP | (layer as u16) << 8 | basic(tap)?)
+        }
+        KeyShorthand::La(layer) if layer < 0x20 => Some(QK_MOMENTARY | layer as u16),
+        KeyShorthand::Lead => Some(QK_LEADER),
+        KeyShorthand::CapsWord => Some(QK_CAPS_WORD_TOGGLE),
+        KeyShorthand::Sys(key) => code_of(SYSTEM, key),
+        KeyShorthand::Cc(key) => code_of(CONSUMER, key),
+        KeyShorthand::Ms(action) => code_of(MOUSE, action),
+        _ => None,
+    }
+}
+
+/// `KC_TRANSPARENT` is `None` as layers do not fall through, callers copy the binding below
+pub fn from_keycode(code: u16) -> Option<KeyShorthand> {
+    let low = (code & 0xFF) as u8;
+    match code {
+        KC_TRANSPARENT => None,
+        0x0000..=0x00FF => from_basic(low)
+            .map(KeyShorthand::Kb)
+            .or_else(|| value_of(SYSTEM, low).map(KeyShorthand::Sys))
+            .or_else(|| value_of(CONSUMER, low).map(KeyShorthand::Cc))
+            .or_else(|| value_of(MOUSE, low).map(KeyShorthand::Ms)),
+        0x0100..=0x1FFF => {
+            let mods = from_qmk_mods(code >> 8 & 0x1F);
+            from_basic(low).map(|key| KeyShorthand::MK(mods, key))
+        }
+        0x2000..=0x3FFF => {
+            let mods = from_qmk_mods(code >> 8 & 0x1F);
+            let tap = from_basic(low)?;
+            let mut keys = mods.keys();
+            match (keys.next(), keys.next()) {
+                (Some(mod_), None) => Some(KeyShorthand::MT(mod_, tap)),
+                _ => Some(KeyShorthand::MMT(mods, tap)),
+            }
+        }
+        0x4000..=0x4FFF => {
+            from_basic(low).map(|tap| KeyShorthand::LT((code >> 8 & 0x0F) as u8, tap))
+        }
+        0x5220..=0x523F => Some(KeyShorthand::La((code & 0x1F) as u8)),
+        QK_LEADER => Some(KeyShorthand::Lead),
+        QK_CAPS_WORD_TOGGLE => Some(KeyShorthand::CapsWord),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::prelude::*;
+    use super::*;
+
+    #[test]
+    fn keycodes() {
+        let cases = [
+            (Kb(A), 0x0004),
+            (Kb(___), KC_NO),
+            (Kb(LeftShift), 0x00E1),
+            (Sft(K1), 0x021E),
+            (RCtl(Escape), 0x1129),
+            (MT(LCTL, Escape), 0x2129),
+            (MT(RALT, A), 0x3404),
+            (MMT(Mods::LCTL | Mods::LSFT, B), 0x2305),
+            (La(1), 0x5221),
+            (LT(1, Space), 0x412C),
+            (Cc(VOLU), 0x00A9),
+            (Sys(SLEP), 0x00A6),
+            (Ms(BTN1), 0x00D1),
+            (Ms(WH_D), 0x00DA),
+            (Lead, 0x7C58),
+            (CapsWord, 0x7C73),
+        ];
+        for (binding, code) in cases {
+            assert_eq!(to_keycode(binding), Some(code), "{:?}", binding);
+            assert_eq!(from_keycode(code), Some(binding), "{:04x}", code);
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(to_keycode(Str("hi")), None);
+        assert_eq!(to_keycode(AS(A)), None);
+        assert_eq!(to_keycode(Kb(ErrorRollOver)), None);
+        assert_eq!(to_keycode(MT(A, B)), None);
+        assert_eq!(to_keycode(MK(Mods::LCTL.union(Mods::RALT), A)), None);
+        assert_eq!(from_keycode(KC_TRANSPARENT), None);
+        // Mod-tap with modifiers of both sides
+        assert_eq!(to_keycode(MMT(Mods::LCTL | Mods::RSFT, A)), None);
+        assert_eq!(from_keycode(0x00BF), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Reports waiting for the host, so a key pressed and released while the USB endpoint is busy
+//! still reaches it
+
+use heapless::Deque;
+
+/// A bounded queue of reports, sent oldest first
+#[derive(Debug)]
+pub struct ReportQueue<Report, const N: usize> {
+    reports: Deque<Report, N>,
+    /// The most recent report queued, to tell which are redundant
+    last: Report,
+}
+
+impl<Report: Clone + Default + PartialEq, const N: usize> Default for ReportQueue<Report, N> {
+    fn default() -> Self {
+        ReportQueue {
+            reports: Deque::new(),
+            last: Report::default(),
+        }
+    }
+}
+
+impl<Report: Clone + Default + PartialEq, const N: usize> ReportQueue<Report, N> {
+    /// Queues `report` unless it is the same as the one before it. If the queue is full it is
+    /// left as it was and `false` is returned, so check `room` before processing the keymap and
+    /// hold off until there is room for its reports, rather than lose a transition.
+    pub fn push(&mut self, report: Report) -> bool {
+        if report == self.last {
+            return true;
+        }
+        match self.reports.push_back(report.clone()) {
+            Ok(()) => {
+                self.last = report;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Sends the queued reports in order until `send`, returning whether it sent one, fails
+    pub fn flush(&mut self, mut send: impl FnMut(&Report) -> bool) {
+        while let Some(report) = self.reports.front() {
+            if !send(report) {
+                return;
+            }
+            self.reports.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.reports.is_full()
+    }
+
+    /// How many more reports fit
+    pub fn room(&self) -> usize {
+        N - self.reports.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::prelude::*;
+    use super::super::report::Report;
+    use super::super::Keymap;
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn redundant() {
+        let mut queue: ReportQueue<u8, 4> = ReportQueue::default();
+        for report in [0, 1, 1, 2, 1, 1] {
+            assert!(queue.push(report));
+        }
+        assert_eq!(queue.len(), 3);
+        let mut sent = Vec::new();
+        queue.flush(|report| {
+            sent.push(*report);
+            true
+        });
+        assert_eq!(sent, [1, 2, 1]);
+        assert!(queue.is_empty());
+        assert!(queue.push(0));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn full() {
+        let mut queue: ReportQueue<u8, 2> = ReportQueue::default();
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(queue.is_full());
+        assert_eq!(queue.room(), 0);
+        assert!(!queue.push(3));
+        let mut sent = Vec::new();
+        queue.flush(|report| {
+            sent.push(*report);
+            true
+        });
+        assert_eq!(sent, [1, 2]);
+        assert!(queue.push(3));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn tap_while_busy() {
+        let mut keymap: Keymap<1, 1, 8> = Keymap::new([[Kb(A)]], 2, 4, 6);
+        let mut queue: ReportQueue<Report<8>, 8> = ReportQueue::default();
+        let mut sent = Vec::new();
+        for (pressed, now, ready) in [(true, 1, false), (false, 2, false), (false, 3, true)] {
+            keymap.process([pressed], now);
+            queue.push(keymap.report());
+            queue.flush(|report| {
+                if ready {
+                    sent.push(report.keys.clone());
+                }
+                ready
+            });
+        }
+        assert_eq!(sent, [[A].as_slice(), &[]]);
+    }
+
+    #[test]
+    fn back_pressure() {
+        let mut keymap: Keymap<2, 1, 8> = Keymap::new([[Kb(A), Kb(B)]], 2, 4, 6);
+        let mut queue: ReportQueue<Report<8>, 2> = ReportQueue::default();
+        let mut sent = Vec::new();
+        // The host stops reading for a while, then catches up one report per scan
+        let scans = [
+            ([true, false], false),
+            ([false, false], false),
+            ([false, true], false),
+            ([false, true], true),
+            ([false, true], true),
+            ([false, false], true),
+            ([false, false], true),
+        ];
+        for (now, (pressed, ready)) in scans.into_iter().enumerate() {
+            if !queue.is_full() {
+                keymap.process(pressed, now as u64);
+                assert!(queue.push(keymap.report()));
+            }
+            let mut budget = 1;
+            queue.flush(|report| {
+                if ready && budget > 0 {
+                    budget -= 1;
+                    sent.push(report.keys.clone());
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        assert_eq!(sent, [[A].as_slice(), &[], &[B], &[]]);
+    }
+}
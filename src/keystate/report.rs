@@ -0,0 +1,123 @@
+//! The keyboard state sent to the host, with the modifiers kept apart from the other keys
+
+use heapless::Vec;
+use usbd_human_interface_device::device::keyboard::{BootKeyboardReport, NKROBootKeyboardReport};
+
+use super::mods::Mods;
+use super::Keyboard;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report<const N: usize> {
+    /// Held by modifier keys, `Kb`, `MT` and `LT` keys and custom keys
+    pub real_mods: Mods,
+    /// Sent only with the keys pressed along with them, e.g. the shift of `MK(LSFT, K1)`, auto
+    /// shift or Caps Word. A modified key's go out only while it is the most recently pressed
+    /// key, so two held together do not share their modifiers.
+    pub weak_mods: Mods,
+    /// Held but not sent, e.g. the shift a key override turns `Shift+Backspace` into `Delete` with
+    pub masked_mods: Mods,
+    /// Pressed keys other than modifiers, each once
+    pub keys: Vec<Keyboard, N>,
+}
+
+impl<const N: usize> Report<N> {
+    /// The modifiers the host sees
+    pub fn mods(&self) -> Mods {
+        self.real_mods
+            .difference(self.masked_mods)
+            .union(self.weak_mods)
+    }
+
+    /// Every key the host sees as pressed, modifiers first, as `write_report` takes them
+    pub fn codes(&self) -> impl Iterator<Item = Keyboard> + '_ {
+        self.mods().keys().chain(self.keys.iter().copied())
+    }
+}
+
+/// A 6KRO report, with `ErrorRollOver` in every slot if the keys do not fit
+impl<const N: usize> From<&Report<N>> for BootKeyboardReport {
+    fn from(report: &Report<N>) -> Self {
+        BootKeyboardReport::new(report.codes())
+    }
+}
+
+/// An NKRO report, also holding the boot report for hosts which only read that
+impl<const N: usize> From<&Report<N>> for NKROBootKeyboardReport {
+    fn from(report: &Report<N>) -> Self {
+        NKROBootKeyboardReport::new(report.codes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::macros::Stroke;
+    use super::super::overrides::KeyOverride;
+    use super::super::prelude::*;
+    use super::super::Keymap;
+    use super::*;
+
+    #[test]
+    fn mods() {
+        let report: Report<4> = Report {
+            real_mods: Mods::LCTL | Mods::LSFT,
+            weak_mods: Mods::RALT,
+            masked_mods: Mods::LSFT,
+            keys: Vec::from_slice(&[A]).unwrap(),
+        };
+        assert_eq!(report.mods(), Mods::LCTL | Mods::RALT);
+        assert!(report.codes().eq([LeftControl, RightAlt, A]));
+    }
+
+    #[test]
+    fn hid_reports() {
+        let mut report: Report<8> = Report {
+            weak_mods: Mods::LSFT,
+            ..Default::default()
+        };
+        for key in [A, B, C, D, E, F, G] {
+            report.keys.push(key).unwrap();
+        }
+        let boot = BootKeyboardReport::from(&report);
+        assert!(boot.left_shift);
+        assert_eq!(boot.keys, [ErrorRollOver; 6]);
+        let nkro = NKROBootKeyboardReport::from(&report);
+        assert!(nkro.left_shift);
+        assert_eq!(nkro.boot_keys, [ErrorRollOver; 6]);
+        assert_eq!(nkro.nkro_keys[0], 0b1111_0000);
+        assert_eq!(nkro.nkro_keys[1], 0b0000_0111);
+
+        report.keys.truncate(2);
+        let boot = BootKeyboardReport::from(&report);
+        assert_eq!(boot.keys, [A, B, NOP, NOP, NOP, NOP]);
+    }
+
+    #[test]
+    fn keymap_report() {
+        let mut keymap: Keymap<4, 1, 8> = Keymap::new(
+            [[Kb(LSFT), MK(Mods::RALT, K1), Kb(DeleteBackspace), Kb(A)]],
+            2,
+            4,
+            6,
+        );
+        const OVERRIDES: &[KeyOverride] = &[KeyOverride::new(
+            Mods::LSFT,
+            DeleteBackspace,
+            Stroke::key(DeleteForward),
+        )];
+        keymap.overrides = OVERRIDES;
+        keymap.process([true, false, false, true], 1);
+        keymap.process([true, true, false, true], 2);
+        let report = keymap.report();
+        assert_eq!(report.real_mods, Mods::LSFT);
+        assert_eq!(report.weak_mods, Mods::RALT);
+        assert_eq!(report.masked_mods, Mods::NONE);
+        assert_eq!(report.keys, [K1, A]);
+
+        keymap.process([true, false, true, false], 3);
+        let report = keymap.report();
+        assert_eq!(report.real_mods, Mods::LSFT);
+        assert_eq!(report.masked_mods, Mods::LSFT);
+        assert_eq!(report.mods(), Mods::NONE);
+        assert_eq!(report.keys, [DeleteForward]);
+    }
+}
@@ -0,0 +1,117 @@
+//! What is reported when more keys are pressed than fit: modifiers are kept over other keys, and
+//! a boot protocol report, with room for 6 keys, reports `ErrorRollOver` instead of any of them
+
+use heapless::Vec;
+
+use super::keycode::KeyCode;
+use super::mods::Mods;
+use super::Keyboard;
+
+/// The keys a boot protocol keyboard report has room for, besides the modifier byte
+pub const BOOT_KEYS: usize = 6;
+
+fn is_modifier<Code: KeyCode>(code: Code) -> bool {
+    code.to_keyboard().and_then(Mods::from_key).is_some()
+}
+
+/// Adds `code` to the pressed keys. If they are full, a modifier takes the place of the most
+/// recent other key. Returns `false` if a key was dropped.
+pub fn push<Code: KeyCode, const N: usize>(keys: &mut Vec<Code, N>, code: Code) -> bool {
+    if keys.push(code).is_ok() {
+        return true;
+    }
+    if is_modifier(code) {
+        if let Some(index) = keys.iter().rposition(|key| !is_modifier(*key)) {
+            keys.remove(index);
+            keys.push(code).ok();
+        }
+    }
+    false
+}
+
+/// Replaces the pressed keys other than modifiers with a single `ErrorRollOver` if there are more
+/// than a boot report holds, or `dropped` says some are missing, as the HID spec asks of a
+/// keyboard in the boot protocol. Returns whether it did.
+pub fn boot<Code: KeyCode, const N: usize>(keys: &mut Vec<Code, N>, dropped: bool) -> bool {
+    let mut others: Vec<Code, N> = Vec::new();
+    for key in keys.iter().filter(|key| !is_modifier(**key)) {
+        if !others.contains(key) {
+            others.push(*key).ok();
+        }
+    }
+    if others.len() <= BOOT_KEYS && !dropped {
+        return false;
+    }
+    keys.retain(|key| is_modifier(*key));
+    if let Some(error) = Code::from_keyboard(Keyboard::ErrorRollOver) {
+        keys.push(error).ok();
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prelude::*;
+    use super::super::Keymap;
+    use super::*;
+
+    #[test]
+    fn modifiers_first() {
+        let mut keys: Vec<Keyboard, 3> = Vec::new();
+        assert!(push(&mut keys, A));
+        assert!(push(&mut keys, LeftControl));
+        assert!(push(&mut keys, B));
+        assert!(!push(&mut keys, C));
+        assert_eq!(keys, [A, LeftControl, B]);
+        assert!(!push(&mut keys, LeftShift));
+        assert_eq!(keys, [A, LeftControl, LeftShift]);
+        assert!(!push(&mut keys, RightShift));
+        assert!(!push(&mut keys, RightAlt));
+        assert_eq!(keys, [LeftControl, LeftShift, RightShift]);
+    }
+
+    #[test]
+    fn error_rollover() {
+        let mut keymap: Keymap<8, 1, 8> = Keymap::new(
+            [[
+                Kb(A),
+                Kb(B),
+                Kb(C),
+                Kb(D),
+                Kb(E),
+                Kb(F),
+                Kb(G),
+                Kb(LeftShift),
+            ]],
+            2,
+            4,
+            6,
+        );
+        keymap.process([true, true, true, true, true, true, true, true], 1);
+        assert_eq!(keymap.pressed_keys, [A, B, C, D, E, F, G, LeftShift]);
+        assert!(!keymap.flags.rollover);
+
+        keymap.boot_protocol = true;
+        keymap.process([true, true, true, true, true, true, true, true], 2);
+        assert_eq!(keymap.pressed_keys, [LeftShift, ErrorRollOver]);
+        assert!(keymap.flags.rollover);
+        keymap.process([true, true, true, true, true, true, false, true], 3);
+        assert_eq!(keymap.pressed_keys, [A, B, C, D, E, F, LeftShift]);
+        assert!(!keymap.flags.rollover);
+    }
+
+    #[test]
+    fn shift_kept() {
+        let mut keymap: Keymap<4, 1, 3> =
+            Keymap::new([[Kb(A), Kb(B), Kb(C), Kb(LeftShift)]], 2, 4, 6);
+        keymap.process([true, true, true, true], 1);
+        assert_eq!(keymap.pressed_keys, [A, B, LeftShift]);
+        assert!(keymap.flags.rollover);
+        keymap.process([true, true, false, true], 2);
+        assert!(!keymap.flags.rollover);
+
+        keymap.boot_protocol = true;
+        keymap.process([true, true, true, true], 3);
+        assert_eq!(keymap.pressed_keys, [LeftShift, ErrorRollOver]);
+    }
+}
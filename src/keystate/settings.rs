@@ -0,0 +1,82 @@
+//! Keymap-wide settings which can change at runtime, stored along with the keymap
+
+use super::host::HostLayout;
+use super::unicode::UnicodeMode;
+use super::Layer;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Layer used while no other is held
+    pub default_layer: Layer,
+    /// `AutoShiftConfig::global`
+    pub auto_shift: bool,
+    pub layout: HostLayout,
+    pub unicode: UnicodeMode,
+}
+
+impl Settings {
+    pub const LEN: usize = 4;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let layout = match self.layout {
+            HostLayout::Us => 0,
+            HostLayout::Uk => 1,
+            HostLayout::De => 2,
+            HostLayout::Fr => 3,
+        };
+        let unicode = match self.unicode {
+            UnicodeMode::Linux => 0,
+            UnicodeMode::MacOs => 1,
+            UnicodeMode::WinCompose => 2,
+        };
+        [self.default_layer, self.auto_shift as u8, layout, unicode]
+    }
+
+    /// `None` if any value is out of range
+    pub fn from_bytes(bytes: [u8; Self::LEN]) -> Option<Self> {
+        let [default_layer, auto_shift, layout, unicode] = bytes;
+        Some(Settings {
+            default_layer,
+            auto_shift: match auto_shift {
+                0 => false,
+                1 => true,
+                _ => return None,
+            },
+            layout: match layout {
+                0 => HostLayout::Us,
+                1 => HostLayout::Uk,
+                2 => HostLayout::De,
+                3 => HostLayout::Fr,
+                _ => return None,
+            },
+            unicode: match unicode {
+                0 => UnicodeMode::Linux,
+                1 => UnicodeMode::MacOs,
+                2 => UnicodeMode::WinCompose,
+                _ => return None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let settings = Settings {
+            default_layer: 2,
+            auto_shift: true,
+            layout: HostLayout::Fr,
+            unicode: UnicodeMode::WinCompose,
+        };
+        assert_eq!(settings.to_bytes(), [2, 1, 3, 2]);
+        assert_eq!(Settings::from_bytes(settings.to_bytes()), Some(settings));
+        assert_eq!(Settings::from_bytes([0, 2, 0, 0]), None);
+        assert_eq!(Settings::from_bytes([0, 0, 4, 0]), None);
+        assert_eq!(Settings::from_bytes([0, 0, 0, 3]), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Checks for `keymap!`, the layer sizes evaluated in constants so mistakes fail the build
+
+use super::KeyShorthand;
+
+/// Panics unless a layer of `len` keys has exactly `size`
+#[doc(hidden)]
+pub const fn check_len(len: usize, size: usize) {
+    if len != size {
+        panic!("keymap!: every layer must have exactly SIZE keys");
+    }
+}
+
+/// Panics if a `La` or `LT` key targets a layer that does not exist
+#[doc(hidden)]
+pub const fn check_layers<Code, Custom, const SIZE: usize, const LAYERS: usize>(
+    keymap: &[[KeyShorthand<Code, Custom>; SIZE]; LAYERS],
+) {
+    let mut layer = 0;
+    while layer < LAYERS {
+        let mut index = 0;
+        while index < SIZE {
+            if let KeyShorthand::La(target) | KeyShorthand::LT(target, _) = &keymap[layer][index] {
+                if *target as usize >= LAYERS {
+                    panic!("keymap!: La(n) and LT(n, _) must target a layer below LAYERS");
+                }
+            }
+            index += 1;
+        }
+        layer += 1;
+    }
+}
+
+/// Layers written as grids, as the `[[KeyShorthand; SIZE]; LAYERS]` that `Keymap::new` takes,
+/// checked at compile time to have the same number of keys. `La` keys targeting a layer that does
+/// not exist panic, at compile time if the keymap is a constant. Keys may be any expression, e.g.
+/// `MMT(Mods::LCTL | Mods::LSFT, B)`, of any `KeyCode`. The prelude is in scope for the keys.
+/// `SIZE` is the first layer's size unless given.
+///
+/// ```
+/// # use rmk_mekk_elek::keymap;
+/// # use rmk_mekk_elek::keystate::Keymap;
+/// let keymap: Keymap<4, 2, 6> = Keymap::new(
+///     keymap![
+///         [
+///             Kb(Escape), MT(LSFT, A),
+///             La(1),      Sft(K1),
+///         ],
+///         [
+///             Kb(F1),     Kb(___),
+///             Kb(___),    Cc(VOLU),
+///         ],
+///     ],
+///     200,
+///     100,
+///     500,
+/// );
+/// ```
+///
+/// A layer of the wrong size, or in a constant a layer that does not exist, fails to compile:
+///
+/// ```compile_fail
+/// # use rmk_mekk_elek::keymap;
+/// let keymap = keymap![4; [Kb(A), Kb(B), Kb(C), Kb(D)], [Kb(E), Kb(F), Kb(G)]];
+/// ```
+///
+/// ```compile_fail
+/// # use rmk_mekk_elek::keymap;
+/// # use rmk_mekk_elek::keystate::KeyShorthand;
+/// const KEYMAP: [[KeyShorthand; 2]; 2] = keymap![[Kb(A), La(2)], [Kb(B), Kb(C)]];
+/// ```
+#[macro_export]
+macro_rules! keymap {
+    ($([$($key:expr),* $(,)?]),+ $(,)?) => {
+        $crate::keymap!(@first $([$($key),*]),+)
+    };
+    (@first [$($first:expr),*] $(, [$($key:expr),*])*) => {
+        $crate::keymap!(
+            <[()]>::len(&[$($crate::keymap!(@unit $first)),*]);
+            [$($first),*] $(, [$($key),*])*
+        )
+    };
+    (@unit $key:expr) => {
+        ()
+    };
+    ($size:expr; $([$($key:expr),* $(,)?]),+ $(,)?) => {{
+        #[allow(unused_imports)]
+        use $crate::keystate::prelude::*;
+        $(const {
+            $crate::keystate::shape::check_len(
+                <[()]>::len(&[$($crate::keymap!(@unit $key)),*]),
+                $size,
+            )
+        };)+
+        let keymap = [$([$($key),*]),+];
+        $crate::keystate::shape::check_layers(&keymap);
+        keymap
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prelude::*;
+    use super::KeyShorthand;
+
+    #[test]
+    fn keymap() {
+        #[rustfmt::skip]
+        let keymap: [[KeyShorthand; 4]; 2] = keymap![
+            [
+                Kb(A),   La(1),
+                Kb(___), Sft(K1),
+            ],
+            [
+                Kb(B),   Kb(___),
+                La(0),   MT(LCTL, Escape),
+            ],
+        ];
+        assert_eq!(
+            keymap,
+            [
+                [Kb(A), La(1), Kb(___), Sft(K1)],
+                [Kb(B), Kb(___), La(0), MT(LCTL, Escape)],
+            ]
+        );
+        const SIZE: usize = 3;
+        let keymap: [[KeyShorthand; SIZE]; 1] = keymap![SIZE; [Kb(A), Kb(B), Kb(C)]];
+        assert_eq!(keymap, [[Kb(A), Kb(B), Kb(C)]]);
+        // Not constant
+        let keymap: [[KeyShorthand; 1]; 1] = keymap![[MMT(Mods::LCTL | Mods::LSFT, B)]];
+        assert_eq!(keymap, [[MMT(Mods::LCTL.union(Mods::LSFT), B)]]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Typing arbitrary code points through the host's Unicode input method
+
+use heapless::Vec;
+
+use super::host::HostConfig;
+use super::macros::Sequence;
+use super::macros::Stroke;
+use super::mods::Mods;
+use super::Keyboard;
+
+/// The input method the host uses for entering code points
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeMode {
+    /// IBus (GTK/Qt on Linux): `Ctrl+Shift+U`, hex, `Space`
+    #[default]
+    Linux,
+    /// macOS "Unicode Hex Input": hex of each UTF-16 unit while holding `Option`
+    MacOs,
+    /// WinCompose on Windows with the default compose key: `RAlt`, `u`, hex, `Enter`
+    WinCompose,
+}
+
+/// The code point typed by a `Macro::Unicode` key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unicode(pub char);
+
+enum Part {
+    Stroke(Stroke),
+    /// Typed through the host layout, as hex digits move around on some layouts
+    Char(char),
+}
+
+fn hex_digit(digit: u32) -> char {
+    char::from_digit(digit, 16).unwrap_or('0')
+}
+
+fn push_hex(parts: &mut Vec<Part, 12>, value: u32, digits: u32) {
+    for shift in (0..digits).rev() {
+        parts
+            .push(Part::Char(hex_digit((value >> (4 * shift)) & 0xF)))
+            .ok();
+    }
+}
+
+impl Unicode {
+    /// Everything typed in order, and the modifiers held throughout
+    fn parts(self, mode: UnicodeMode) -> (Vec<Part, 12>, Mods) {
+        let mut parts = Vec::new();
+        let code = self.0 as u32;
+        let digits = (32 - code.leading_zeros()).div_ceil(4).max(1);
+        match mode {
+            UnicodeMode::Linux => {
+                let start = Stroke::new(Mods::LCTL | Mods::LSFT, Keyboard::U);
+                parts.push(Part::Stroke(start)).ok();
+                push_hex(&mut parts, code, digits);
+                parts.push(Part::Stroke(Stroke::key(Keyboard::Space))).ok();
+                (parts, Mods::NONE)
+            }
+            UnicodeMode::MacOs => {
+                let mut units = [0; 2];
+                for unit in self.0.encode_utf16(&mut units) {
+                    push_hex(&mut parts, *unit as u32, 4);
+                }
+                (parts, Mods::LALT)
+            }
+            UnicodeMode::WinCompose => {
+                let compose = Stroke::new(Mods::RALT, Keyboard::NoEventIndicated);
+                parts.push(Part::Stroke(compose)).ok();
+                parts.push(Part::Char('u')).ok();
+                push_hex(&mut parts, code, digits);
+                parts
+                    .push(Part::Stroke(Stroke::key(Keyboard::ReturnEnter)))
+                    .ok();
+                (parts, Mods::NONE)
+            }
+        }
+    }
+}
+
+/// Each part is pressed for one report then released for one report, with the held modifiers
+/// only released at the end
+impl Sequence for Unicode {
+    fn stroke(&self, step: usize, host: &HostConfig) -> Option<Stroke> {
+        let (parts, held) = self.parts(host.unicode);
+        let part = parts.get(step / 2)?;
+        let stroke = if !step.is_multiple_of(2) {
+            Stroke::EMPTY
+        } else {
+            match part {
+                Part::Stroke(stroke) => *stroke,
+                Part::Char(c) => host.layout.strokes(*c).first().copied().unwrap_or_default(),
+            }
+        };
+        let held = if step + 1 < 2 * parts.len() {
+            held
+        } else {
+            Mods::NONE
+        };
+        Some(Stroke::new(stroke.mods | held, stroke.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::host::HostLayout;
+    use super::*;
+    use std::vec::Vec;
+
+    fn played(c: char, host: &HostConfig) -> Vec<Stroke> {
+        (0..)
+            .map_while(|step| Unicode(c).stroke(step, host))
+            .collect()
+    }
+
+    #[test]
+    fn linux() {
+        let host = HostConfig::default();
+        assert_eq!(
+            played('é', &host),
+            [
+                Stroke::new(Mods::LCTL | Mods::LSFT, Keyboard::U),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::E),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::Keyboard9),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::Space),
+                Stroke::EMPTY,
+            ]
+        );
+    }
+
+    #[test]
+    fn macos_surrogates() {
+        let host = HostConfig {
+            unicode: UnicodeMode::MacOs,
+            ..Default::default()
+        };
+        let strokes = played('\u{1F600}', &host);
+        assert_eq!(strokes.len(), 16);
+        assert_eq!(strokes[0], Stroke::new(Mods::LALT, Keyboard::D));
+        assert_eq!(
+            strokes[1],
+            Stroke::new(Mods::LALT, Keyboard::NoEventIndicated)
+        );
+        assert_eq!(strokes[8], Stroke::new(Mods::LALT, Keyboard::D));
+        assert_eq!(strokes[10], Stroke::new(Mods::LALT, Keyboard::E));
+        assert_eq!(strokes[14], Stroke::new(Mods::LALT, Keyboard::Keyboard0));
+        assert_eq!(strokes[15], Stroke::EMPTY);
+    }
+
+    #[test]
+    fn wincompose_on_azerty() {
+        let host = HostConfig {
+            layout: HostLayout::Fr,
+            unicode: UnicodeMode::WinCompose,
+        };
+        assert_eq!(
+            played('\u{3A9}', &host),
+            [
+                Stroke::new(Mods::RALT, Keyboard::NoEventIndicated),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::U),
+                Stroke::EMPTY,
+                Stroke::new(Mods::LSFT, Keyboard::Keyboard3),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::Q),
+                Stroke::EMPTY,
+                Stroke::new(Mods::LSFT, Keyboard::Keyboard9),
+                Stroke::EMPTY,
+                Stroke::key(Keyboard::ReturnEnter),
+                Stroke::EMPTY,
+            ]
+        );
+    }
+}
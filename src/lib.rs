@@ -1,5 +1,9 @@
 #![cfg_attr(not(test), no_std)]
 
+#[cfg(any(test, feature = "codegen"))]
+pub mod codegen;
 pub mod debounce;
 pub mod keystate;
 pub mod matrix;
+pub mod storage;
+pub mod via;
@@ -0,0 +1,343 @@
+//! Settings store on NOR flash, with wear levelling and power-loss-safe writes
+//!
+//! Each save appends a record, a header and the payload, after the previous one and moves on to
+//! the next sector of a ring when the current one is full, so the sectors wear evenly. A record
+//! only counts with a matching CRC, so a save cut short by power loss leaves the previous one as
+//! the latest. Anything unexpected in a sector, such as a torn record, ends it for writing.
+
+use crate::keystate::binary::crc32_update;
+
+/// The flash region the store owns, offsets are relative to its start
+pub trait Flash {
+    /// Erase unit in bytes, a multiple of 256
+    const SECTOR_SIZE: u32;
+    fn read(&mut self, offset: u32, buf: &mut [u8]);
+    /// Sets the sector starting at `offset` to `0xFF`s
+    fn erase(&mut self, offset: u32);
+    /// Programs `data` to erased flash at `offset`, with any alignment
+    fn program(&mut self, offset: u32, data: &[u8]);
+}
+
+const MAGIC: [u8; 4] = *b"RMKS";
+/// Magic, sequence, length and CRC
+const HEADER_LEN: u32 = 16;
+/// Records start on a flash page
+const ALIGN: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    /// The payload does not fit in a sector
+    TooLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    offset: u32,
+    sequence: u32,
+    len: u32,
+}
+
+impl Record {
+    fn end(&self) -> u32 {
+        (self.offset + HEADER_LEN + self.len).next_multiple_of(ALIGN)
+    }
+}
+
+/// Where the next record goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cursor {
+    sector: u32,
+    /// Free space in `sector`, `None` if it is full
+    offset: Option<u32>,
+    sequence: u32,
+}
+
+pub struct Store<F: Flash> {
+    flash: F,
+    sectors: u32,
+    latest: Option<Record>,
+    /// `None` until the flash has been scanned
+    cursor: Option<Cursor>,
+}
+
+impl<F: Flash> Store<F> {
+    /// A store over `sectors` sectors of `flash`
+    pub fn new(flash: F, sectors: u32) -> Self {
+        Store {
+            flash,
+            sectors,
+            latest: None,
+            cursor: None,
+        }
+    }
+
+    /// Reads the latest saved payload into `buf`, `None` if there is none or it does not fit
+    pub fn load<'b>(&mut self, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        self.cursor();
+        let record = self.latest?;
+        let data = buf.get_mut(..record.len as usize)?;
+        self.flash.read(record.offset + HEADER_LEN, data);
+        Some(data)
+    }
+
+    pub fn save(&mut self, data: &[u8]) -> Result<(), StoreError> {
+        let len = u32::try_from(data.len()).map_err(|_| StoreError::TooLarge)?;
+        if HEADER_LEN + len > F::SECTOR_SIZE {
+            return Err(StoreError::TooLarge);
+        }
+        let cursor = self.cursor();
+        let offset = match cursor.offset {
+            Some(offset) if offset + HEADER_LEN + len <= (cursor.sector + 1) * F::SECTOR_SIZE => {
+                offset
+            }
+            _ => {
+                let offset = (cursor.sector + 1) % self.sectors * F::SECTOR_SIZE;
+                self.flash.erase(offset);
+                offset
+            }
+        };
+        let record = Record {
+            offset,
+            sequence: cursor.sequence,
+            len,
+        };
+
+        let mut header = [0; HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&record.sequence.to_le_bytes());
+        header[8..12].copy_from_slice(&len.to_le_bytes());
+        let crc = !crc32_update(crc32_update(!0, &header[4..12]), data);
+        header[12..16].copy_from_slice(&crc.to_le_bytes());
+        // The header goes first, so erased header bytes mean nothing after them was written
+        self.flash.program(offset, &header);
+        self.flash.program(offset + HEADER_LEN, data);
+
+        self.latest = Some(record);
+        self.cursor = Some(Cursor {
+            sector: offset / F::SECTOR_SIZE,
+            offset: Some(record.end()),
+            sequence: record.sequence.wrapping_add(1),
+        });
+        Ok(())
+    }
+
+    fn cursor(&mut self) -> Cursor {
+        if let Some(cursor) = self.cursor {
+            return cursor;
+        }
+        let mut cursor = Cursor {
+            sector: self.sectors - 1,
+            offset: None,
+            sequence: 0,
+        };
+        for sector in 0..self.sectors {
+            let (latest, end) = self.scan_sector(sector);
+            let Some(latest) = latest else {
+                continue;
+            };
+            if self
+                .latest
+                .is_none_or(|record| latest.sequence > record.sequence)
+            {
+                self.latest = Some(latest);
+                cursor = Cursor {
+                    sector,
+                    offset: end,
+                    sequence: latest.sequence.wrapping_add(1),
+                };
+            }
+        }
+        self.cursor = Some(cursor);
+        cursor
+    }
+
+    /// The sector's latest valid record, and where its free space starts unless it is full
+    fn scan_sector(&mut self, sector: u32) -> (Option<Record>, Option<u32>) {
+        let start = sector * F::SECTOR_SIZE;
+        let end = start + F::SECTOR_SIZE;
+        let mut latest: Option<Record> = None;
+        let mut offset = start;
+        while offset + HEADER_LEN <= end {
+            let mut header = [0; HEADER_LEN as usize];
+            self.flash.read(offset, &mut header);
+            if header[0..4] != MAGIC {
+                let erased = header.iter().all(|byte| *byte == 0xFF);
+                return (latest, erased.then_some(offset));
+            }
+            let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+            let record = Record {
+                offset,
+                sequence: field(4),
+                len: field(8),
+            };
+            if record.len > end - offset - HEADER_LEN || !self.crc_matches(&record, &header) {
+                return (latest, None);
+            }
+            if latest.is_none_or(|latest| record.sequence > latest.sequence) {
+                latest = Some(record);
+            }
+            offset = record.end();
+        }
+        (latest, None)
+    }
+
+    fn crc_matches(&mut self, record: &Record, header: &[u8; HEADER_LEN as usize]) -> bool {
+        let mut crc = crc32_update(!0, &header[4..12]);
+        let mut chunk = [0; 32];
+        let mut offset = record.offset + HEADER_LEN;
+        let end = offset + record.len;
+        while offset < end {
+            let chunk = &mut chunk[..(end - offset).min(32) as usize];
+            self.flash.read(offset, chunk);
+            crc = crc32_update(crc, chunk);
+            offset += chunk.len() as u32;
+        }
+        (!crc).to_le_bytes() == header[12..16]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const SECTOR: u32 = 1024;
+
+    struct FakeFlash {
+        data: Vec<u8>,
+        erases: Vec<u32>,
+        /// Bytes left to program before the power goes
+        budget: usize,
+    }
+
+    impl FakeFlash {
+        fn new(sectors: u32) -> Self {
+            FakeFlash {
+                data: std::vec![0xFF; (sectors * SECTOR) as usize],
+                erases: std::vec![0; sectors as usize],
+                budget: usize::MAX,
+            }
+        }
+    }
+
+    impl Flash for FakeFlash {
+        const SECTOR_SIZE: u32 = SECTOR;
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) {
+            let offset = offset as usize;
+            buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+        }
+
+        fn erase(&mut self, offset: u32) {
+            assert_eq!(offset % SECTOR, 0);
+            if self.budget == 0 {
+                return;
+            }
+            self.erases[(offset / SECTOR) as usize] += 1;
+            let offset = offset as usize;
+            self.data[offset..offset + SECTOR as usize].fill(0xFF);
+        }
+
+        fn program(&mut self, offset: u32, data: &[u8]) {
+            for (index, byte) in data.iter().enumerate() {
+                if self.budget == 0 {
+                    return;
+                }
+                self.budget -= 1;
+                let old = &mut self.data[offset as usize + index];
+                assert_eq!(*old, 0xFF, "programming unerased flash at {}", offset);
+                *old = *byte;
+            }
+        }
+    }
+
+    fn loaded(store: &mut Store<FakeFlash>) -> Option<Vec<u8>> {
+        let mut buf = [0; 1024];
+        store.load(&mut buf).map(|data| data.to_vec())
+    }
+
+    /// A new store over the same flash, as after a reboot
+    fn reboot(store: Store<FakeFlash>) -> Store<FakeFlash> {
+        let mut flash = store.flash;
+        flash.budget = usize::MAX;
+        Store::new(flash, store.sectors)
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut store = Store::new(FakeFlash::new(2), 2);
+        assert_eq!(loaded(&mut store), None);
+        store.save(b"first").unwrap();
+        assert_eq!(loaded(&mut store).as_deref(), Some(&b"first"[..]));
+        store.save(b"second").unwrap();
+        let mut store = reboot(store);
+        assert_eq!(loaded(&mut store).as_deref(), Some(&b"second"[..]));
+        store.save(b"").unwrap();
+        assert_eq!(loaded(&mut reboot(store)).as_deref(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn power_loss() {
+        for budget in [0, 3, HEADER_LEN as usize, HEADER_LEN as usize + 100] {
+            let mut store = Store::new(FakeFlash::new(2), 2);
+            store.save(&[1; 300]).unwrap();
+            store.flash.budget = budget;
+            store.save(&[2; 300]).unwrap();
+
+            let mut store = reboot(store);
+            assert_eq!(loaded(&mut store), Some(std::vec![1; 300]));
+            store.save(&[3; 300]).unwrap();
+            assert_eq!(loaded(&mut reboot(store)), Some(std::vec![3; 300]));
+        }
+    }
+
+    #[test]
+    fn power_loss_while_erasing() {
+        let mut store = Store::new(FakeFlash::new(2), 2);
+        for value in 0..4 {
+            store.save(&[value; 300]).unwrap();
+        }
+        // Both sectors are full, so the next save erases the first one, which is left half-erased
+        store.flash.data[..SECTOR as usize].fill(0x55);
+        store.flash.budget = 0;
+        store.save(&[9; 300]).unwrap();
+
+        let mut store = reboot(store);
+        assert_eq!(loaded(&mut store), Some(std::vec![3; 300]));
+        store.save(&[4; 300]).unwrap();
+        assert_eq!(loaded(&mut reboot(store)), Some(std::vec![4; 300]));
+    }
+
+    #[test]
+    fn wear_levelling() {
+        let mut store = Store::new(FakeFlash::new(4), 4);
+        for value in 0..=200 {
+            store.save(&[value; 200]).unwrap();
+            if value % 7 == 0 {
+                store = reboot(store);
+            }
+        }
+        assert_eq!(loaded(&mut store), Some(std::vec![200; 200]));
+        let erases = &store.flash.erases;
+        assert!(erases.iter().max().unwrap() - erases.iter().min().unwrap() <= 1);
+    }
+
+    #[test]
+    fn corrupt_flash() {
+        let mut flash = FakeFlash::new(2);
+        flash.data.fill(0x00);
+        flash.data[..4].copy_from_slice(&MAGIC);
+        let mut store = Store::new(flash, 2);
+        assert_eq!(loaded(&mut store), None);
+        store.save(b"fresh").unwrap();
+        assert_eq!(loaded(&mut reboot(store)).as_deref(), Some(&b"fresh"[..]));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut store = Store::new(FakeFlash::new(2), 2);
+        assert_eq!(store.save(&[0; SECTOR as usize]), Err(StoreError::TooLarge));
+    }
+}
@@ -0,0 +1,339 @@
+//! The VIA raw HID protocol, for remapping from the VIA app without reflashing
+//!
+//! Each request is a 32-byte report whose first byte is the command, and is answered in place.
+//! Keycodes are QMK's, see `keystate::qmk`, and bindings without one read as `KC_NO`. Setting
+//! `KC_TRNS` copies the default layer's binding, which is what QMK falls through to. Vial's
+//! commands are answered as unhandled, since Vial needs the keyboard definition in the firmware.
+//!
+//! There are no VIA macros, as no key could play them: the count and buffer size read as zero, so
+//! the app does not offer them, and the buffer reads as empty.
+
+use crate::keystate::qmk;
+use crate::keystate::KeyShorthand;
+use crate::keystate::Keyboard;
+use crate::keystate::Keymap;
+
+pub const REPORT_LEN: usize = 32;
+pub const PROTOCOL_VERSION: u16 = 0x000C;
+
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const GET_KEYCODE: u8 = 0x04;
+const SET_KEYCODE: u8 = 0x05;
+const KEYMAP_RESET: u8 = 0x06;
+const LIGHTING_SET_VALUE: u8 = 0x07;
+const LIGHTING_GET_VALUE: u8 = 0x08;
+const LIGHTING_SAVE: u8 = 0x09;
+const EEPROM_RESET: u8 = 0x0A;
+const BOOTLOADER_JUMP: u8 = 0x0B;
+const MACRO_GET_COUNT: u8 = 0x0C;
+const MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const MACRO_GET_BUFFER: u8 = 0x0E;
+const MACRO_SET_BUFFER: u8 = 0x0F;
+const MACRO_RESET: u8 = 0x10;
+const GET_LAYER_COUNT: u8 = 0x11;
+const GET_KEYMAP_BUFFER: u8 = 0x12;
+const SET_KEYMAP_BUFFER: u8 = 0x13;
+const UNHANDLED: u8 = 0xFF;
+
+const UPTIME: u8 = 0x01;
+const LAYOUT_OPTIONS: u8 = 0x02;
+
+/// Bytes after the command, offset and size of a buffer request
+const CHUNK_LEN: usize = REPORT_LEN - 4;
+
+/// What the firmware has to do after a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViaRequest {
+    /// Put back the keymap the firmware was built with
+    ResetKeymap,
+    /// Reboot into the bootloader
+    Bootloader,
+}
+
+pub struct Via {
+    /// Matrix columns, a key's index is `row * cols + col`
+    cols: u8,
+    /// Set by the app to pick between alternative physical layouts
+    pub layout_options: u32,
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+impl Via {
+    pub fn new(cols: u8) -> Self {
+        Via {
+            cols,
+            layout_options: 0,
+        }
+    }
+
+    /// Answers `report` in place, `uptime` is in milliseconds
+    pub fn handle<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize>(
+        &mut self,
+        report: &mut [u8; REPORT_LEN],
+        keymap: &mut Keymap<SIZE, LAYERS, ROLLOVER>,
+        uptime: u32,
+    ) -> Option<ViaRequest> {
+        match report[0] {
+            GET_PROTOCOL_VERSION => report[1..3].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes()),
+            GET_KEYBOARD_VALUE => match report[1] {
+                UPTIME => report[2..6].copy_from_slice(&uptime.to_be_bytes()),
+                LAYOUT_OPTIONS => report[2..6].copy_from_slice(&self.layout_options.to_be_bytes()),
+                _ => report[0] = UNHANDLED,
+            },
+            SET_KEYBOARD_VALUE => match report[1] {
+                LAYOUT_OPTIONS => {
+                    self.layout_options = u32::from_be_bytes(report[2..6].try_into().unwrap())
+                }
+                _ => report[0] = UNHANDLED,
+            },
+            GET_KEYCODE => {
+                let code = self
+                    .index(report[2], report[3])
+                    .map_or(qmk::KC_NO, |index| get_keycode(keymap, report[1], index));
+                report[4..6].copy_from_slice(&code.to_be_bytes());
+            }
+            SET_KEYCODE => {
+                if let Some(index) = self.index(report[2], report[3]) {
+                    set_keycode(keymap, report[1], index, be_u16(&report[4..6]));
+                }
+            }
+            KEYMAP_RESET => return Some(ViaRequest::ResetKeymap),
+            // No lighting, so every value reads as zero and setting one does nothing
+            LIGHTING_GET_VALUE => report[3..].fill(0),
+            LIGHTING_SET_VALUE | LIGHTING_SAVE => (),
+            EEPROM_RESET => return Some(ViaRequest::ResetKeymap),
+            BOOTLOADER_JUMP => return Some(ViaRequest::Bootloader),
+            MACRO_GET_COUNT => report[1] = 0,
+            MACRO_GET_BUFFER_SIZE => report[1..3].fill(0),
+            MACRO_GET_BUFFER => chunk(report).1.fill(0),
+            MACRO_SET_BUFFER | MACRO_RESET => (),
+            GET_LAYER_COUNT => report[1] = LAYERS.min(u8::MAX as usize) as u8,
+            GET_KEYMAP_BUFFER => {
+                let (offset, data) = chunk(report);
+                for (pair, bytes) in data.chunks_exact_mut(2).enumerate() {
+                    let code = self
+                        .buffer_key(SIZE, offset + pair * 2)
+                        .map_or(qmk::KC_NO, |(layer, index)| {
+                            get_keycode(keymap, layer, index)
+                        });
+                    bytes.copy_from_slice(&code.to_be_bytes());
+                }
+            }
+            SET_KEYMAP_BUFFER => {
+                let (offset, data) = chunk(report);
+                for (pair, bytes) in data.chunks_exact(2).enumerate() {
+                    if let Some((layer, index)) = self.buffer_key(SIZE, offset + pair * 2) {
+                        set_keycode(keymap, layer, index, be_u16(bytes));
+                    }
+                }
+            }
+            _ => report[0] = UNHANDLED,
+        }
+        None
+    }
+
+    fn index(&self, row: u8, col: u8) -> Option<usize> {
+        (col < self.cols).then_some(row as usize * self.cols as usize + col as usize)
+    }
+
+    /// The layer and key at a byte offset of the keymap buffer, which holds every layer's keys by
+    /// row then column as big-endian keycodes
+    fn buffer_key(&self, size: usize, offset: usize) -> Option<(u8, usize)> {
+        let layer_len = size / self.cols.max(1) as usize * self.cols as usize;
+        if !offset.is_multiple_of(2) || layer_len == 0 {
+            return None;
+        }
+        let key = offset / 2;
+        Some((u8::try_from(key / layer_len).ok()?, key % layer_len))
+    }
+}
+
+/// The offset of a buffer request, and its data clamped to the report
+fn chunk(report: &mut [u8; REPORT_LEN]) -> (usize, &mut [u8]) {
+    let offset = be_u16(&report[1..3]) as usize;
+    let len = (report[3] as usize).min(CHUNK_LEN);
+    (offset, &mut report[4..4 + len])
+}
+
+fn get_keycode<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize>(
+    keymap: &Keymap<SIZE, LAYERS, ROLLOVER>,
+    layer: u8,
+    index: usize,
+) -> u16 {
+    keymap
+        .get_binding(layer, index)
+        .and_then(qmk::to_keycode)
+        .unwrap_or(qmk::KC_NO)
+}
+
+/// Keycodes without a binding, or for a layer that does not exist, are ignored
+fn set_keycode<const SIZE: usize, const LAYERS: usize, const ROLLOVER: usize>(
+    keymap: &mut Keymap<SIZE, LAYERS, ROLLOVER>,
+    layer: u8,
+    index: usize,
+    code: u16,
+) {
+    let binding = match code {
+        qmk::KC_TRANSPARENT if layer == keymap.default_layer() => {
+            Some(KeyShorthand::Kb(Keyboard::NoEventIndicated))
+        }
+        qmk::KC_TRANSPARENT => keymap.get_binding(keymap.default_layer(), index),
+        code => qmk::from_keycode(code),
+    };
+    match binding {
+        Some(KeyShorthand::La(target) | KeyShorthand::LT(target, _))
+            if target as usize >= LAYERS => {}
+        Some(binding) => {
+            keymap.set_binding(layer, index, binding);
+        }
+        None => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::keystate::prelude::*;
+
+    /// A 2x2 matrix with two layers
+    fn keymap() -> Keymap<4, 2, 6> {
+        Keymap::new(
+            [
+                [Kb(A), La(1), Sft(K1), Str("hi")],
+                [Kb(B), Kb(___), MT(LCTL, Escape), Cc(VOLU)],
+            ],
+            200,
+            100,
+            200,
+        )
+    }
+
+    fn request(bytes: &[u8]) -> [u8; REPORT_LEN] {
+        let mut report = [0; REPORT_LEN];
+        report[..bytes.len()].copy_from_slice(bytes);
+        report
+    }
+
+    fn handle(via: &mut Via, keymap: &mut Keymap<4, 2, 6>, bytes: &[u8]) -> [u8; REPORT_LEN] {
+        let mut report = request(bytes);
+        assert_eq!(via.handle(&mut report, keymap, 0x0102_0304), None);
+        report
+    }
+
+    #[test]
+    fn keyboard_values() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let report = handle(&mut via, &mut keymap, &[0x01]);
+        assert_eq!(report, request(&[0x01, 0x00, 0x0C]));
+        let report = handle(&mut via, &mut keymap, &[0x02, 0x01]);
+        assert_eq!(report, request(&[0x02, 0x01, 0x01, 0x02, 0x03, 0x04]));
+        handle(&mut via, &mut keymap, &[0x03, 0x02, 0x00, 0x00, 0x00, 0x05]);
+        assert_eq!(via.layout_options, 5);
+        let report = handle(&mut via, &mut keymap, &[0x02, 0x02]);
+        assert_eq!(report, request(&[0x02, 0x02, 0x00, 0x00, 0x00, 0x05]));
+        let report = handle(&mut via, &mut keymap, &[0x11]);
+        assert_eq!(report, request(&[0x11, 0x02]));
+    }
+
+    #[test]
+    fn keycodes() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let report = handle(&mut via, &mut keymap, &[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(report, request(&[0x04, 0x00, 0x01, 0x00, 0x02, 0x1E]));
+        // Strings have no keycode
+        let report = handle(&mut via, &mut keymap, &[0x04, 0x00, 0x01, 0x01]);
+        assert_eq!(report, request(&[0x04, 0x00, 0x01, 0x01, 0x00, 0x00]));
+        // Out of the matrix
+        let report = handle(&mut via, &mut keymap, &[0x04, 0x00, 0x00, 0x02, 0xAA, 0xAA]);
+        assert_eq!(report, request(&[0x04, 0x00, 0x00, 0x02, 0x00, 0x00]));
+
+        handle(&mut via, &mut keymap, &[0x05, 0x01, 0x00, 0x01, 0x00, 0x1D]);
+        assert_eq!(keymap.get_binding(1, 1), Some(Kb(Z)));
+        assert!(keymap.take_changed());
+        // Layers that do not exist, and an unsupported keycode, change nothing
+        handle(&mut via, &mut keymap, &[0x05, 0x00, 0x00, 0x00, 0x52, 0x22]);
+        handle(&mut via, &mut keymap, &[0x05, 0x00, 0x00, 0x00, 0x42, 0x04]);
+        handle(&mut via, &mut keymap, &[0x05, 0x00, 0x00, 0x00, 0x7C, 0x00]);
+        assert_eq!(keymap.get_binding(0, 0), Some(Kb(A)));
+        assert!(!keymap.take_changed());
+    }
+
+    #[test]
+    fn keymap_buffer() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let report = handle(&mut via, &mut keymap, &[0x12, 0x00, 0x00, 0x10]);
+        #[rustfmt::skip]
+        let expected = request(&[
+            0x12, 0x00, 0x00, 0x10,
+            0x00, 0x04, 0x52, 0x21, 0x02, 0x1E, 0x00, 0x00,
+            0x00, 0x05, 0x00, 0x00, 0x21, 0x29, 0x00, 0xA9,
+        ]);
+        assert_eq!(report, expected);
+
+        let report = handle(&mut via, &mut keymap, &[0x12, 0x00, 0x0E, 0x1C]);
+        assert_eq!(report, request(&[0x12, 0x00, 0x0E, 0x1C, 0x00, 0xA9]));
+
+        handle(
+            &mut via,
+            &mut keymap,
+            &[0x13, 0x00, 0x0A, 0x04, 0x00, 0x06, 0x7C, 0x73],
+        );
+        assert_eq!(keymap.get_binding(1, 1), Some(Kb(C)));
+        assert_eq!(keymap.get_binding(1, 2), Some(CapsWord));
+
+        // Transparent keys take the default layer's binding
+        handle(&mut via, &mut keymap, &[0x05, 0x01, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(keymap.get_binding(1, 0), Some(Kb(A)));
+        handle(&mut via, &mut keymap, &[0x05, 0x00, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(keymap.get_binding(0, 1), Some(Kb(___)));
+    }
+
+    #[test]
+    fn no_macros() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let report = handle(&mut via, &mut keymap, &[0x0C, 0x04]);
+        assert_eq!(report, request(&[0x0C, 0x00]));
+        let report = handle(&mut via, &mut keymap, &[0x0D, 0x00, 0x10]);
+        assert_eq!(report, request(&[0x0D, 0x00, 0x00]));
+
+        handle(
+            &mut via,
+            &mut keymap,
+            &[0x0F, 0x00, 0x00, 0x04, b'a', b'b', b'c', b'd'],
+        );
+        let report = handle(&mut via, &mut keymap, &[0x0E, 0x00, 0x00, 0x04, 0xAA]);
+        assert_eq!(report, request(&[0x0E, 0x00, 0x00, 0x04]));
+    }
+
+    #[test]
+    fn requests() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let mut report = request(&[0x06]);
+        let reset = via.handle(&mut report, &mut keymap, 0);
+        assert_eq!(reset, Some(ViaRequest::ResetKeymap));
+        let mut report = request(&[0x0B]);
+        let bootloader = via.handle(&mut report, &mut keymap, 0);
+        assert_eq!(bootloader, Some(ViaRequest::Bootloader));
+    }
+
+    #[test]
+    fn lighting_and_unhandled() {
+        let (mut via, mut keymap) = (Via::new(2), keymap());
+        let report = handle(&mut via, &mut keymap, &[0x08, 0x01, 0x02, 0x33]);
+        assert_eq!(report, request(&[0x08, 0x01, 0x02]));
+        let report = handle(&mut via, &mut keymap, &[0x07, 0x01, 0x02, 0x33]);
+        assert_eq!(report, request(&[0x07, 0x01, 0x02, 0x33]));
+        // Switch matrix state, and Vial's keyboard ID
+        let report = handle(&mut via, &mut keymap, &[0x02, 0x03]);
+        assert_eq!(report, request(&[0xFF, 0x03]));
+        let report = handle(&mut via, &mut keymap, &[0xFE, 0x00]);
+        assert_eq!(report, request(&[0xFF]));
+    }
+}